@@ -0,0 +1,37 @@
+//! Time of a simulation step on a developed flow, e.g. to measure the gain of
+//! the SIMD kernels:
+//!
+//! ```text
+//! cargo bench --bench solver -- --save-baseline scalar
+//! cargo bench --bench solver --features simd -- --baseline scalar
+//! ```
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+use fluid_simulation_rs::simu::{apply_scenario, Fluid};
+
+const DT: f32 = 1. / 60.;
+const NUM_ITERS: u32 = 40;
+const OVER_RELAXATION: f32 = 1.9;
+
+fn simulate(c: &mut Criterion) {
+    let mut group = c.benchmark_group("simulate");
+
+    for (num_x, num_y) in [(200, 100), (400, 200)] {
+        let in_vel = 0.8;
+        let mut fluid = Fluid::create(0., in_vel, num_x, num_y, 1. / num_y as f32, 1000.);
+        apply_scenario(&mut fluid, "cylindrical", in_vel);
+        for _ in 0..60 {
+            fluid.simulate(DT, NUM_ITERS, OVER_RELAXATION);
+        }
+
+        group.bench_function(
+            BenchmarkId::from_parameter(format!("{}x{}", num_x, num_y)),
+            |b| b.iter(|| fluid.simulate(DT, NUM_ITERS, OVER_RELAXATION)),
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, simulate);
+criterion_main!(benches);
@@ -11,8 +11,8 @@ import('./pkg')
         // Get the streamlines checkbox
         const streamlines_checkbox = document.getElementById("streamlines");
 
-        // Get the streamlines_num_seg slider
-        const streamlines_num_seg_slider = document.getElementById("streamlines_num_seg");
+        // Get the streamlines_max_length slider
+        const streamlines_max_length_slider = document.getElementById("streamlines_max_length");
 
         // Get the streamlines_spacing slider
         const streamlines_spacing_slider = document.getElementById("streamlines_spacing");
@@ -68,17 +68,303 @@ import('./pkg')
         // Create the fluid simulation
         const fluid = wasm.Fluid.create(gravity, in_vel, numX, numY, h, density)
 
-        // Setup the obstacles
-        fluid.clear_obstacles();
-
-        // Run the simulation
-        wasm.run_with_selector(numIters, overrelaxation, fluid,
-            simu_canvas, scenario_selector,
-            pressure_checkbox, streamlines_checkbox,
-            streamlines_num_seg_slider, streamlines_spacing_slider,
-            in_vel_slider,
-            colormap_selector,
-            sim_to_canvas_ratio)
+        // Create the simulation
+        const simulation = new wasm.Simulation(fluid, simu_canvas, numIters, overrelaxation, sim_to_canvas_ratio);
+
+        // Bind the controls
+        const update_scenario = () => simulation.set_scenario(scenario_selector.value);
+        const update_in_vel = () => simulation.set_in_vel(parseFloat(in_vel_slider.value));
+        const update_colormap = () => simulation.set_colormap(colormap_selector.value);
+        const update_draw_options = () => {
+            try {
+                simulation.set_draw_options(
+                    pressure_checkbox.checked,
+                    streamlines_checkbox.checked,
+                    parseFloat(streamlines_max_length_slider.value),
+                    parseFloat(streamlines_spacing_slider.value));
+            } catch (e) {
+                console.warn(e);
+            }
+        };
+
+        update_in_vel();
+        update_scenario();
+        update_colormap();
+        update_draw_options();
+
+        scenario_selector.addEventListener("change", update_scenario);
+        in_vel_slider.addEventListener("input", update_in_vel);
+        colormap_selector.addEventListener("change", update_colormap);
+
+        const field_selector = document.getElementById("field");
+        field_selector.addEventListener("change", () => simulation.set_field(field_selector.value));
+
+        const quiver_checkbox = document.getElementById("quiver");
+        const quiver_spacing_slider = document.getElementById("quiver_spacing");
+        const quiver_scale_slider = document.getElementById("quiver_scale");
+        const update_quiver = () => simulation.set_quiver(
+            quiver_checkbox.checked,
+            parseInt(quiver_spacing_slider.value),
+            parseFloat(quiver_scale_slider.value));
+        for (const element of [quiver_checkbox, quiver_spacing_slider, quiver_scale_slider]) {
+            element.addEventListener("input", update_quiver);
+        }
+
+        const lic_checkbox = document.getElementById("lic");
+        const lic_length_slider = document.getElementById("lic_length");
+        const lic_animate_checkbox = document.getElementById("lic_animate");
+        const update_lic = () => simulation.set_lic(
+            lic_checkbox.checked,
+            parseFloat(lic_length_slider.value),
+            lic_animate_checkbox.checked);
+        for (const element of [lic_checkbox, lic_length_slider, lic_animate_checkbox]) {
+            element.addEventListener("input", update_lic);
+        }
+
+        const interpolation_selector = document.getElementById("interpolation");
+        interpolation_selector.addEventListener("change", () => simulation.set_interpolation(interpolation_selector.value));
+
+        const contours_input = document.getElementById("contours");
+        contours_input.addEventListener("input", () => simulation.set_contours(parseInt(contours_input.value) || 0));
+
+        const colorbar_checkbox = document.getElementById("colorbar");
+        const axes_checkbox = document.getElementById("axes");
+        const hud_checkbox = document.getElementById("hud");
+        const update_overlays = () => simulation.set_overlays(colorbar_checkbox.checked, axes_checkbox.checked, hud_checkbox.checked);
+        for (const element of [colorbar_checkbox, axes_checkbox, hud_checkbox]) {
+            element.addEventListener("input", update_overlays);
+        }
+
+        const color_range_selector = document.getElementById("color_range");
+        const color_range_a = document.getElementById("color_range_a");
+        const color_range_b = document.getElementById("color_range_b");
+        const update_color_range = () => {
+            try {
+                simulation.set_color_range(color_range_selector.value, parseFloat(color_range_a.value), parseFloat(color_range_b.value));
+            } catch (e) {
+                console.warn(e);
+            }
+        };
+        color_range_selector.addEventListener("change", () => {
+            // start from sensible parameters for the new mode
+            switch (color_range_selector.value) {
+                case "fixed":
+                    const range = simulation.color_range();
+                    if (range) {
+                        [color_range_a.value, color_range_b.value] = range;
+                    }
+                    break;
+                case "percentile":
+                    color_range_a.value = 2;
+                    break;
+                case "smoothed":
+                    color_range_a.value = 0.1;
+                    break;
+            }
+            update_color_range();
+        });
+        color_range_a.addEventListener("change", update_color_range);
+        color_range_b.addEventListener("change", update_color_range);
+
+        const viscosity_input = document.getElementById("viscosity");
+        viscosity_input.addEventListener("input", () => {
+            const viscosity = parseFloat(viscosity_input.value);
+            if (viscosity > 0) {
+                simulation.set_viscosity(viscosity);
+            }
+        });
+
+        // The renderer falls back to 2d when WebGL2 is not available
+        const renderer_selector = document.getElementById("renderer");
+        renderer_selector.addEventListener("change", () => {
+            renderer_selector.value = simulation.set_renderer(renderer_selector.value);
+        });
+        for (const element of [pressure_checkbox, streamlines_checkbox, streamlines_max_length_slider, streamlines_spacing_slider]) {
+            element.addEventListener("input", update_draw_options);
+        }
+
+        // The canvas tool places particles, streamline seeds or flow lines; the
+        // canvas is replaced when the renderer changes, so listen on the document
+        const tool_selector = document.getElementById("tool");
+        const to_physical = (event) => {
+            const rect = event.target.getBoundingClientRect();
+            const px = (event.clientX - rect.left) * event.target.width / rect.width;
+            const py = (event.clientY - rect.top) * event.target.height / rect.height;
+            // the canvas covers the interior cells, y goes up
+            return [
+                (px / sim_to_canvas_ratio + 1) * h,
+                ((event.target.height - py) / sim_to_canvas_ratio + 1) * h,
+            ];
+        };
+        // a click at a point, or a drag along a segment
+        let drag_start = null;
+        document.addEventListener("pointerdown", (event) => {
+            if (event.target.id === "canvas") {
+                drag_start = to_physical(event);
+            }
+        });
+        document.addEventListener("pointerup", (event) => {
+            if (drag_start !== null && event.target.id === "canvas") {
+                const [x0, y0] = drag_start;
+                const [x1, y1] = to_physical(event);
+                switch (tool_selector.value) {
+                    case "particles": simulation.spawn_particles(x1, y1, 2 * h, 20); break;
+                    case "streamlines": simulation.add_streamline_seeds(x0, y0, x1, y1, 12); break;
+                    case "pathline": simulation.add_pathline(x1, y1); break;
+                    case "streakline": simulation.add_streakline(x1, y1); break;
+                    case "timeline": simulation.add_timeline(x0, y0, x1, y1, 50); break;
+                }
+            }
+            drag_start = null;
+        });
+        document.getElementById("clear_flowlines").addEventListener("click", () => simulation.clear_flowlines());
+        document.getElementById("clear_seeds").addEventListener("click", () => simulation.clear_streamline_seeds());
+
+        // Tracer and inertial particles
+        const particle_integrator_selector = document.getElementById("particle_integrator");
+        const particle_style_selector = document.getElementById("particle_style");
+        const particle_trail_length_slider = document.getElementById("particle_trail_length");
+        const update_particles = () => simulation.set_particles(
+            particle_integrator_selector.value,
+            particle_style_selector.value,
+            parseInt(particle_trail_length_slider.value));
+        for (const element of [particle_integrator_selector, particle_style_selector, particle_trail_length_slider]) {
+            element.addEventListener("input", update_particles);
+        }
+        update_particles();
+        const inertial_checkbox = document.getElementById("inertial");
+        const particle_diameter_input = document.getElementById("particle_diameter");
+        const particle_density_input = document.getElementById("particle_density");
+        const particle_wall_selector = document.getElementById("particle_wall");
+        const update_inertia = () => {
+            if (inertial_checkbox.checked) {
+                simulation.set_inertial_particles(
+                    parseFloat(particle_diameter_input.value) * 1e-3,
+                    parseFloat(particle_density_input.value),
+                    particle_wall_selector.value,
+                    0.5);
+            } else {
+                simulation.set_tracer_particles();
+            }
+        };
+        for (const element of [inertial_checkbox, particle_diameter_input, particle_density_input, particle_wall_selector]) {
+            element.addEventListener("input", update_inertia);
+        }
+        document.getElementById("inlet_emitter").addEventListener("click", () => simulation.add_inlet_emitter(50));
+        document.getElementById("clear_particles").addEventListener("click", () => simulation.clear_particles());
+
+        // Playback controls
+        const pause_button = document.getElementById("pause");
+        const step_button = document.getElementById("step");
+        const reset_button = document.getElementById("reset");
+
+        pause_button.addEventListener("click", () => simulation.is_paused() ? simulation.resume() : simulation.pause());
+        step_button.addEventListener("click", (event) => simulation.step_frames(event.shiftKey ? 10 : 1));
+        reset_button.addEventListener("click", () => simulation.reset());
+
+        const worker_checkbox = document.getElementById("worker");
+        worker_checkbox.addEventListener("change", () => {
+            simulation.set_worker(worker_checkbox.checked ? new Worker(new URL("./worker.js", import.meta.url)) : undefined);
+        });
+
+        simulation.on("pause", () => pause_button.textContent = "Resume");
+        simulation.on("resume", () => pause_button.textContent = "Pause");
+
+        // Replay controls
+        const record_checkbox = document.getElementById("record");
+        const replay_slider = document.getElementById("replay");
+
+        record_checkbox.addEventListener("change", () => {
+            if (record_checkbox.checked) {
+                // the last ~20s at 60 fps, every other step, in half precision
+                simulation.start_recording(600, 2, true);
+            } else {
+                simulation.stop_recording();
+            }
+        });
+        replay_slider.addEventListener("input", () => simulation.seek(parseInt(replay_slider.value)));
+        document.getElementById("live").addEventListener("click", () => simulation.leave_replay(false));
+        document.getElementById("branch").addEventListener("click", () => simulation.leave_replay(true));
+        const download = (bytes, type, name) => {
+            const blob = new Blob([bytes], { type });
+            const link = document.createElement("a");
+            link.href = URL.createObjectURL(blob);
+            link.download = name;
+            link.click();
+            URL.revokeObjectURL(link.href);
+        };
+
+        document.getElementById("export_replay").addEventListener("click", () => {
+            download(simulation.export_replay(), "application/octet-stream", "replay.flrp");
+        });
+
+        // State save and export
+        document.getElementById("save_state").addEventListener("click", () => {
+            download(simulation.save(), "application/octet-stream", "fluid.flsm");
+        });
+        const restore_input = document.getElementById("restore_state");
+        restore_input.addEventListener("change", async () => {
+            const file = restore_input.files[0];
+            if (!file) {
+                return;
+            }
+            try {
+                simulation.restore(new Uint8Array(await file.arrayBuffer()));
+            } catch (e) {
+                console.warn(e);
+            }
+            restore_input.value = "";
+        });
+        document.getElementById("export_npz").addEventListener("click", () => {
+            download(simulation.export_npz(), "application/zip", "fluid.npz");
+            download(simulation.export_metadata(), "application/json", "fluid.json");
+        });
+        document.getElementById("export_vti").addEventListener("click", () => {
+            download(simulation.export_vti("raw"), "application/xml", "fluid.vti");
+        });
+
+        // Image export
+        const capture_button = document.getElementById("capture");
+        const capture_format = document.getElementById("capture_format");
+        // format of the capture in progress, if any
+        let capturing = null;
+
+        document.getElementById("save_image").addEventListener("click", () => {
+            download(simulation.snapshot_png(), "image/png", "fluid.png");
+        });
+        document.getElementById("save_svg").addEventListener("click", () => {
+            const raster = document.getElementById("svg_raster").checked;
+            download(simulation.export_svg(raster), "image/svg+xml", "fluid.svg");
+        });
+        capture_button.addEventListener("click", () => {
+            if (capturing) {
+                const gif = capturing === "gif";
+                capturing = null;
+                capture_button.textContent = "Capture";
+                download(simulation.stop_capture(), gif ? "image/gif" : "image/apng", gif ? "fluid.gif" : "fluid.png");
+            } else {
+                simulation.start_capture(
+                    capture_format.value,
+                    parseInt(document.getElementById("capture_stride").value),
+                    parseInt(document.getElementById("capture_fps").value),
+                );
+                capturing = capture_format.value;
+                capture_button.textContent = "Stop capture";
+            }
+        });
+
+        simulation.on("frame", () => {
+            // unchecked if the worker failed
+            worker_checkbox.checked = simulation.has_worker();
+            if (capturing) {
+                capture_button.textContent = `Stop capture (${simulation.captured_frames()})`;
+            }
+            replay_slider.max = Math.max(simulation.replay_len() - 1, 0);
+            const index = simulation.replay_index();
+            replay_slider.value = index === undefined ? replay_slider.max : index;
+        });
+
+        simulation.bind_keyboard(document);
 
     })
     .catch(console.error);
\ No newline at end of file
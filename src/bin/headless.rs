@@ -0,0 +1,227 @@
+//! Headless runner: simulates a scenario and streams the rendered frames as raw
+//! video, to stdout or to a named pipe, e.g.
+//!
+//! ```text
+//! cargo run --release --bin headless -- --frames 600 | ffmpeg -f yuv4mpegpipe -i - -pix_fmt yuv420p out.mp4
+//! ```
+//!
+//! The `ffmpeg` input options of the stream are printed on stderr.
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::process::ExitCode;
+use std::str::FromStr;
+
+use fluid_simulation_rs::export::video::{VideoFormat, VideoWriter};
+use fluid_simulation_rs::flowlines::Flowlines;
+use fluid_simulation_rs::lic::LicCache;
+use fluid_simulation_rs::particles::Particles;
+use fluid_simulation_rs::simu::{apply_scenario, DrawOptions, Fluid, Quantity, SCENARIOS};
+
+const USAGE: &str = "usage: headless [options]
+
+  --scenario NAME         cylindrical or rectangular (default: cylindrical)
+  --size WxH              grid size in cells, the domain is 1 m high (default: 200x100)
+  --in-vel V              inlet velocity in m/s (default: 0.8)
+  --iters N               pressure solver iterations (default: 40)
+  --over-relaxation W     over-relaxation of the solver (default: 1.9)
+  --dt T                  time step in s (default: 1/60)
+  --steps-per-frame N     simulation steps between frames (default: 2)
+  --frames N              number of frames (default: 300)
+  --fps N                 frame rate of the video (default: 30)
+  --ratio N               pixels per cell (default: 4)
+  --field NAME            pressure, speed, u, v, vorticity, divergence or smoke (default: pressure)
+  --colormap NAME         colormap of the field (default: jet)
+  --no-streamlines        do not draw the streamlines
+  --no-hud                do not draw the colorbar and the HUD
+  --format FORMAT         y4m or rgb24 (default: y4m)
+  --output PATH           file or named pipe, - for stdout (default: -)";
+
+struct Args {
+    scenario: String,
+    size: (usize, usize),
+    in_vel: f32,
+    iters: u32,
+    over_relaxation: f32,
+    dt: f32,
+    steps_per_frame: usize,
+    frames: usize,
+    fps: u32,
+    ratio: u32,
+    field: Quantity,
+    colormap: String,
+    streamlines: bool,
+    hud: bool,
+    format: VideoFormat,
+    output: String,
+}
+
+fn parse<T: FromStr>(name: &str, value: Option<String>) -> Result<T, String> {
+    parse_valid(name, value, |_| true)
+}
+
+/// Parse a value, rejected unless `valid` holds for it.
+fn parse_valid<T: FromStr>(
+    name: &str,
+    value: Option<String>,
+    valid: impl Fn(&T) -> bool,
+) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("missing value for {}", name))?;
+    value
+        .parse()
+        .ok()
+        .filter(valid)
+        .ok_or_else(|| format!("invalid value for {}: {}", name, value))
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut parsed = Args {
+        scenario: SCENARIOS[0].to_string(),
+        size: (200, 100),
+        in_vel: 0.8,
+        iters: 40,
+        over_relaxation: 1.9,
+        dt: 1. / 60.,
+        steps_per_frame: 2,
+        frames: 300,
+        fps: 30,
+        ratio: 4,
+        field: Quantity::Pressure,
+        colormap: "jet".to_string(),
+        streamlines: true,
+        hud: true,
+        format: VideoFormat::Y4m,
+        output: "-".to_string(),
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--scenario" => parsed.scenario = parse(&arg, args.next())?,
+            "--size" => {
+                let size: String = parse(&arg, args.next())?;
+                parsed.size = size
+                    .split_once('x')
+                    .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+                    .filter(|&(w, h)| w > 0 && h > 0)
+                    .ok_or_else(|| format!("invalid value for --size: {}", size))?;
+            }
+            "--in-vel" => parsed.in_vel = parse(&arg, args.next())?,
+            "--iters" => parsed.iters = parse(&arg, args.next())?,
+            "--over-relaxation" => parsed.over_relaxation = parse(&arg, args.next())?,
+            "--dt" => {
+                parsed.dt = parse_valid(&arg, args.next(), |dt: &f32| dt.is_finite() && *dt > 0.)?
+            }
+            "--steps-per-frame" => parsed.steps_per_frame = parse(&arg, args.next())?,
+            "--frames" => parsed.frames = parse(&arg, args.next())?,
+            "--fps" => parsed.fps = parse_valid(&arg, args.next(), |&fps| fps > 0)?,
+            "--ratio" => parsed.ratio = parse_valid(&arg, args.next(), |&ratio| ratio > 0)?,
+            "--field" => parsed.field = parse(&arg, args.next())?,
+            "--colormap" => parsed.colormap = parse(&arg, args.next())?,
+            "--no-streamlines" => parsed.streamlines = false,
+            "--no-hud" => parsed.hud = false,
+            "--format" => parsed.format = parse(&arg, args.next())?,
+            "--output" => parsed.output = parse(&arg, args.next())?,
+            _ => return Err(format!("unknown option: {}", arg)),
+        }
+    }
+
+    if !SCENARIOS.contains(&parsed.scenario.as_str()) {
+        return Err(format!("unknown scenario: {}", parsed.scenario));
+    }
+
+    Ok(parsed)
+}
+
+fn run(args: Args) -> Result<(), String> {
+    let (num_x, num_y) = args.size;
+    let h = 1. / num_y as f32;
+    let mut fluid = Fluid::create(0., args.in_vel, num_x, num_y, h, 1000.);
+    apply_scenario(&mut fluid, &args.scenario, args.in_vel);
+
+    let mut options = DrawOptions::new(&fluid);
+    options.field = args.field;
+    options.colormap = args.colormap;
+    options.streamlines = args.streamlines;
+    options.colorbar = args.hud;
+    options.hud = args.hud;
+
+    // no particles nor flowlines in the headless runs
+    let particles = Particles::default();
+    let flowlines = Flowlines::default();
+    let mut lic = LicCache::default();
+
+    let output: Box<dyn Write> = if args.output == "-" {
+        Box::new(io::stdout().lock())
+    } else {
+        // also opens named pipes, once a reader is connected
+        Box::new(File::create(&args.output).map_err(|e| format!("{}: {}", args.output, e))?)
+    };
+    let mut writer = VideoWriter::new(BufWriter::new(output), args.format, args.fps);
+
+    let mut range = None;
+    for _ in 0..args.frames {
+        for _ in 0..args.steps_per_frame {
+            fluid.simulate(args.dt, args.iters, args.over_relaxation);
+        }
+
+        let field = fluid.scalar_field(options.field);
+        let frame_range = fluid.color_range(&field, options.color_range, range);
+        range = Some(frame_range);
+
+        let image = fluid.render_frame(
+            &options,
+            &field,
+            frame_range,
+            None,
+            args.ratio,
+            &particles,
+            &flowlines,
+            &mut lic,
+        )?;
+
+        if writer.frames() == 0 {
+            eprintln!(
+                "{} frames of {}x{} at {} fps, read with: ffmpeg {} ...",
+                args.frames,
+                image.size().0,
+                image.size().1,
+                args.fps,
+                args.format.ffmpeg_input(image.size(), args.fps)
+            );
+        }
+
+        match writer.write_frame(&image) {
+            // the reader is gone, e.g. ffmpeg was given a duration
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => return Ok(()),
+            result => result.map_err(|e| e.to_string())?,
+        }
+    }
+
+    match writer.into_inner() {
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => result.map(|_| ()).map_err(|e| e.to_string()),
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--help" || arg == "-h") {
+        println!("{}", USAGE);
+        return ExitCode::SUCCESS;
+    }
+
+    let args = match parse_args(args.into_iter()) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("headless: {}\n\n{}", e, USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("headless: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,319 @@
+//! Checkpoints of the full simulation state
+//!
+//! A checkpoint is a little-endian binary blob:
+//!
+//! | field                         | type          |
+//! |-------------------------------|---------------|
+//! | magic `b"FLSM"`               | `[u8; 4]`     |
+//! | version                       | `u32`         |
+//! | num_x, num_y (incl. borders)  | `u32`         |
+//! | h, density, gravity, in_vel   | `f32`         |
+//! | num_iters                     | `u32`         |
+//! | over_relaxation               | `f32`         |
+//! | step                          | `u64`         |
+//! | time                          | `f64`         |
+//! | u, v, p, s, m                 | `num_x * num_y` x `f32` each |
+//!
+//! The scratch buffers (`new_u`, `new_v`, `new_m`) are not saved.
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use wasm_bindgen::prelude::*;
+
+use crate::simu::Fluid;
+
+/// Magic number at the start of every checkpoint.
+const MAGIC: &[u8; 4] = b"FLSM";
+
+/// Current version of the checkpoint format.
+pub const VERSION: u32 = 1;
+
+/// Error when restoring a checkpoint
+#[derive(Debug)]
+pub enum CheckpointError {
+    /// The data does not start with the checkpoint magic number
+    BadMagic,
+    /// The checkpoint was written by an unsupported version of the format
+    UnsupportedVersion(u32),
+    /// The grid size in the checkpoint is not valid
+    InvalidGrid { num_x: usize, num_y: usize },
+    /// A physical parameter in the checkpoint is not a positive number
+    InvalidParameter { name: &'static str, value: f32 },
+    /// The underlying reader failed (including truncated data)
+    Io(io::Error),
+}
+
+impl fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckpointError::BadMagic => write!(f, "not a fluid checkpoint"),
+            CheckpointError::UnsupportedVersion(v) => {
+                write!(
+                    f,
+                    "unsupported checkpoint version {} (expected {})",
+                    v, VERSION
+                )
+            }
+            CheckpointError::InvalidGrid { num_x, num_y } => {
+                write!(f, "invalid grid size {}x{}", num_x, num_y)
+            }
+            CheckpointError::InvalidParameter { name, value } => {
+                write!(f, "invalid {}: {}", name, value)
+            }
+            CheckpointError::Io(e) => write!(f, "failed to read checkpoint: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for CheckpointError {}
+
+impl From<io::Error> for CheckpointError {
+    fn from(e: io::Error) -> Self {
+        CheckpointError::Io(e)
+    }
+}
+
+fn write_f32s<W: Write>(w: &mut W, data: &[f32]) -> io::Result<()> {
+    let mut buf = Vec::with_capacity(4 * data.len());
+    for x in data {
+        buf.extend_from_slice(&x.to_le_bytes());
+    }
+    w.write_all(&buf)
+}
+
+fn read_array<R: Read, const N: usize>(r: &mut R) -> io::Result<[u8; N]> {
+    let mut buf = [0_u8; N];
+    r.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    Ok(u32::from_le_bytes(read_array(r)?))
+}
+
+fn read_f32<R: Read>(r: &mut R) -> io::Result<f32> {
+    Ok(f32::from_le_bytes(read_array(r)?))
+}
+
+fn read_f32s<R: Read>(r: &mut R, len: usize) -> io::Result<Vec<f32>> {
+    // grow the buffer as data comes in rather than trusting the header
+    let mut buf = Vec::new();
+    r.take(4 * len as u64).read_to_end(&mut buf)?;
+    if buf.len() != 4 * len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(buf
+        .chunks_exact(4)
+        .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+        .collect())
+}
+
+impl Fluid {
+    /// Write a checkpoint of the full simulation state.
+    pub fn write_checkpoint<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        w.write_all(&VERSION.to_le_bytes())?;
+
+        w.write_all(&(self.num_x as u32).to_le_bytes())?;
+        w.write_all(&(self.num_y as u32).to_le_bytes())?;
+        w.write_all(&self.h.to_le_bytes())?;
+        w.write_all(&self.density.to_le_bytes())?;
+        w.write_all(&self.gravity.to_le_bytes())?;
+        w.write_all(&self.in_vel.to_le_bytes())?;
+        w.write_all(&self.num_iters.to_le_bytes())?;
+        w.write_all(&self.over_relaxation.to_le_bytes())?;
+        w.write_all(&self.step.to_le_bytes())?;
+        w.write_all(&self.time.to_le_bytes())?;
+
+        for field in [&self.u, &self.v, &self.p, &self.s, &self.m] {
+            write_f32s(w, field)?;
+        }
+
+        Ok(())
+    }
+
+    /// Read a checkpoint written by [Fluid::write_checkpoint].
+    pub fn read_checkpoint<R: Read>(r: &mut R) -> Result<Fluid, CheckpointError> {
+        if &read_array::<_, 4>(r)? != MAGIC {
+            return Err(CheckpointError::BadMagic);
+        }
+
+        let version = read_u32(r)?;
+        if version != VERSION {
+            return Err(CheckpointError::UnsupportedVersion(version));
+        }
+
+        let num_x = read_u32(r)? as usize;
+        let num_y = read_u32(r)? as usize;
+        // the solver needs at least one interior cell surrounded by borders,
+        // and the size of each field in bytes must fit
+        let num_cells = match num_x.checked_mul(num_y) {
+            Some(num_cells) if num_x >= 3 && num_y >= 3 && num_cells.checked_mul(4).is_some() => {
+                num_cells
+            }
+            _ => return Err(CheckpointError::InvalidGrid { num_x, num_y }),
+        };
+
+        let h = read_f32(r)?;
+        let density = read_f32(r)?;
+        for (name, value) in [("cell size", h), ("density", density)] {
+            if !value.is_finite() || value <= 0. {
+                return Err(CheckpointError::InvalidParameter { name, value });
+            }
+        }
+        let gravity = read_f32(r)?;
+        let in_vel = read_f32(r)?;
+        let num_iters = read_u32(r)?;
+        let over_relaxation = read_f32(r)?;
+        let step = u64::from_le_bytes(read_array(r)?);
+        let time = f64::from_le_bytes(read_array(r)?);
+
+        let u = read_f32s(r, num_cells)?;
+        let v = read_f32s(r, num_cells)?;
+        let p = read_f32s(r, num_cells)?;
+        let s = read_f32s(r, num_cells)?;
+        let m = read_f32s(r, num_cells)?;
+
+        let mut fluid = Fluid::create(gravity, in_vel, num_x - 2, num_y - 2, h, density);
+        fluid.num_iters = num_iters;
+        fluid.over_relaxation = over_relaxation;
+        fluid.step = step;
+        fluid.time = time;
+        fluid.u = u;
+        fluid.v = v;
+        fluid.p = p;
+        fluid.s = s;
+        fluid.m = m;
+
+        Ok(fluid)
+    }
+
+    /// Return a checkpoint of the full simulation state.
+    pub fn to_checkpoint(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        self.write_checkpoint(&mut buf)
+            .expect("writing to a Vec cannot fail");
+        buf
+    }
+
+    /// Restore a simulation from a checkpoint returned by [Fluid::to_checkpoint].
+    pub fn from_checkpoint(mut data: &[u8]) -> Result<Fluid, CheckpointError> {
+        Fluid::read_checkpoint(&mut data)
+    }
+}
+
+#[wasm_bindgen]
+impl Fluid {
+    /// Save the full simulation state (as a `Uint8Array`).
+    pub fn save(&self) -> Vec<u8> {
+        self.to_checkpoint()
+    }
+
+    /// Restore a simulation saved with [Fluid::save].
+    pub fn restore(data: &[u8]) -> Result<Fluid, JsValue> {
+        Fluid::from_checkpoint(data).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simu::apply_scenario;
+
+    fn fluid() -> Fluid {
+        let mut fluid = Fluid::create(0., 1.5, 20, 10, 0.01, 1000.);
+        apply_scenario(&mut fluid, "cylindrical", 1.5);
+        for _ in 0..3 {
+            fluid.simulate(1. / 60., 20, 1.9);
+        }
+        fluid
+    }
+
+    /// Replace the bytes at `offset` in the checkpoint of a fluid.
+    fn patched(offset: usize, bytes: &[u8]) -> Vec<u8> {
+        let mut data = fluid().to_checkpoint();
+        data[offset..offset + bytes.len()].copy_from_slice(bytes);
+        data
+    }
+
+    #[test]
+    fn round_trip() {
+        let fluid = fluid();
+        let data = fluid.to_checkpoint();
+        assert_eq!(data.len(), 4 + 4 + 8 + 16 + 8 + 8 + 8 + 5 * 4 * 22 * 12);
+
+        let restored = Fluid::from_checkpoint(&data).unwrap();
+        assert_eq!((restored.num_x, restored.num_y), (22, 12));
+        assert_eq!(restored.h, fluid.h);
+        assert_eq!(restored.density, fluid.density);
+        assert_eq!(restored.gravity, fluid.gravity);
+        assert_eq!(restored.in_vel, fluid.in_vel);
+        assert_eq!(restored.num_iters, fluid.num_iters);
+        assert_eq!(restored.over_relaxation, fluid.over_relaxation);
+        assert_eq!(restored.step, 3);
+        assert_eq!(restored.time, fluid.time);
+        assert_eq!(restored.u, fluid.u);
+        assert_eq!(restored.v, fluid.v);
+        assert_eq!(restored.p, fluid.p);
+        assert_eq!(restored.s, fluid.s);
+        assert_eq!(restored.m, fluid.m);
+
+        assert_eq!(restored.to_checkpoint(), data);
+    }
+
+    #[test]
+    fn bad_magic() {
+        let data = patched(0, b"FLSX");
+        assert!(matches!(
+            Fluid::from_checkpoint(&data),
+            Err(CheckpointError::BadMagic)
+        ));
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let data = patched(4, &2_u32.to_le_bytes());
+        assert!(matches!(
+            Fluid::from_checkpoint(&data),
+            Err(CheckpointError::UnsupportedVersion(2))
+        ));
+    }
+
+    #[test]
+    fn truncated() {
+        let data = fluid().to_checkpoint();
+        // in the header, then in the fields
+        for len in [0, 3, 10, 40, data.len() - 1] {
+            match Fluid::from_checkpoint(&data[..len]) {
+                Err(CheckpointError::Io(e)) => {
+                    assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof)
+                }
+                other => panic!("truncated to {} bytes: {:?}", len, other.map(|_| ())),
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_grid() {
+        for (num_x, num_y) in [(2, 12), (22, 0), (u32::MAX, u32::MAX)] {
+            let mut size = num_x.to_le_bytes().to_vec();
+            size.extend_from_slice(&num_y.to_le_bytes());
+            let data = patched(8, &size);
+            assert!(matches!(
+                Fluid::from_checkpoint(&data),
+                Err(CheckpointError::InvalidGrid { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn invalid_parameters() {
+        for (offset, value) in [(16, 0.), (16, f32::NAN), (20, -1000.), (20, f32::INFINITY)] {
+            let data = patched(offset, &f32::to_le_bytes(value));
+            assert!(matches!(
+                Fluid::from_checkpoint(&data),
+                Err(CheckpointError::InvalidParameter { .. })
+            ));
+        }
+    }
+}
@@ -0,0 +1,56 @@
+//! Export of the simulation fields and of the rendered images to file formats
+//! used by external tools
+pub mod animation;
+mod deflate;
+pub mod gif;
+pub mod npy;
+pub mod png;
+pub mod svg;
+pub mod video;
+pub mod vtk;
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encode data in (padded) base64.
+pub(crate) fn base64(data: &[u8]) -> String {
+    let mut out = String::with_capacity(4 * data.len().div_ceil(3));
+
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | b[2] as u32;
+
+        for k in 0..4 {
+            if k <= chunk.len() {
+                out.push(BASE64_ALPHABET[((n >> (18 - 6 * k)) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+
+    out
+}
+
+/// CRC-32 (IEEE) checksum, as used by zip and PNG.
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffff_u32;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+/// Opaque color of an RGBA pixel, blended over white like the page behind the canvas.
+pub(crate) fn over_white([r, g, b, a]: [u8; 4]) -> [u8; 3] {
+    let over = |c: u8| ((c as u32 * a as u32 + 255 * (255 - a as u32) + 127) / 255) as u8;
+    [over(r), over(g), over(b)]
+}
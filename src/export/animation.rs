@@ -0,0 +1,121 @@
+//! Recording of rendered frames into an animated GIF or PNG
+use std::fmt;
+use std::str::FromStr;
+
+use crate::export::gif::Gif;
+use crate::export::png::Apng;
+use crate::visualization::Image;
+
+/// File format of a [FrameRecorder]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AnimationFormat {
+    /// Animated GIF: 256 colors per frame, plays everywhere
+    Gif,
+    /// Animated PNG: lossless, larger
+    Apng,
+}
+
+impl FromStr for AnimationFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gif" => Ok(AnimationFormat::Gif),
+            "apng" => Ok(AnimationFormat::Apng),
+            _ => Err(format!("unknown animation format: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for AnimationFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AnimationFormat::Gif => "gif",
+            AnimationFormat::Apng => "apng",
+        })
+    }
+}
+
+enum Encoder {
+    Gif(Gif),
+    Apng(Apng),
+}
+
+/// Keeps one frame every `stride` and encodes them as they come
+///
+/// The size of the animation is the size of the first frame kept.
+pub struct FrameRecorder {
+    format: AnimationFormat,
+    stride: usize,
+    fps: u16,
+    /// number of frames offered so far, kept or not
+    offered: usize,
+    encoder: Option<Encoder>,
+}
+
+impl FrameRecorder {
+    /// Create a recorder keeping every `stride`-th frame, played at `fps` frames per second.
+    pub fn new(format: AnimationFormat, stride: usize, fps: u16) -> Self {
+        Self {
+            format,
+            stride: stride.max(1),
+            fps,
+            offered: 0,
+            encoder: None,
+        }
+    }
+
+    pub fn format(&self) -> AnimationFormat {
+        self.format
+    }
+
+    /// Number of frames kept so far
+    pub fn len(&self) -> usize {
+        match &self.encoder {
+            None => 0,
+            Some(Encoder::Gif(gif)) => gif.len(),
+            Some(Encoder::Apng(apng)) => apng.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Offer a frame, rendered by `render` only if it is kept.
+    ///
+    /// Returns true if the frame was kept.
+    pub fn add_frame(
+        &mut self,
+        render: impl FnOnce() -> Result<Image, String>,
+    ) -> Result<bool, String> {
+        let due = self.offered.is_multiple_of(self.stride);
+        self.offered += 1;
+        if !due {
+            return Ok(false);
+        }
+
+        let image = render()?;
+        let (width, height) = image.size();
+        let (format, fps) = (self.format, self.fps);
+        let encoder = self.encoder.get_or_insert_with(|| match format {
+            AnimationFormat::Gif => Encoder::Gif(Gif::new(width, height, fps)),
+            AnimationFormat::Apng => Encoder::Apng(Apng::new(width, height, fps)),
+        });
+
+        match encoder {
+            Encoder::Gif(gif) => gif.add_frame(&image)?,
+            Encoder::Apng(apng) => apng.add_frame(&image)?,
+        }
+        Ok(true)
+    }
+
+    /// Returns the animation file; fails if no frame was kept.
+    pub fn finish(self) -> Result<Vec<u8>, String> {
+        match self.encoder {
+            None => Err("no frame recorded".to_string()),
+            Some(Encoder::Gif(gif)) => Ok(gif.finish()),
+            Some(Encoder::Apng(apng)) => apng.finish(),
+        }
+    }
+}
@@ -0,0 +1,302 @@
+//! Minimal zlib (deflate) compressor for the PNG export
+//!
+//! Greedy LZ77 matching with hash chains, written as a single block with the
+//! fixed Huffman codes: far from the best ratio, but the rendered images are
+//! mostly flat colors and compress well anyway.
+
+/// Size of the LZ77 window
+const WINDOW: usize = 1 << 15;
+/// Number of candidates looked at for a match
+const MAX_CHAIN: usize = 64;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const HASH_BITS: u32 = 15;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// Bits packed from the least significant one, as deflate wants them
+struct BitWriter {
+    out: Vec<u8>,
+    bits: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, count: u32) {
+        self.bits |= (value as u64) << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Huffman codes are packed from their most significant bit.
+    fn write_code(&mut self, code: u32, length: u32) {
+        self.write(code.reverse_bits() >> (32 - length), length);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.bits as u8);
+        }
+        self.out
+    }
+}
+
+/// Write a literal/length symbol with the fixed Huffman code.
+fn write_symbol(w: &mut BitWriter, symbol: u32) {
+    match symbol {
+        0..=143 => w.write_code(0x30 + symbol, 8),
+        144..=255 => w.write_code(0x190 + symbol - 144, 9),
+        256..=279 => w.write_code(symbol - 256, 7),
+        _ => w.write_code(0xc0 + symbol - 280, 8),
+    }
+}
+
+fn write_match(w: &mut BitWriter, length: usize, distance: usize) {
+    let code = LENGTH_BASE.partition_point(|&base| base as usize <= length) - 1;
+    write_symbol(w, 257 + code as u32);
+    w.write(
+        (length - LENGTH_BASE[code] as usize) as u32,
+        LENGTH_EXTRA[code] as u32,
+    );
+
+    let code = DISTANCE_BASE.partition_point(|&base| base as usize <= distance) - 1;
+    w.write_code(code as u32, 5);
+    w.write(
+        (distance - DISTANCE_BASE[code] as usize) as u32,
+        DISTANCE_EXTRA[code] as u32,
+    );
+}
+
+fn hash(data: &[u8]) -> usize {
+    let n = (data[0] as u32) << 16 | (data[1] as u32) << 8 | data[2] as u32;
+    (n.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+}
+
+/// Adler-32 checksum, as used by zlib
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1_u32, 0_u32);
+    // the sums cannot overflow within 5552 bytes
+    for chunk in data.chunks(5552) {
+        for &x in chunk {
+            a += x as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    b << 16 | a
+}
+
+/// Compress `data` into a zlib stream.
+pub(crate) fn zlib(data: &[u8]) -> Vec<u8> {
+    let mut w = BitWriter {
+        // deflate, 32K window, no dictionary, fastest
+        out: vec![0x78, 0x01],
+        bits: 0,
+        count: 0,
+    };
+    // a single final block with the fixed codes
+    w.write(1, 1);
+    w.write(1, 2);
+
+    // most recent position of each hash, and the previous one with the same
+    // hash for each position in the window
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; WINDOW];
+    let insert = |i: usize, head: &mut [usize], prev: &mut [usize]| {
+        if i + MIN_MATCH <= data.len() {
+            let h = hash(&data[i..]);
+            prev[i % WINDOW] = head[h];
+            head[h] = i;
+        }
+    };
+
+    let mut i = 0;
+    while i < data.len() {
+        let mut best = (0, 0);
+
+        if i + MIN_MATCH <= data.len() {
+            let max = MAX_MATCH.min(data.len() - i);
+            let mut candidate = head[hash(&data[i..])];
+
+            for _ in 0..MAX_CHAIN {
+                if candidate == usize::MAX || i - candidate > WINDOW - 1 {
+                    break;
+                }
+                let length = data[candidate..]
+                    .iter()
+                    .zip(&data[i..i + max])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if length > best.0 {
+                    best = (length, i - candidate);
+                    if length == max {
+                        break;
+                    }
+                }
+
+                let next = prev[candidate % WINDOW];
+                // the slot was reused by a more recent position
+                if next != usize::MAX && next >= candidate {
+                    break;
+                }
+                candidate = next;
+            }
+        }
+
+        let (length, distance) = best;
+        if length >= MIN_MATCH {
+            write_match(&mut w, length, distance);
+            for k in i..i + length {
+                insert(k, &mut head, &mut prev);
+            }
+            i += length;
+        } else {
+            write_symbol(&mut w, data[i] as u32);
+            insert(i, &mut head, &mut prev);
+            i += 1;
+        }
+    }
+    write_symbol(&mut w, 256);
+
+    let mut out = w.finish();
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Bits read from the least significant one
+    struct BitReader<'a> {
+        data: &'a [u8],
+        position: usize,
+    }
+
+    impl BitReader<'_> {
+        fn bit(&mut self) -> u32 {
+            let bit = (self.data[self.position / 8] >> (self.position % 8)) & 1;
+            self.position += 1;
+            bit as u32
+        }
+
+        fn bits(&mut self, count: u32) -> u32 {
+            (0..count).fold(0, |value, k| value | self.bit() << k)
+        }
+
+        /// A fixed Huffman code of `length` bits, most significant bit first
+        fn code(&mut self, mut code: u32, length: u32) -> u32 {
+            for _ in 0..length {
+                code = code << 1 | self.bit();
+            }
+            code
+        }
+
+        /// A literal/length symbol of the fixed Huffman code
+        fn symbol(&mut self) -> u32 {
+            let code = self.code(0, 7);
+            if code <= 0x17 {
+                return 256 + code;
+            }
+            let code = self.code(code, 1);
+            match code {
+                0x30..=0xbf => code - 0x30,
+                0xc0..=0xc7 => 280 + code - 0xc0,
+                _ => 144 + self.code(code, 1) - 0x190,
+            }
+        }
+    }
+
+    /// Decompress a zlib stream of fixed Huffman blocks, checking its header
+    /// and checksum.
+    pub(crate) fn inflate(stream: &[u8]) -> Vec<u8> {
+        assert_eq!(stream[0] & 0x0f, 8, "not deflate");
+        assert_eq!((stream[0] as u32 * 256 + stream[1] as u32) % 31, 0);
+
+        let mut r = BitReader {
+            data: &stream[2..],
+            position: 0,
+        };
+        let mut out: Vec<u8> = vec![];
+        loop {
+            let last = r.bit();
+            assert_eq!(r.bits(2), 1, "not a fixed Huffman block");
+
+            loop {
+                let symbol = r.symbol();
+                match symbol {
+                    0..=255 => out.push(symbol as u8),
+                    256 => break,
+                    _ => {
+                        let code = (symbol - 257) as usize;
+                        let length =
+                            LENGTH_BASE[code] as usize + r.bits(LENGTH_EXTRA[code] as u32) as usize;
+                        let code = r.code(0, 5) as usize;
+                        let distance = DISTANCE_BASE[code] as usize
+                            + r.bits(DISTANCE_EXTRA[code] as u32) as usize;
+                        assert!(distance <= out.len());
+                        for _ in 0..length {
+                            out.push(out[out.len() - distance]);
+                        }
+                    }
+                }
+            }
+
+            if last == 1 {
+                break;
+            }
+        }
+
+        let end = 2 + r.position.div_ceil(8);
+        assert_eq!(stream[end..], adler32(&out).to_be_bytes());
+        out
+    }
+
+    #[test]
+    fn adler32_reference() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+    }
+
+    #[test]
+    fn zlib_round_trip() {
+        let mut seed = 1_u32;
+        let mut random = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed
+        };
+
+        let noise: Vec<u8> = (0..10_000).map(|_| random() as u8).collect();
+        let runs: Vec<u8> = (0..100_000).map(|k| (k / 1000) as u8).collect();
+        // repeats further away than the window
+        let far: Vec<u8> = noise.iter().cycle().take(50_000).copied().collect();
+        let text = b"abcabcabcabd abracadabra abracadabra".repeat(50);
+
+        for data in [&[][..], b"a", b"ab", &noise, &runs, &far, &text] {
+            assert_eq!(inflate(&zlib(data)), data);
+        }
+
+        assert!(zlib(&runs).len() < runs.len() / 50);
+    }
+}
@@ -0,0 +1,406 @@
+//! Animated GIF encoding of rendered images
+//!
+//! Each frame gets its own palette of 256 colors, chosen by median cut over
+//! the colors of the frame reduced to 5 bits per channel, so that the smooth
+//! gradients of the colormaps keep as many shades as possible. Transparent
+//! pixels are drawn over white.
+use std::collections::HashMap;
+
+use crate::export::over_white;
+use crate::visualization::Image;
+
+/// Number of bins of the 5 bits per channel histogram
+const BINS: usize = 1 << 15;
+
+fn bin([r, g, b]: [u8; 3]) -> usize {
+    (r as usize >> 3) << 10 | (g as usize >> 3) << 5 | b as usize >> 3
+}
+
+/// A color of the histogram: the bin, its population and the sums of its colors
+#[derive(Clone, Copy)]
+struct Entry {
+    bin: usize,
+    count: u32,
+    sum: [u32; 3],
+}
+
+impl Entry {
+    fn channel(&self, c: usize) -> u32 {
+        (self.bin >> (10 - 5 * c)) as u32 & 31
+    }
+}
+
+/// Returns the palette of the pixels and the palette index of each bin.
+fn quantize(pixels: &[[u8; 3]]) -> (Vec<[u8; 3]>, Vec<u8>) {
+    let mut histogram = vec![
+        Entry {
+            bin: 0,
+            count: 0,
+            sum: [0; 3]
+        };
+        BINS
+    ];
+    for &p in pixels {
+        let entry = &mut histogram[bin(p)];
+        entry.count += 1;
+        for (sum, c) in entry.sum.iter_mut().zip(p) {
+            *sum += c as u32;
+        }
+    }
+
+    let mut entries: Vec<Entry> = histogram
+        .iter()
+        .enumerate()
+        .filter(|(_, entry)| entry.count > 0)
+        .map(|(bin, entry)| Entry { bin, ..*entry })
+        .collect();
+
+    // boxes are ranges of `entries`; split the one with the widest channel at its median
+    let mut boxes = Vec::with_capacity(256);
+    boxes.push(0..entries.len());
+    while boxes.len() < 256 {
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.len() > 1)
+            .map(|(k, b)| {
+                let (channel, width) = (0..3)
+                    .map(|c| {
+                        let values = entries[b.clone()].iter().map(|e| e.channel(c));
+                        let width = values.clone().max().unwrap() - values.min().unwrap();
+                        (c, width)
+                    })
+                    .max_by_key(|&(_, width)| width)
+                    .unwrap();
+                (k, channel, width)
+            })
+            .max_by_key(|&(_, _, width)| width);
+
+        let Some((k, channel, _)) = widest else {
+            break;
+        };
+
+        let b = boxes[k].clone();
+        let slice = &mut entries[b.clone()];
+        slice.sort_unstable_by_key(|e| e.channel(channel));
+
+        let total: u32 = slice.iter().map(|e| e.count).sum();
+        let mut seen = 0;
+        let median = slice
+            .iter()
+            .position(|e| {
+                seen += e.count;
+                2 * seen >= total
+            })
+            .unwrap();
+        // both halves keep at least one color
+        let split = b.start + (median + 1).min(slice.len() - 1);
+
+        boxes[k] = b.start..split;
+        boxes.push(split..b.end);
+    }
+
+    let mut palette = Vec::with_capacity(256);
+    let mut lookup = vec![0; BINS];
+    for (k, b) in boxes.iter().enumerate() {
+        let mut count = 0_u64;
+        let mut sum = [0_u64; 3];
+        for entry in &entries[b.clone()] {
+            count += entry.count as u64;
+            for (sum, s) in sum.iter_mut().zip(entry.sum) {
+                *sum += s as u64;
+            }
+            lookup[entry.bin] = k as u8;
+        }
+        palette.push(sum.map(|s| ((s + count / 2) / count.max(1)) as u8));
+    }
+
+    (palette, lookup)
+}
+
+/// Variable-length codes, packed from the least significant bit
+struct Codes {
+    out: Vec<u8>,
+    bits: u32,
+    count: u32,
+}
+
+impl Codes {
+    fn emit(&mut self, code: u32, size: u32) {
+        self.bits |= code << self.count;
+        self.count += size;
+        while self.count >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.bits as u8);
+        }
+        self.out
+    }
+}
+
+/// LZW compression of the color indices, as GIF does it: codes from 9 to 12
+/// bits, with a clear code once the dictionary is full.
+fn lzw(indices: &[u8]) -> Vec<u8> {
+    const CLEAR: u32 = 256;
+    const END: u32 = 257;
+
+    let mut codes = Codes {
+        out: vec![],
+        bits: 0,
+        count: 0,
+    };
+    let mut dictionary: HashMap<(u32, u8), u32> = HashMap::new();
+    let mut next = END + 1;
+    let mut size = 9;
+    codes.emit(CLEAR, size);
+
+    let Some((&first, rest)) = indices.split_first() else {
+        codes.emit(END, size);
+        return codes.finish();
+    };
+
+    let mut prefix = first as u32;
+    for &index in rest {
+        if let Some(&code) = dictionary.get(&(prefix, index)) {
+            prefix = code;
+            continue;
+        }
+
+        codes.emit(prefix, size);
+        // the decoder widens the codes once it has filled the current size
+        if next >= 1 << size && size < 12 {
+            size += 1;
+        }
+        if next < 4096 {
+            dictionary.insert((prefix, index), next);
+            next += 1;
+        } else {
+            codes.emit(CLEAR, size);
+            dictionary.clear();
+            next = END + 1;
+            size = 9;
+        }
+        prefix = index as u32;
+    }
+
+    codes.emit(prefix, size);
+    if next >= 1 << size && size < 12 {
+        size += 1;
+    }
+    codes.emit(END, size);
+
+    codes.finish()
+}
+
+/// Animated GIF, built frame by frame
+pub struct Gif {
+    size: (usize, usize),
+    /// delay between the frames, in hundredths of a second
+    delay: u16,
+    frames: usize,
+    out: Vec<u8>,
+}
+
+impl Gif {
+    /// Create an animation of `width` by `height` pixels at `fps` frames per second.
+    ///
+    /// GIF delays are in hundredths of a second: the frame rate is rounded.
+    pub fn new(width: usize, height: usize, fps: u16) -> Self {
+        let mut out = b"GIF89a".to_vec();
+        out.extend_from_slice(&(width as u16).to_le_bytes());
+        out.extend_from_slice(&(height as u16).to_le_bytes());
+        // no global palette, background color and aspect ratio unused
+        out.extend_from_slice(&[0, 0, 0]);
+        // loop forever
+        out.extend_from_slice(b"\x21\xff\x0bNETSCAPE2.0\x03\x01\x00\x00\x00");
+
+        Self {
+            size: (width, height),
+            delay: (100. / fps.max(1) as f32).round().max(1.) as u16,
+            frames: 0,
+            out,
+        }
+    }
+
+    /// Number of frames added so far
+    pub fn len(&self) -> usize {
+        self.frames
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames == 0
+    }
+
+    /// Add a frame; it must have the size of the animation.
+    pub fn add_frame(&mut self, image: &Image) -> Result<(), String> {
+        if image.size() != self.size {
+            return Err(format!(
+                "frame of {:?} pixels in an animation of {:?}",
+                image.size(),
+                self.size
+            ));
+        }
+        let (width, height) = self.size;
+
+        let pixels: Vec<[u8; 3]> = image
+            .data()
+            .chunks_exact(4)
+            .map(|p| over_white([p[0], p[1], p[2], p[3]]))
+            .collect();
+        let (mut palette, lookup) = quantize(&pixels);
+        palette.resize(256, [0; 3]);
+        let indices: Vec<u8> = pixels.iter().map(|&p| lookup[bin(p)]).collect();
+
+        let out = &mut self.out;
+        // graphic control: the frame is left in place, no transparency
+        out.extend_from_slice(&[0x21, 0xf9, 4, 1 << 2]);
+        out.extend_from_slice(&self.delay.to_le_bytes());
+        out.extend_from_slice(&[0, 0]);
+
+        // image descriptor, with a local palette of 256 colors
+        out.push(0x2c);
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(&(width as u16).to_le_bytes());
+        out.extend_from_slice(&(height as u16).to_le_bytes());
+        out.push(0x80 | 7);
+        out.extend(palette.iter().flatten());
+
+        // minimum code size, then the data in blocks of at most 255 bytes
+        out.push(8);
+        for block in lzw(&indices).chunks(255) {
+            out.push(block.len() as u8);
+            out.extend_from_slice(block);
+        }
+        out.push(0);
+
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Returns the GIF file, looping forever.
+    pub fn finish(mut self) -> Vec<u8> {
+        self.out.push(0x3b);
+        self.out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decode GIF LZW codes with a minimum code size of 8; returns the indices
+    /// and the number of clear codes after the first one.
+    fn unlzw(data: &[u8]) -> (Vec<u8>, usize) {
+        const CLEAR: u32 = 256;
+        const END: u32 = 257;
+
+        let mut position = 0;
+        let mut read = |size: u32| {
+            let mut code = 0;
+            for k in 0..size {
+                let bit = (data[position / 8] >> (position % 8)) & 1;
+                code |= (bit as u32) << k;
+                position += 1;
+            }
+            code
+        };
+
+        let initial: Vec<Vec<u8>> = (0..=255_u8)
+            .map(|k| vec![k])
+            .chain([vec![], vec![]])
+            .collect();
+        let mut dictionary = initial.clone();
+        let mut size = 9;
+        let mut previous: Option<u32> = None;
+        let mut out = vec![];
+        let mut resets = 0;
+
+        assert_eq!(read(size), CLEAR);
+        loop {
+            let code = read(size);
+            if code == CLEAR {
+                dictionary = initial.clone();
+                size = 9;
+                previous = None;
+                resets += 1;
+                continue;
+            }
+            if code == END {
+                break;
+            }
+
+            let entry = match previous {
+                None => dictionary[code as usize].clone(),
+                Some(previous) => {
+                    let prefix = &dictionary[previous as usize];
+                    let entry = match dictionary.get(code as usize) {
+                        Some(entry) => entry.clone(),
+                        // the entry being defined by this very code
+                        None => [&prefix[..], &prefix[..1]].concat(),
+                    };
+                    if dictionary.len() < 4096 {
+                        dictionary.push([&prefix[..], &entry[..1]].concat());
+                    }
+                    entry
+                }
+            };
+            out.extend_from_slice(&entry);
+            previous = Some(code);
+
+            if dictionary.len() == 1 << size && size < 12 {
+                size += 1;
+            }
+        }
+
+        (out, resets)
+    }
+
+    #[test]
+    fn lzw_round_trip() {
+        assert_eq!(unlzw(&lzw(&[])), (vec![], 0));
+        assert_eq!(unlzw(&lzw(&[7])), (vec![7], 0));
+
+        let runs: Vec<u8> = (0..20_000).map(|k| (k / 100) as u8).collect();
+        assert_eq!(unlzw(&lzw(&runs)).0, runs);
+
+        // incompressible data fills the dictionary several times
+        let mut seed = 1_u32;
+        let noise: Vec<u8> = (0..50_000)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                seed as u8
+            })
+            .collect();
+        let (decoded, resets) = unlzw(&lzw(&noise));
+        assert_eq!(decoded, noise);
+        assert!(resets >= 2);
+    }
+
+    #[test]
+    fn gif_structure() {
+        let mut image = Image::new(5, 3, 1);
+        image.put_pixel(1, 1, [255, 0, 0, 255]);
+
+        let mut gif = Gif::new(5, 3, 20);
+        assert!(gif.add_frame(&Image::new(4, 3, 1)).is_err());
+        gif.add_frame(&image).unwrap();
+        gif.add_frame(&image).unwrap();
+        assert_eq!(gif.len(), 2);
+
+        let out = gif.finish();
+        assert_eq!(&out[..6], b"GIF89a");
+        assert_eq!(out[6..10], [5, 0, 3, 0]);
+        assert_eq!(out.last(), Some(&0x3b));
+        // after the header and the loop extension, 5 hundredths of a second per frame
+        assert_eq!(out[32..36], [0x21, 0xf9, 4, 1 << 2]);
+        assert_eq!(out[36..38], [5, 0]);
+    }
+}
@@ -0,0 +1,324 @@
+//! NumPy (`.npy`/`.npz`) export with a JSON metadata sidecar
+//!
+//! Each step is bundled into an `.npz` archive holding the raw fields with
+//! their `(num_x, num_y)` shape, border cells included, indexed as
+//! `field[i, j]`:
+//! - `u`, `v`: staggered velocity components
+//! - `u_cell`, `v_cell`: velocity averaged to the cell centers
+//! - `p`: pressure
+//! - `s`: obstacle field (0 => obstacle, 1 => fluid)
+//! - `m`: smoke
+//!
+//! The sidecar `.json` records the grid, the physical parameters, the
+//! simulated time and the solver settings.
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use wasm_bindgen::prelude::*;
+
+use crate::export::crc32;
+use crate::simu::Fluid;
+
+/// Write a 2D `float32` array in `.npy` format.
+pub fn write_npy<W: Write>(data: &[f32], shape: (usize, usize), w: &mut W) -> io::Result<()> {
+    assert_eq!(data.len(), shape.0 * shape.1, "shape does not match data");
+
+    let mut header = format!(
+        "{{'descr': '<f4', 'fortran_order': False, 'shape': ({}, {}), }}",
+        shape.0, shape.1
+    );
+    // magic (6) + version (2) + header length (2) + header must be a multiple of 64
+    let padding = (64 - (10 + header.len() + 1) % 64) % 64;
+    header.extend(std::iter::repeat_n(' ', padding));
+    header.push('\n');
+
+    w.write_all(b"\x93NUMPY\x01\x00")?;
+    w.write_all(&(header.len() as u16).to_le_bytes())?;
+    w.write_all(header.as_bytes())?;
+
+    let mut buf = Vec::with_capacity(4 * data.len());
+    for x in data {
+        buf.extend_from_slice(&x.to_le_bytes());
+    }
+    w.write_all(&buf)
+}
+
+/// Write files in an uncompressed zip archive (what `numpy.savez` produces).
+fn write_zip<W: Write>(files: &[(String, Vec<u8>)], w: &mut W) -> io::Result<()> {
+    // 1980-01-01 00:00, the earliest date zip can store
+    const DOS_TIME: u16 = 0;
+    const DOS_DATE: u16 = (1 << 5) | 1;
+
+    let mut central = Vec::new();
+    let mut offset = 0_u32;
+
+    for (name, data) in files {
+        let crc = crc32(data);
+        let size = data.len() as u32;
+
+        let mut local = Vec::with_capacity(30 + name.len());
+        local.extend_from_slice(&0x0403_4b50_u32.to_le_bytes());
+        local.extend_from_slice(&20_u16.to_le_bytes()); // version needed
+        local.extend_from_slice(&0_u16.to_le_bytes()); // flags
+        local.extend_from_slice(&0_u16.to_le_bytes()); // stored
+        local.extend_from_slice(&DOS_TIME.to_le_bytes());
+        local.extend_from_slice(&DOS_DATE.to_le_bytes());
+        local.extend_from_slice(&crc.to_le_bytes());
+        local.extend_from_slice(&size.to_le_bytes()); // compressed size
+        local.extend_from_slice(&size.to_le_bytes()); // uncompressed size
+        local.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        local.extend_from_slice(&0_u16.to_le_bytes()); // extra field length
+        local.extend_from_slice(name.as_bytes());
+
+        central.extend_from_slice(&0x0201_4b50_u32.to_le_bytes());
+        central.extend_from_slice(&20_u16.to_le_bytes()); // version made by
+        central.extend_from_slice(&local[4..30]);
+        central.extend_from_slice(&0_u16.to_le_bytes()); // comment length
+        central.extend_from_slice(&0_u16.to_le_bytes()); // disk number
+        central.extend_from_slice(&0_u16.to_le_bytes()); // internal attributes
+        central.extend_from_slice(&0_u32.to_le_bytes()); // external attributes
+        central.extend_from_slice(&offset.to_le_bytes());
+        central.extend_from_slice(name.as_bytes());
+
+        w.write_all(&local)?;
+        w.write_all(data)?;
+
+        offset += local.len() as u32 + size;
+    }
+
+    w.write_all(&central)?;
+
+    w.write_all(&0x0605_4b50_u32.to_le_bytes())?;
+    w.write_all(&0_u16.to_le_bytes())?; // this disk
+    w.write_all(&0_u16.to_le_bytes())?; // disk with the central directory
+    w.write_all(&(files.len() as u16).to_le_bytes())?;
+    w.write_all(&(files.len() as u16).to_le_bytes())?;
+    w.write_all(&(central.len() as u32).to_le_bytes())?;
+    w.write_all(&offset.to_le_bytes())?;
+    w.write_all(&0_u16.to_le_bytes()) // comment length
+}
+
+/// Write the fields of the simulation as an `.npz` archive.
+pub fn write_npz<W: Write>(fluid: &Fluid, w: &mut W) -> io::Result<()> {
+    let shape = (fluid.num_x, fluid.num_y);
+    let (u_cell, v_cell) = fluid.cell_centered_velocity();
+
+    let fields: [(&str, &[f32]); 7] = [
+        ("u", &fluid.u),
+        ("v", &fluid.v),
+        ("u_cell", &u_cell),
+        ("v_cell", &v_cell),
+        ("p", &fluid.p),
+        ("s", &fluid.s),
+        ("m", &fluid.m),
+    ];
+
+    let mut files = Vec::with_capacity(fields.len());
+    for (name, data) in fields {
+        let mut buf = Vec::new();
+        write_npy(data, shape, &mut buf)?;
+        files.push((format!("{}.npy", name), buf));
+    }
+
+    write_zip(&files, w)
+}
+
+/// Format a number as JSON (which has no representation for NaN and infinities).
+fn json_number<T: Into<f64> + fmt::Display + Copy>(x: T) -> String {
+    if x.into().is_finite() {
+        x.to_string()
+    } else {
+        "null".to_string()
+    }
+}
+
+/// Return the JSON sidecar describing the current state of the simulation.
+pub fn metadata_json(fluid: &Fluid) -> String {
+    format!(
+        r#"{{
+  "num_x": {},
+  "num_y": {},
+  "border_cells": 1,
+  "h": {},
+  "density": {},
+  "gravity": {},
+  "in_vel": {},
+  "step": {},
+  "time": {},
+  "solver": {{
+    "num_iters": {},
+    "over_relaxation": {}
+  }}
+}}
+"#,
+        fluid.num_x,
+        fluid.num_y,
+        json_number(fluid.h),
+        json_number(fluid.density),
+        json_number(fluid.gravity),
+        json_number(fluid.in_vel),
+        fluid.step,
+        json_number(fluid.time),
+        fluid.num_iters,
+        json_number(fluid.over_relaxation),
+    )
+}
+
+/// Write `<prefix>_<step>.npz` and its `<prefix>_<step>.json` sidecar in `dir`.
+///
+/// Returns the path of the `.npz` file.
+pub fn write_step(dir: impl AsRef<Path>, prefix: &str, fluid: &Fluid) -> io::Result<PathBuf> {
+    let dir = dir.as_ref();
+    fs::create_dir_all(dir)?;
+
+    let stem = format!("{}_{:06}", prefix, fluid.step);
+
+    let path = dir.join(format!("{}.npz", stem));
+    let mut w = io::BufWriter::new(fs::File::create(&path)?);
+    write_npz(fluid, &mut w)?;
+    w.flush()?;
+
+    fs::write(dir.join(format!("{}.json", stem)), metadata_json(fluid))?;
+
+    Ok(path)
+}
+
+#[wasm_bindgen]
+impl Fluid {
+    /// Export the fields as an `.npz` archive.
+    pub fn export_npz(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        write_npz(self, &mut buf).expect("writing to a Vec cannot fail");
+        buf
+    }
+
+    /// Export the JSON sidecar of [Fluid::export_npz].
+    pub fn export_metadata(&self) -> String {
+        metadata_json(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u16_at(data: &[u8], at: usize) -> u16 {
+        u16::from_le_bytes([data[at], data[at + 1]])
+    }
+
+    fn u32_at(data: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]])
+    }
+
+    /// Returns the header and the values of a `.npy` file.
+    fn read_npy(data: &[u8]) -> (&str, Vec<f32>) {
+        assert_eq!(&data[..8], b"\x93NUMPY\x01\x00");
+        let end = 10 + u16_at(data, 8) as usize;
+        let header = std::str::from_utf8(&data[10..end]).unwrap();
+        let values = data[end..]
+            .chunks_exact(4)
+            .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+            .collect();
+        (header, values)
+    }
+
+    #[test]
+    fn npy_header() {
+        for shape in [(1, 1), (3, 5), (22, 12), (1000, 1000)] {
+            let values: Vec<f32> = (0..shape.0 * shape.1).map(|k| k as f32 - 0.5).collect();
+            let mut data = Vec::new();
+            write_npy(&values, shape, &mut data).unwrap();
+
+            let (header, read) = read_npy(&data);
+            // the data starts aligned on 64 bytes
+            assert_eq!((10 + header.len()) % 64, 0);
+            assert!(header.ends_with('\n'));
+            assert_eq!(
+                header.trim_end(),
+                format!(
+                    "{{'descr': '<f4', 'fortran_order': False, 'shape': ({}, {}), }}",
+                    shape.0, shape.1
+                )
+            );
+            assert_eq!(read, values);
+        }
+    }
+
+    #[test]
+    fn npz_archive() {
+        let mut fluid = Fluid::create(0., 1., 4, 3, 0.1, 1000.);
+        for (k, p) in fluid.p.iter_mut().enumerate() {
+            *p = k as f32;
+        }
+        let mut data = Vec::new();
+        write_npz(&fluid, &mut data).unwrap();
+
+        // end of central directory
+        let end = data.len() - 22;
+        assert_eq!(u32_at(&data, end), 0x0605_4b50);
+        assert_eq!(u16_at(&data, end + 8), 7);
+        assert_eq!(u16_at(&data, end + 10), 7);
+        let central_size = u32_at(&data, end + 12) as usize;
+        let central_offset = u32_at(&data, end + 16) as usize;
+        assert_eq!(central_offset + central_size, end);
+
+        let mut names = vec![];
+        let mut local = 0;
+        let mut central = central_offset;
+        while central < end {
+            assert_eq!(u32_at(&data, central), 0x0201_4b50);
+            let name_len = u16_at(&data, central + 28) as usize;
+            let name = std::str::from_utf8(&data[central + 46..central + 46 + name_len]).unwrap();
+            assert_eq!(u32_at(&data, central + 42) as usize, local);
+
+            // the local header repeats the central one
+            assert_eq!(u32_at(&data, local), 0x0403_4b50);
+            assert_eq!(data[local + 4..local + 30], data[central + 6..central + 32]);
+            assert_eq!(u16_at(&data, local + 8), 0, "stored");
+            let crc = u32_at(&data, local + 14);
+            let size = u32_at(&data, local + 18) as usize;
+            assert_eq!(u32_at(&data, local + 22) as usize, size);
+            assert_eq!(&data[local + 30..local + 30 + name_len], name.as_bytes());
+
+            let start = local + 30 + name_len;
+            let file = &data[start..start + size];
+            assert_eq!(crc32(file), crc);
+
+            let (header, values) = read_npy(file);
+            assert!(header.contains("'shape': (6, 5)"));
+            assert_eq!(values.len(), 30);
+            if name == "p.npy" {
+                assert_eq!(values, fluid.p);
+            }
+
+            names.push(name.to_string());
+            local = start + size;
+            central += 46 + name_len;
+        }
+        assert_eq!(local, central_offset);
+        assert_eq!(
+            names,
+            [
+                "u.npy",
+                "v.npy",
+                "u_cell.npy",
+                "v_cell.npy",
+                "p.npy",
+                "s.npy",
+                "m.npy"
+            ]
+        );
+    }
+
+    #[test]
+    fn metadata() {
+        let mut fluid = Fluid::create(-9.81, 1.5, 4, 3, 0.1, 1000.);
+        fluid.time = f64::NAN;
+        let json = metadata_json(&fluid);
+        assert!(json.contains(r#""num_x": 6,"#));
+        assert!(json.contains(r#""gravity": -9.81,"#));
+        assert!(json.contains(r#""time": null,"#));
+    }
+}
@@ -0,0 +1,326 @@
+//! PNG and animated PNG (APNG) encoding of rendered images
+//!
+//! The images are written as 8-bit RGBA, each row with the filter that
+//! minimizes the sum of the absolute filtered bytes, as libpng does by default.
+use crate::export::crc32;
+use crate::export::deflate::zlib;
+use crate::visualization::Image;
+
+const SIGNATURE: &[u8; 8] = b"\x89PNG\r\n\x1a\n";
+
+/// Append a chunk of type `kind` to `out`.
+fn write_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32(&out[start..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn write_header(out: &mut Vec<u8>, (width, height): (usize, usize)) {
+    out.extend_from_slice(SIGNATURE);
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&(width as u32).to_be_bytes());
+    ihdr.extend_from_slice(&(height as u32).to_be_bytes());
+    // 8 bits per channel, RGBA, deflate, adaptive filtering, not interlaced
+    ihdr.extend_from_slice(&[8, 6, 0, 0, 0]);
+    write_chunk(out, b"IHDR", &ihdr);
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Filter the rows of the image and compress them.
+fn compress(image: &Image) -> Vec<u8> {
+    let (width, _) = image.size();
+    let stride = 4 * width;
+    let zero = vec![0; stride];
+
+    let mut filtered = Vec::with_capacity(image.data().len() + image.data().len() / stride);
+    let mut candidate = vec![0; stride];
+    let mut best = vec![0; stride];
+
+    for (j, row) in image.data().chunks(stride).enumerate() {
+        let above = if j == 0 {
+            &zero[..]
+        } else {
+            &image.data()[(j - 1) * stride..j * stride]
+        };
+
+        let mut best_filter = 0;
+        let mut best_cost = u64::MAX;
+        for filter in 0..5_u8 {
+            for k in 0..stride {
+                let a = if k >= 4 { row[k - 4] } else { 0 };
+                let b = above[k];
+                let c = if k >= 4 { above[k - 4] } else { 0 };
+                let predicted = match filter {
+                    0 => 0,
+                    1 => a,
+                    2 => b,
+                    3 => ((a as u16 + b as u16) / 2) as u8,
+                    _ => paeth(a, b, c),
+                };
+                candidate[k] = row[k].wrapping_sub(predicted);
+            }
+
+            let cost = candidate
+                .iter()
+                .map(|&x| (x as i8).unsigned_abs() as u64)
+                .sum();
+            if cost < best_cost {
+                best_cost = cost;
+                best_filter = filter;
+                std::mem::swap(&mut best, &mut candidate);
+            }
+        }
+
+        filtered.push(best_filter);
+        filtered.extend_from_slice(&best);
+    }
+
+    zlib(&filtered)
+}
+
+/// Encode the image as a PNG file.
+pub fn encode(image: &Image) -> Vec<u8> {
+    let mut out = vec![];
+    write_header(&mut out, image.size());
+    write_chunk(&mut out, b"IDAT", &compress(image));
+    write_chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+/// Animated PNG, built frame by frame
+///
+/// The frames are compressed as they are added; the file is assembled by
+/// [Apng::finish] since the number of frames comes first.
+pub struct Apng {
+    size: (usize, usize),
+    /// delay between the frames, in seconds as a fraction
+    delay: (u16, u16),
+    frames: Vec<Vec<u8>>,
+}
+
+impl Apng {
+    /// Create an animation of `width` by `height` pixels at `fps` frames per second.
+    pub fn new(width: usize, height: usize, fps: u16) -> Self {
+        Self {
+            size: (width, height),
+            delay: (1, fps.max(1)),
+            frames: vec![],
+        }
+    }
+
+    /// Number of frames added so far
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Add a frame; it must have the size of the animation.
+    pub fn add_frame(&mut self, image: &Image) -> Result<(), String> {
+        if image.size() != self.size {
+            return Err(format!(
+                "frame of {:?} pixels in an animation of {:?}",
+                image.size(),
+                self.size
+            ));
+        }
+
+        self.frames.push(compress(image));
+        Ok(())
+    }
+
+    /// Returns the APNG file, looping forever.
+    ///
+    /// Fails if no frame was added: a PNG has at least one image.
+    pub fn finish(self) -> Result<Vec<u8>, String> {
+        if self.frames.is_empty() {
+            return Err("no frame in the animation".to_string());
+        }
+
+        let (width, height) = self.size;
+        let mut out = vec![];
+        write_header(&mut out, self.size);
+
+        let mut actl = Vec::with_capacity(8);
+        actl.extend_from_slice(&(self.frames.len() as u32).to_be_bytes());
+        actl.extend_from_slice(&0_u32.to_be_bytes());
+        write_chunk(&mut out, b"acTL", &actl);
+
+        // the frame controls and the frame data share the sequence numbers
+        let mut sequence = 0_u32;
+        for (k, frame) in self.frames.iter().enumerate() {
+            let mut fctl = Vec::with_capacity(26);
+            fctl.extend_from_slice(&sequence.to_be_bytes());
+            fctl.extend_from_slice(&(width as u32).to_be_bytes());
+            fctl.extend_from_slice(&(height as u32).to_be_bytes());
+            // offsets
+            fctl.extend_from_slice(&[0; 8]);
+            fctl.extend_from_slice(&self.delay.0.to_be_bytes());
+            fctl.extend_from_slice(&self.delay.1.to_be_bytes());
+            // no disposal, the frame replaces the previous one
+            fctl.extend_from_slice(&[0, 0]);
+            write_chunk(&mut out, b"fcTL", &fctl);
+            sequence += 1;
+
+            // the first frame is also the image shown by decoders without APNG support
+            if k == 0 {
+                write_chunk(&mut out, b"IDAT", frame);
+            } else {
+                let mut fdat = Vec::with_capacity(4 + frame.len());
+                fdat.extend_from_slice(&sequence.to_be_bytes());
+                fdat.extend_from_slice(frame);
+                write_chunk(&mut out, b"fdAT", &fdat);
+                sequence += 1;
+            }
+        }
+
+        write_chunk(&mut out, b"IEND", &[]);
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::deflate::tests::inflate;
+
+    /// Split a PNG file into its chunks, checking their CRC.
+    fn chunks(file: &[u8]) -> Vec<([u8; 4], &[u8])> {
+        assert_eq!(&file[..8], SIGNATURE);
+
+        let mut chunks = vec![];
+        let mut rest = &file[8..];
+        while !rest.is_empty() {
+            let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            let (kind, data) = (&rest[4..8], &rest[8..8 + len]);
+            let crc = u32::from_be_bytes(rest[8 + len..12 + len].try_into().unwrap());
+            assert_eq!(crc, crc32(&rest[4..8 + len]), "bad CRC");
+
+            chunks.push((kind.try_into().unwrap(), data));
+            rest = &rest[12 + len..];
+        }
+        chunks
+    }
+
+    /// Undo the filters of the rows of an RGBA image.
+    fn unfilter(data: &[u8], width: usize) -> Vec<u8> {
+        let stride = 4 * width;
+        let mut out: Vec<u8> = vec![];
+
+        for (j, row) in data.chunks(stride + 1).enumerate() {
+            let start = out.len();
+            for k in 0..stride {
+                let a = if k >= 4 { out[start + k - 4] } else { 0 };
+                let b = if j > 0 { out[start + k - stride] } else { 0 };
+                let c = if k >= 4 && j > 0 {
+                    out[start + k - stride - 4]
+                } else {
+                    0
+                };
+                let predicted = match row[0] {
+                    0 => 0,
+                    1 => a,
+                    2 => b,
+                    3 => ((a as u16 + b as u16) / 2) as u8,
+                    4 => paeth(a, b, c),
+                    filter => panic!("unknown filter {}", filter),
+                };
+                out.push(row[1 + k].wrapping_add(predicted));
+            }
+        }
+        out
+    }
+
+    fn gradient(width: usize, height: usize, shift: usize) -> Image {
+        let mut image = Image::new(width, height, 1);
+        for y in 0..height {
+            for x in 0..width {
+                let v = ((x + shift) * 255 / width) as u8;
+                image.put_pixel(x, y, [v, (y * 255 / height) as u8, 255 - v, 200]);
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn crc32_reference() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let image = gradient(17, 9, 0);
+        let file = encode(&image);
+
+        let chunks = chunks(&file);
+        let kinds: Vec<&[u8; 4]> = chunks.iter().map(|(kind, _)| kind).collect();
+        assert_eq!(kinds, [b"IHDR", b"IDAT", b"IEND"]);
+        assert_eq!(chunks[0].1, [0, 0, 0, 17, 0, 0, 0, 9, 8, 6, 0, 0, 0]);
+
+        assert_eq!(unfilter(&inflate(chunks[1].1), 17), image.data());
+    }
+
+    #[test]
+    fn apng_frames() {
+        assert!(Apng::new(4, 4, 10).finish().is_err());
+
+        let frames: Vec<Image> = (0..3).map(|k| gradient(6, 4, k)).collect();
+        let mut apng = Apng::new(6, 4, 10);
+        assert!(apng.add_frame(&Image::new(6, 5, 1)).is_err());
+        for frame in &frames {
+            apng.add_frame(frame).unwrap();
+        }
+        let file = apng.finish().unwrap();
+
+        let chunks = chunks(&file);
+        let kinds: Vec<&[u8; 4]> = chunks.iter().map(|(kind, _)| kind).collect();
+        assert_eq!(
+            kinds,
+            [b"IHDR", b"acTL", b"fcTL", b"IDAT", b"fcTL", b"fdAT", b"fcTL", b"fdAT", b"IEND"]
+        );
+        // 3 frames, looping forever
+        assert_eq!(chunks[1].1, [0, 0, 0, 3, 0, 0, 0, 0]);
+
+        // the frame controls and the frame data are numbered together from 0
+        let sequence: Vec<u32> = chunks
+            .iter()
+            .filter(|(kind, _)| kind == b"fcTL" || kind == b"fdAT")
+            .map(|(_, data)| u32::from_be_bytes(data[..4].try_into().unwrap()))
+            .collect();
+        assert_eq!(sequence, [0, 1, 2, 3, 4]);
+
+        let images: Vec<&[u8]> = chunks
+            .iter()
+            .filter_map(|(kind, data)| match kind {
+                b"IDAT" => Some(*data),
+                b"fdAT" => Some(&data[4..]),
+                _ => None,
+            })
+            .collect();
+        for (data, frame) in images.iter().zip(&frames) {
+            assert_eq!(unfilter(&inflate(data), 6), frame.data());
+        }
+    }
+}
@@ -0,0 +1,219 @@
+//! SVG export of the vector layers of a frame
+//!
+//! The obstacle outlines, iso-lines, streamlines and velocity arrows selected
+//! by the [DrawOptions] are written as paths, in the pixels of the rendered
+//! image (see [Fluid::render_frame]), optionally over the raster of the field
+//! embedded as a PNG. The outlines of the obstacles are the iso-line at 0.5 of
+//! the obstacle field, as in the smooth renderings.
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+
+use crate::export::base64;
+use crate::lic::LicCache;
+use crate::simu::{DrawOptions, Fluid};
+use crate::visualization::{self, ScalarField};
+
+/// Join segments sharing their ends into polylines.
+fn join(segments: &[[(f32, f32); 2]]) -> Vec<Vec<(f32, f32)>> {
+    // the ends are computed on each side of a cell edge: they match up to rounding
+    let key = |(x, y): (f32, f32)| ((x * 64.).round() as i64, (y * 64.).round() as i64);
+
+    let mut ends: HashMap<(i64, i64), Vec<usize>> = HashMap::new();
+    for (k, segment) in segments.iter().enumerate() {
+        for &p in segment {
+            ends.entry(key(p)).or_default().push(k);
+        }
+    }
+
+    let mut used = vec![false; segments.len()];
+    let mut lines = vec![];
+
+    for start in 0..segments.len() {
+        if used[start] {
+            continue;
+        }
+        used[start] = true;
+
+        let mut line = segments[start].to_vec();
+        // forward from the last point, then backward from the first one
+        for _ in 0..2 {
+            while let Some(&next) = ends[&key(line[line.len() - 1])].iter().find(|&&k| !used[k]) {
+                used[next] = true;
+                let [a, b] = segments[next];
+                line.push(if key(a) == key(line[line.len() - 1]) {
+                    b
+                } else {
+                    a
+                });
+            }
+            line.reverse();
+        }
+
+        // the loops end exactly where they start
+        let last = line.len() - 1;
+        if key(line[0]) == key(line[last]) {
+            line[last] = line[0];
+        }
+        lines.push(line);
+    }
+
+    lines
+}
+
+/// Path data of polylines; the closed ones end with `Z`.
+fn path_data(lines: &[Vec<(f32, f32)>]) -> String {
+    let mut d = String::new();
+    for line in lines {
+        for (k, (x, y)) in line.iter().enumerate() {
+            let command = if k == 0 { 'M' } else { 'L' };
+            let _ = write!(d, "{}{:.2},{:.2} ", command, x, y);
+        }
+        if line.len() > 2 && line[0] == line[line.len() - 1] {
+            d.push_str("Z ");
+        }
+    }
+    d.pop();
+    d
+}
+
+/// Write the frame of `fluid` as an SVG image.
+///
+/// `field` and `range` are the field drawn and the range of its colormap (see
+/// [Fluid::render_frame]). With `raster`, the field, and the flow texture if
+/// any, are embedded as an image below the vector layers.
+pub fn write_svg<W: Write>(
+    fluid: &Fluid,
+    options: &DrawOptions,
+    field: &[f32],
+    range: (f32, f32),
+    sim_to_canvas_ratio: u32,
+    raster: bool,
+    w: &mut W,
+) -> io::Result<()> {
+    let colormap = visualization::colormap(&options.colormap)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    let r = sim_to_canvas_ratio as f32;
+    let width = (fluid.num_x - 2) * sim_to_canvas_ratio as usize;
+    let height = (fluid.num_y - 2) * sim_to_canvas_ratio as usize;
+    // physical coordinates to pixels, as in the rendered image
+    let to_pixels = |(x, y): (f32, f32)| {
+        (
+            (x / fluid.h - 1.) * r,
+            height as f32 - (y / fluid.h - 1.) * r,
+        )
+    };
+    let polylines = |lines: Vec<Vec<(f32, f32)>>| -> Vec<Vec<(f32, f32)>> {
+        lines
+            .into_iter()
+            .map(|line| line.into_iter().map(to_pixels).collect())
+            .collect()
+    };
+
+    writeln!(w, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        w,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink" width="{width}" height="{height}" viewBox="0 0 {width} {height}">"#
+    )?;
+
+    if raster {
+        // the vector layers are drawn over it
+        let mut raster_options = options.clone();
+        raster_options.obstacle = false;
+        raster_options.contours = 0;
+        raster_options.streamlines = false;
+        raster_options.quiver = false;
+
+        let image = fluid.render_image(
+            &raster_options,
+            &*colormap,
+            field,
+            range,
+            sim_to_canvas_ratio,
+            &mut LicCache::default(),
+        );
+        writeln!(
+            w,
+            r#"<image id="field" width="{width}" height="{height}" xlink:href="data:image/png;base64,{}"/>"#,
+            base64(&image.to_png())
+        )?;
+    }
+
+    if options.obstacle {
+        let solid = ScalarField::new(&fluid.s, fluid.num_x, fluid.num_y);
+        let h = fluid.h;
+        let segments: Vec<[(f32, f32); 2]> =
+            visualization::contour_segments(&solid, 0.5, |_, _| false)
+                .into_iter()
+                .map(|s| s.map(|(x, y)| to_pixels(((x + 0.5) * h, (y + 0.5) * h))))
+                .collect();
+
+        writeln!(
+            w,
+            r#"<path id="obstacles" fill="black" fill-rule="evenodd" d="{}"/>"#,
+            path_data(&join(&segments))
+        )?;
+    }
+
+    if options.contours > 0 {
+        let segments: Vec<[(f32, f32); 2]> = fluid
+            .contours(field, range, options.contours)
+            .into_iter()
+            .map(|s| s.map(to_pixels))
+            .collect();
+
+        writeln!(
+            w,
+            r#"<path id="contours" fill="none" stroke="black" stroke-opacity="0.7" stroke-linejoin="round" d="{}"/>"#,
+            path_data(&join(&segments))
+        )?;
+    }
+
+    if options.streamlines {
+        writeln!(
+            w,
+            r#"<path id="streamlines" fill="none" stroke="red" stroke-linejoin="round" d="{}"/>"#,
+            path_data(&polylines(fluid.streamlines(options)))
+        )?;
+    }
+
+    if options.quiver {
+        writeln!(w, r#"<g id="quiver" fill="none" stroke-linecap="round">"#)?;
+
+        if let Some((arrows, max_speed)) = fluid.quiver_arrows(options) {
+            for (from, to, speed) in arrows {
+                let (from, to) = (to_pixels(from), to_pixels(to));
+                let mut d = path_data(&[vec![from, to]]);
+
+                // head: two strokes at +/- 25 degrees from the shaft, as on the canvas
+                let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+                let len = (dx * dx + dy * dy).sqrt();
+                if len >= 2. {
+                    let head = (0.35 * len).min(8. * r).max(2.);
+                    let (ux, uy) = (dx / len, dy / len);
+                    let (sin, cos) = 25_f32.to_radians().sin_cos();
+                    let [left, right] = [-1., 1.].map(|side| {
+                        (
+                            to.0 - (ux * cos - side * uy * sin) * head,
+                            to.1 - (uy * cos + side * ux * sin) * head,
+                        )
+                    });
+                    d.push(' ');
+                    d.push_str(&path_data(&[vec![left, to, right]]));
+                }
+
+                let [red, green, blue, _] = colormap.get_color(speed, 0., max_speed);
+                writeln!(
+                    w,
+                    r##"<path stroke="#{:02x}{:02x}{:02x}" d="{}"/>"##,
+                    red, green, blue, d
+                )?;
+            }
+        }
+
+        writeln!(w, "</g>")?;
+    }
+
+    writeln!(w, "</svg>")
+}
@@ -0,0 +1,162 @@
+//! Raw video streams of rendered frames, to be piped into `ffmpeg`
+//!
+//! Two formats are written:
+//! - `rgb24`: the bare pixels, 3 bytes per pixel, row by row from the top; the
+//!   stream has no header, the reader must be given the size and frame rate
+//!   (see [VideoFormat::ffmpeg_input])
+//! - `y4m`: YUV4MPEG2 in 4:4:4, studio range (BT.601); the header carries the
+//!   size and frame rate
+//!
+//! Transparent pixels are drawn over white.
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use crate::export::over_white;
+use crate::visualization::Image;
+
+/// Format of a [VideoWriter] stream
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VideoFormat {
+    Rgb24,
+    Y4m,
+}
+
+impl FromStr for VideoFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rgb24" => Ok(VideoFormat::Rgb24),
+            "y4m" => Ok(VideoFormat::Y4m),
+            _ => Err(format!("unknown video format: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for VideoFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            VideoFormat::Rgb24 => "rgb24",
+            VideoFormat::Y4m => "y4m",
+        })
+    }
+}
+
+impl VideoFormat {
+    /// Returns the `ffmpeg` input options to read a stream of frames of `size` pixels from stdin.
+    pub fn ffmpeg_input(&self, (width, height): (usize, usize), fps: u32) -> String {
+        match self {
+            VideoFormat::Rgb24 => format!(
+                "-f rawvideo -pix_fmt rgb24 -s {}x{} -r {} -i -",
+                width, height, fps
+            ),
+            VideoFormat::Y4m => "-f yuv4mpegpipe -i -".to_string(),
+        }
+    }
+}
+
+/// Studio range YCbCr (BT.601) of an RGB color
+fn ycbcr([r, g, b]: [u8; 3]) -> [u8; 3] {
+    let (r, g, b) = (r as f32 / 255., g as f32 / 255., b as f32 / 255.);
+    let y = 16. + 65.481 * r + 128.553 * g + 24.966 * b;
+    let cb = 128. - 37.797 * r - 74.203 * g + 112. * b;
+    let cr = 128. + 112. * r - 93.786 * g - 18.214 * b;
+    [y.round() as u8, cb.round() as u8, cr.round() as u8]
+}
+
+/// Writes the frames as a raw video stream
+///
+/// All the frames must have the size of the first one.
+pub struct VideoWriter<W: Write> {
+    inner: W,
+    format: VideoFormat,
+    fps: u32,
+    /// size of the frames, known once the first one is written
+    size: Option<(usize, usize)>,
+    frames: usize,
+}
+
+impl<W: Write> VideoWriter<W> {
+    /// Create a stream of `fps` frames per second.
+    pub fn new(inner: W, format: VideoFormat, fps: u32) -> Self {
+        Self {
+            inner,
+            format,
+            fps: fps.max(1),
+            size: None,
+            frames: 0,
+        }
+    }
+
+    /// Number of frames written so far
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// Size of the frames, once the first one is written
+    pub fn size(&self) -> Option<(usize, usize)> {
+        self.size
+    }
+
+    pub fn write_frame(&mut self, image: &Image) -> io::Result<()> {
+        let (width, height) = image.size();
+        match self.size {
+            None => {
+                if self.format == VideoFormat::Y4m {
+                    writeln!(
+                        self.inner,
+                        "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444 XCOLORRANGE=LIMITED",
+                        width, height, self.fps
+                    )?;
+                }
+                self.size = Some((width, height));
+            }
+            Some(size) if size != (width, height) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "frame of {:?} pixels in a video of {:?}",
+                        (width, height),
+                        size
+                    ),
+                ));
+            }
+            Some(_) => {}
+        }
+
+        let pixels = image
+            .data()
+            .chunks_exact(4)
+            .map(|p| over_white([p[0], p[1], p[2], p[3]]));
+
+        match self.format {
+            VideoFormat::Rgb24 => {
+                let data: Vec<u8> = pixels.flatten().collect();
+                self.inner.write_all(&data)?;
+            }
+            VideoFormat::Y4m => {
+                // planar: all the Y, then all the Cb, then all the Cr
+                let n = width * height;
+                let mut data = vec![0; 3 * n];
+                for (k, p) in pixels.enumerate() {
+                    let [y, cb, cr] = ycbcr(p);
+                    data[k] = y;
+                    data[n + k] = cb;
+                    data[2 * n + k] = cr;
+                }
+                self.inner.write_all(b"FRAME\n")?;
+                self.inner.write_all(&data)?;
+            }
+        }
+
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Flush the stream and return the writer.
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
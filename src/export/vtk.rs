@@ -0,0 +1,450 @@
+//! VTK ImageData (`.vti`) export for ParaView
+//!
+//! Each time step is written as a `.vti` file holding the interior cells of
+//! the simulation as cell data:
+//! - `velocity`: cell-centred velocity (averaged from the staggered grid)
+//! - `pressure`
+//! - `smoke`
+//! - `vorticity`
+//! - `solid`: 1 for obstacle cells, 0 for fluid cells
+//!
+//! A series of steps is tied together by a `.pvd` collection file.
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use wasm_bindgen::prelude::*;
+
+use crate::export::base64;
+use crate::simu::Fluid;
+
+/// How the data arrays are encoded in the `.vti` file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VtkEncoding {
+    /// Human readable values
+    Ascii,
+    /// Inline base64-encoded binary
+    Base64,
+    /// Raw binary in an appended data section
+    Raw,
+}
+
+impl FromStr for VtkEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ascii" => Ok(VtkEncoding::Ascii),
+            "base64" => Ok(VtkEncoding::Base64),
+            "raw" => Ok(VtkEncoding::Raw),
+            _ => Err(format!("unknown VTK encoding: {}", s)),
+        }
+    }
+}
+
+/// A cell data array
+struct DataArray {
+    name: &'static str,
+    num_components: usize,
+    /// values in VTK order (x varies fastest)
+    values: Vec<f32>,
+}
+
+impl DataArray {
+    /// Bytes of the array as stored in binary form, prefixed with their length.
+    fn binary(&self) -> Vec<u8> {
+        let len = 4 * self.values.len();
+
+        let mut buf = Vec::with_capacity(4 + len);
+        buf.extend_from_slice(&(len as u32).to_le_bytes());
+        for x in &self.values {
+            buf.extend_from_slice(&x.to_le_bytes());
+        }
+        buf
+    }
+}
+
+/// Gather the exported arrays from the interior cells of the simulation.
+fn data_arrays(fluid: &Fluid) -> Vec<DataArray> {
+    let n = fluid.num_y;
+
+    let (uc, vc) = fluid.cell_centered_velocity();
+    let w = fluid.vorticity();
+
+    // VTK wants x to vary fastest, our fields have y varying fastest
+    let interior = |f: &dyn Fn(usize) -> f32| -> Vec<f32> {
+        let mut out = Vec::with_capacity((fluid.num_x - 2) * (fluid.num_y - 2));
+        for j in 1..fluid.num_y - 1 {
+            for i in 1..fluid.num_x - 1 {
+                out.push(f(i * n + j));
+            }
+        }
+        out
+    };
+
+    let mut velocity = Vec::with_capacity(3 * (fluid.num_x - 2) * (fluid.num_y - 2));
+    for j in 1..fluid.num_y - 1 {
+        for i in 1..fluid.num_x - 1 {
+            velocity.extend_from_slice(&[uc[i * n + j], vc[i * n + j], 0.0]);
+        }
+    }
+
+    vec![
+        DataArray {
+            name: "velocity",
+            num_components: 3,
+            values: velocity,
+        },
+        DataArray {
+            name: "pressure",
+            num_components: 1,
+            values: interior(&|k| fluid.p[k]),
+        },
+        DataArray {
+            name: "smoke",
+            num_components: 1,
+            values: interior(&|k| fluid.m[k]),
+        },
+        DataArray {
+            name: "vorticity",
+            num_components: 1,
+            values: interior(&|k| w[k]),
+        },
+        DataArray {
+            name: "solid",
+            num_components: 1,
+            values: interior(&|k| if fluid.s[k] == 0. { 1.0 } else { 0.0 }),
+        },
+    ]
+}
+
+/// Write the current state of the simulation as a VTK ImageData file.
+pub fn write_vti<W: Write>(fluid: &Fluid, encoding: VtkEncoding, w: &mut W) -> io::Result<()> {
+    let nx = fluid.num_x - 2;
+    let ny = fluid.num_y - 2;
+    let h = fluid.h;
+
+    let arrays = data_arrays(fluid);
+
+    writeln!(w, r#"<?xml version="1.0"?>"#)?;
+    writeln!(
+        w,
+        r#"<VTKFile type="ImageData" version="1.0" byte_order="LittleEndian" header_type="UInt32">"#
+    )?;
+    // the interior cells start one cell away from the origin
+    writeln!(
+        w,
+        r#"  <ImageData WholeExtent="0 {nx} 0 {ny} 0 0" Origin="{h} {h} 0" Spacing="{h} {h} {h}">"#
+    )?;
+    writeln!(w, r#"    <FieldData>"#)?;
+    writeln!(
+        w,
+        r#"      <DataArray type="Float64" Name="TimeValue" NumberOfTuples="1" format="ascii">{}</DataArray>"#,
+        fluid.time
+    )?;
+    writeln!(w, r#"    </FieldData>"#)?;
+    writeln!(w, r#"    <Piece Extent="0 {nx} 0 {ny} 0 0">"#)?;
+    writeln!(
+        w,
+        r#"      <CellData Scalars="pressure" Vectors="velocity">"#
+    )?;
+
+    let mut offset = 0;
+    for array in &arrays {
+        let header = format!(
+            r#"        <DataArray type="Float32" Name="{}" NumberOfComponents="{}""#,
+            array.name, array.num_components
+        );
+
+        match encoding {
+            VtkEncoding::Ascii => {
+                writeln!(w, r#"{} format="ascii">"#, header)?;
+                for row in array.values.chunks(array.num_components * nx) {
+                    let row: Vec<String> = row.iter().map(|x| x.to_string()).collect();
+                    writeln!(w, "          {}", row.join(" "))?;
+                }
+                writeln!(w, "        </DataArray>")?;
+            }
+            VtkEncoding::Base64 => {
+                writeln!(w, r#"{} format="binary">"#, header)?;
+                writeln!(w, "          {}", base64(&array.binary()))?;
+                writeln!(w, "        </DataArray>")?;
+            }
+            VtkEncoding::Raw => {
+                writeln!(w, r#"{} format="appended" offset="{}"/>"#, header, offset)?;
+                offset += 4 + 4 * array.values.len();
+            }
+        }
+    }
+
+    writeln!(w, r#"      </CellData>"#)?;
+    writeln!(w, r#"    </Piece>"#)?;
+    writeln!(w, r#"  </ImageData>"#)?;
+
+    if encoding == VtkEncoding::Raw {
+        writeln!(w, r#"  <AppendedData encoding="raw">"#)?;
+        write!(w, "   _")?;
+        for array in &arrays {
+            w.write_all(&array.binary())?;
+        }
+        writeln!(w)?;
+        writeln!(w, r#"  </AppendedData>"#)?;
+    }
+
+    writeln!(w, r#"</VTKFile>"#)
+}
+
+/// A ParaView collection (`.pvd`) of time steps.
+#[derive(Default)]
+pub struct PvdCollection {
+    /// (time, file) of each step
+    datasets: Vec<(f64, String)>,
+}
+
+impl PvdCollection {
+    /// Create an empty collection
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a time step stored in `file` (relative to the `.pvd` file).
+    pub fn add(&mut self, time: f64, file: impl Into<String>) {
+        self.datasets.push((time, file.into()));
+    }
+
+    /// Write the collection file.
+    pub fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        writeln!(w, r#"<?xml version="1.0"?>"#)?;
+        writeln!(
+            w,
+            r#"<VTKFile type="Collection" version="1.0" byte_order="LittleEndian">"#
+        )?;
+        writeln!(w, r#"  <Collection>"#)?;
+        for (time, file) in &self.datasets {
+            writeln!(
+                w,
+                r#"    <DataSet timestep="{}" group="" part="0" file="{}"/>"#,
+                time, file
+            )?;
+        }
+        writeln!(w, r#"  </Collection>"#)?;
+        writeln!(w, r#"</VTKFile>"#)
+    }
+}
+
+/// A time series of `.vti` files in a directory, tied together by a `.pvd` file.
+pub struct VtkSeries {
+    dir: PathBuf,
+    prefix: String,
+    encoding: VtkEncoding,
+    collection: PvdCollection,
+}
+
+impl VtkSeries {
+    /// Create a series writing `<prefix>_<step>.vti` and `<prefix>.pvd` in `dir`.
+    pub fn new(dir: impl AsRef<Path>, prefix: &str, encoding: VtkEncoding) -> io::Result<Self> {
+        fs::create_dir_all(dir.as_ref())?;
+
+        Ok(Self {
+            dir: dir.as_ref().to_path_buf(),
+            prefix: prefix.to_string(),
+            encoding,
+            collection: PvdCollection::new(),
+        })
+    }
+
+    /// Write the current state of the simulation as a new time step.
+    ///
+    /// The `.pvd` file is rewritten so that it is usable while the run is in progress.
+    pub fn write_step(&mut self, fluid: &Fluid) -> io::Result<PathBuf> {
+        let file = format!("{}_{:06}.vti", self.prefix, fluid.step);
+        let path = self.dir.join(&file);
+
+        let mut w = io::BufWriter::new(fs::File::create(&path)?);
+        write_vti(fluid, self.encoding, &mut w)?;
+        w.flush()?;
+
+        self.collection.add(fluid.time, file);
+
+        let mut w = io::BufWriter::new(fs::File::create(
+            self.dir.join(format!("{}.pvd", self.prefix)),
+        )?);
+        self.collection.write(&mut w)?;
+        w.flush()?;
+
+        Ok(path)
+    }
+}
+
+#[wasm_bindgen]
+impl Fluid {
+    /// Export the current state as a VTK ImageData file.
+    ///
+    /// `encoding` is one of `ascii`, `base64` or `raw`.
+    pub fn export_vti(&self, encoding: &str) -> Result<Vec<u8>, JsValue> {
+        let encoding = VtkEncoding::from_str(encoding).map_err(|e| JsValue::from_str(&e))?;
+
+        let mut buf = Vec::new();
+        write_vti(self, encoding, &mut buf).expect("writing to a Vec cannot fail");
+        Ok(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Interior of 4 by 3 cells, with the pressure of the cell (i, j) at 10 i + j
+    fn fluid() -> Fluid {
+        let mut fluid = Fluid::create(0., 1., 4, 3, 0.1, 1000.);
+        let n = fluid.num_y;
+        for i in 0..fluid.num_x {
+            for j in 0..n {
+                fluid.p[i * n + j] = (10 * i + j) as f32;
+            }
+        }
+        fluid
+    }
+
+    /// Pressure of the interior cells, x varying fastest
+    fn pressure() -> Vec<f32> {
+        (1..4)
+            .flat_map(|j| (1..5).map(move |i| (10 * i + j) as f32))
+            .collect()
+    }
+
+    fn decode_base64(text: &str) -> Vec<u8> {
+        const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let sextets: Vec<u32> = text
+            .bytes()
+            .take_while(|&c| c != b'=')
+            .map(|c| ALPHABET.iter().position(|&a| a == c).unwrap() as u32)
+            .collect();
+
+        let mut out = vec![];
+        for chunk in sextets.chunks(4) {
+            let bits = chunk
+                .iter()
+                .chain(std::iter::repeat(&0))
+                .take(4)
+                .fold(0, |acc, &s| acc << 6 | s);
+            out.extend_from_slice(&bits.to_be_bytes()[1..chunk.len()]);
+        }
+        out
+    }
+
+    /// Returns the length header and the values of a binary array.
+    fn read_binary(data: &[u8]) -> (usize, Vec<f32>) {
+        let len = u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize;
+        let values = data[4..4 + len]
+            .chunks_exact(4)
+            .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+            .collect();
+        (len, values)
+    }
+
+    fn vti(encoding: VtkEncoding) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_vti(&fluid(), encoding, &mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn ascii() {
+        let text = String::from_utf8(vti(VtkEncoding::Ascii)).unwrap();
+        assert!(
+            text.contains(r#"WholeExtent="0 4 0 3 0 0" Origin="0.1 0.1 0" Spacing="0.1 0.1 0.1""#)
+        );
+
+        let start = text.find(r#"Name="pressure""#).unwrap();
+        let rows: Vec<&str> = text[start..].lines().skip(1).take(3).collect();
+        let values: Vec<f32> = rows
+            .iter()
+            .flat_map(|row| row.split_whitespace().map(|x| x.parse::<f32>().unwrap()))
+            .collect();
+        assert_eq!(values, pressure());
+    }
+
+    #[test]
+    fn base64_blocks() {
+        let text = String::from_utf8(vti(VtkEncoding::Base64)).unwrap();
+
+        let mut arrays = 0;
+        let mut lines = text.lines();
+        while let Some(line) = lines.next() {
+            if !line.contains(r#"format="binary""#) {
+                continue;
+            }
+            arrays += 1;
+
+            let components = if line.contains(r#"Name="velocity""#) {
+                3
+            } else {
+                1
+            };
+            let data = decode_base64(lines.next().unwrap().trim());
+            // a UInt32 length, then the values
+            let (len, values) = read_binary(&data);
+            assert_eq!(data.len(), 4 + len);
+            assert_eq!(values.len(), 12 * components);
+
+            if line.contains(r#"Name="pressure""#) {
+                assert_eq!(values, pressure());
+            }
+        }
+        assert_eq!(arrays, 5);
+    }
+
+    #[test]
+    fn appended_offsets() {
+        let data = vti(VtkEncoding::Raw);
+        // the appended data starts after the underscore
+        let start = data.windows(4).position(|w| w == b"   _").unwrap() + 4;
+        let header = String::from_utf8_lossy(&data[..start]);
+
+        let mut offsets = vec![];
+        for line in header
+            .lines()
+            .filter(|l| l.contains(r#"format="appended""#))
+        {
+            let offset = line.split(r#"offset=""#).nth(1).unwrap();
+            let offset: usize = offset[..offset.find('"').unwrap()].parse().unwrap();
+            offsets.push((offset, line.contains(r#"Name="pressure""#)));
+        }
+        assert_eq!(offsets.len(), 5);
+
+        let mut expected = 0;
+        for (offset, is_pressure) in offsets {
+            assert_eq!(offset, expected);
+            let (len, values) = read_binary(&data[start + offset..]);
+            if is_pressure {
+                assert_eq!(values, pressure());
+            }
+            expected += 4 + len;
+        }
+        assert_eq!(
+            &data[start + expected..],
+            b"\n  </AppendedData>\n</VTKFile>\n"
+        );
+    }
+
+    #[test]
+    fn collection() {
+        let mut pvd = PvdCollection::new();
+        pvd.add(0., "run_000000.vti");
+        pvd.add(0.5, "run_000030.vti");
+
+        let mut out = Vec::new();
+        pvd.write(&mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let datasets: Vec<&str> = text.lines().filter(|l| l.contains("<DataSet")).collect();
+        assert_eq!(
+            datasets,
+            [
+                r#"    <DataSet timestep="0" group="" part="0" file="run_000000.vti"/>"#,
+                r#"    <DataSet timestep="0.5" group="" part="0" file="run_000030.vti"/>"#,
+            ]
+        );
+    }
+}
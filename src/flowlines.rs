@@ -0,0 +1,416 @@
+//! Pathlines, streaklines and timelines
+//!
+//! In an unsteady flow the streamlines of a frame say little about where the
+//! fluid goes. These lines follow fluid particles through the simulation steps
+//! instead:
+//! - a pathline is the trajectory of a single particle,
+//! - a streakline joins the particles released from a fixed point, like dye
+//!   injected continuously,
+//! - a timeline joins particles released together along a segment, like a
+//!   material line.
+use crate::particles::{self, Integrator};
+use crate::simu::Fluid;
+use crate::streamlines::SeedLine;
+use crate::visualization::Image;
+
+/// Trajectory of a particle
+#[derive(Clone, Debug)]
+struct Pathline {
+    seed: (f32, f32),
+    /// visited positions, the last one being the current position
+    points: Vec<(f32, f32)>,
+    /// false once the particle left the fluid
+    alive: bool,
+}
+
+/// Particles released from a fixed point at every step
+#[derive(Clone, Debug)]
+struct Streakline {
+    source: (f32, f32),
+    /// oldest first; `None` for particles which left the fluid
+    points: Vec<Option<(f32, f32)>>,
+}
+
+/// Particles released together along a segment
+#[derive(Clone, Debug)]
+struct Timeline {
+    seeds: SeedLine,
+    /// in order along the line; `None` for particles which left the fluid
+    points: Vec<Option<(f32, f32)>>,
+}
+
+impl Timeline {
+    fn release(&mut self) {
+        self.points = self.seeds.seeds().map(Some).collect();
+    }
+}
+
+/// Flow lines accumulated across the simulation steps
+#[derive(Clone, Debug)]
+pub struct Flowlines {
+    pathlines: Vec<Pathline>,
+    streaklines: Vec<Streakline>,
+    timelines: Vec<Timeline>,
+    pub integrator: Integrator,
+    /// Maximum number of points of a line; the oldest points of pathlines and
+    /// streaklines are dropped first
+    pub max_points: usize,
+    /// Timelines are refined where two neighbors get farther apart than this
+    /// distance (in m, 0 => never)
+    pub refine_distance: f32,
+}
+
+impl Default for Flowlines {
+    fn default() -> Self {
+        Flowlines::new(Integrator::Rk2)
+    }
+}
+
+impl Flowlines {
+    pub fn new(integrator: Integrator) -> Self {
+        Flowlines {
+            pathlines: vec![],
+            streaklines: vec![],
+            timelines: vec![],
+            integrator,
+            max_points: 2000,
+            refine_distance: 0.,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pathlines.is_empty() && self.streaklines.is_empty() && self.timelines.is_empty()
+    }
+
+    /// Follow the particle starting at (x, y), in physical coordinates.
+    pub fn add_pathline(&mut self, x: f32, y: f32) {
+        self.pathlines.push(Pathline {
+            seed: (x, y),
+            points: vec![(x, y)],
+            alive: true,
+        });
+    }
+
+    /// Release a particle at (x, y) at every step.
+    pub fn add_streakline(&mut self, x: f32, y: f32) {
+        self.streaklines.push(Streakline {
+            source: (x, y),
+            points: vec![],
+        });
+    }
+
+    /// Release particles along a segment, once.
+    pub fn add_timeline(&mut self, seeds: SeedLine) {
+        let mut timeline = Timeline {
+            seeds,
+            points: vec![],
+        };
+        timeline.release();
+        self.timelines.push(timeline);
+    }
+
+    /// Remove all the lines.
+    pub fn clear(&mut self) {
+        self.pathlines.clear();
+        self.streaklines.clear();
+        self.timelines.clear();
+    }
+
+    /// Start all the lines again from their seeds, e.g. when the flow is reset.
+    pub fn restart(&mut self) {
+        for pathline in &mut self.pathlines {
+            pathline.points = vec![pathline.seed];
+            pathline.alive = true;
+        }
+        for streakline in &mut self.streaklines {
+            streakline.points.clear();
+        }
+        for timeline in &mut self.timelines {
+            timeline.release();
+        }
+    }
+
+    /// Returns the lines as polylines in physical coordinates: pathlines,
+    /// then streaklines, then timelines.
+    ///
+    /// A line is split where particles left the fluid.
+    pub fn polylines(&self) -> Vec<Vec<(f32, f32)>> {
+        let mut lines: Vec<Vec<(f32, f32)>> =
+            self.pathlines.iter().map(|p| p.points.clone()).collect();
+
+        for points in self
+            .streaklines
+            .iter()
+            .map(|s| &s.points)
+            .chain(self.timelines.iter().map(|t| &t.points))
+        {
+            lines.extend(split(points));
+        }
+
+        lines
+    }
+
+    /// Advance all the particles by `dt`.
+    pub fn step(&mut self, fluid: &Fluid, dt: f32) {
+        let integrator = self.integrator;
+        let max_points = self.max_points.max(2);
+        let advect = |p: (f32, f32)| particles::advect(fluid, p, dt, integrator);
+
+        for pathline in &mut self.pathlines {
+            if !pathline.alive {
+                continue;
+            }
+
+            let last = *pathline.points.last().expect("a pathline has its seed");
+            match advect(last) {
+                Some(p) => pathline.points.push(p),
+                None => pathline.alive = false,
+            }
+            if pathline.points.len() > max_points {
+                let excess = pathline.points.len() - max_points;
+                pathline.points.drain(..excess);
+            }
+        }
+
+        for streakline in &mut self.streaklines {
+            for point in &mut streakline.points {
+                *point = point.and_then(advect);
+            }
+            streakline.points.push(Some(streakline.source));
+
+            // the oldest particles, and those which left, are dropped first
+            let first = streakline.points.iter().position(Option::is_some);
+            streakline.points.drain(..first.unwrap_or(0));
+            if streakline.points.len() > max_points {
+                let excess = streakline.points.len() - max_points;
+                streakline.points.drain(..excess);
+            }
+        }
+
+        let refine = self.refine_distance;
+        for timeline in &mut self.timelines {
+            for point in &mut timeline.points {
+                *point = point.and_then(advect);
+            }
+            if refine > 0. {
+                refine_line(fluid, &mut timeline.points, refine, max_points);
+            }
+        }
+    }
+
+    /// Draw the pathlines in magenta, the streaklines in yellow and the
+    /// timelines in cyan, over a dark shadow.
+    pub(crate) fn draw(&self, fluid: &Fluid, image: &mut Image) {
+        const PATHLINE: [u8; 4] = [255, 0, 255, 255];
+        const STREAKLINE: [u8; 4] = [255, 230, 0, 255];
+        const TIMELINE: [u8; 4] = [0, 230, 255, 255];
+
+        let pathlines = self.pathlines.iter().map(|p| (p.points.clone(), PATHLINE));
+        let streaklines = self
+            .streaklines
+            .iter()
+            .flat_map(|s| split(&s.points))
+            .map(|line| (line, STREAKLINE));
+        let timelines = self
+            .timelines
+            .iter()
+            .flat_map(|t| split(&t.points))
+            .map(|line| (line, TIMELINE));
+
+        for (line, color) in pathlines.chain(streaklines).chain(timelines) {
+            for segment in line.windows(2) {
+                let a = fluid.to_image(image, segment[0]);
+                let b = fluid.to_image(image, segment[1]);
+
+                let shadow = |(x, y): (f32, f32)| (x + 1., y + 1.);
+                image.draw_line(shadow(a), shadow(b), [0, 0, 0, 120]);
+                image.draw_line(a, b, color);
+            }
+        }
+    }
+}
+
+/// Split a line at the particles which left the fluid.
+fn split(points: &[Option<(f32, f32)>]) -> Vec<Vec<(f32, f32)>> {
+    points
+        .split(Option::is_none)
+        .filter(|part| part.len() >= 2)
+        .map(|part| part.iter().flatten().copied().collect())
+        .collect()
+}
+
+/// Insert midpoints between neighbors farther apart than `distance`, keeping
+/// at most `max_points` points.
+///
+/// Midpoints falling in an obstacle are skipped: the neighbors went around it.
+fn refine_line(
+    fluid: &Fluid,
+    points: &mut Vec<Option<(f32, f32)>>,
+    distance: f32,
+    max_points: usize,
+) {
+    let mut refined = Vec::with_capacity(points.len());
+
+    for (k, &point) in points.iter().enumerate() {
+        if let (Some(&Some(a)), Some(b)) = (k.checked_sub(1).map(|k| &points[k]), point) {
+            let d = ((b.0 - a.0).powi(2) + (b.1 - a.1).powi(2)).sqrt();
+            let middle = (0.5 * (a.0 + b.0), 0.5 * (a.1 + b.1));
+            if d > distance
+                && points.len() + refined.len() - k < max_points
+                && fluid.is_fluid(middle.0, middle.1)
+            {
+                refined.push(Some(middle));
+            }
+        }
+        refined.push(point);
+    }
+
+    *points = refined;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A channel of 40 by 20 cells of 1 cm, open on the right, with a uniform
+    /// horizontal velocity of 0.1 m/s and an obstacle in the cell (20, 10).
+    fn channel() -> Fluid {
+        let mut fluid = Fluid::create(0., 0.1, 40, 20, 0.01, 1000.);
+        let n = fluid.num_y;
+        for i in 0..fluid.num_x {
+            for j in 0..n {
+                let wall = i == 0 || j == 0 || j == n - 1 || (i, j) == (20, 10);
+                fluid.s[i * n + j] = if wall { 0. } else { 1. };
+            }
+        }
+        fluid.u.fill(0.1);
+        fluid
+    }
+
+    fn close(a: (f32, f32), b: (f32, f32)) -> bool {
+        (a.0 - b.0).abs() < 1e-5 && (a.1 - b.1).abs() < 1e-5
+    }
+
+    #[test]
+    fn split_where_particles_left() {
+        let p = |x: f32| Some((x, 0.));
+        let points = [p(1.), p(2.), None, p(3.), None, None, p(4.), p(5.), p(6.)];
+        assert_eq!(
+            split(&points),
+            [vec![(1., 0.), (2., 0.)], vec![(4., 0.), (5., 0.), (6., 0.)]]
+        );
+        assert!(split(&[None, p(1.), None]).is_empty());
+    }
+
+    #[test]
+    fn streaklines_are_trimmed() {
+        let fluid = channel();
+        let mut flowlines = Flowlines::new(Integrator::Rk2);
+        flowlines.max_points = 3;
+        flowlines.add_streakline(0.05, 0.05);
+        for _ in 0..5 {
+            flowlines.step(&fluid, 0.1);
+        }
+
+        // the newest particles, oldest first
+        let lines = flowlines.polylines();
+        assert_eq!(lines.len(), 1);
+        let expected = [(0.07, 0.05), (0.06, 0.05), (0.05, 0.05)];
+        assert!(lines[0].iter().zip(expected).all(|(&a, b)| close(a, b)));
+
+        // near the outlet, the particles which left are dropped
+        let mut flowlines = Flowlines::default();
+        flowlines.add_streakline(0.385, 0.05);
+        for _ in 0..10 {
+            flowlines.step(&fluid, 0.1);
+            let points = &flowlines.streaklines[0].points;
+            assert!(points.len() <= 3 && points.iter().all(Option::is_some));
+        }
+    }
+
+    #[test]
+    fn timelines_split_around_obstacles() {
+        let fluid = channel();
+        let mut flowlines = Flowlines::default();
+        // the sixth particle runs into the obstacle
+        flowlines.add_timeline(SeedLine {
+            from: (0.195, 0.055),
+            to: (0.195, 0.155),
+            count: 11,
+        });
+        flowlines.step(&fluid, 0.1);
+
+        let lines = flowlines.polylines();
+        assert_eq!(lines.iter().map(Vec::len).collect::<Vec<_>>(), [5, 5]);
+        assert!(close(lines[0][0], (0.205, 0.055)));
+        assert!(close(lines[1][0], (0.205, 0.115)));
+    }
+
+    #[test]
+    fn refine_line_keeps_max_points() {
+        let fluid = channel();
+        let line = vec![Some((0.05, 0.05)), Some((0.15, 0.05)), Some((0.25, 0.05))];
+
+        // a midpoint in each gap, as long as there is room for it
+        for (max_points, len) in [(2, 3), (3, 3), (4, 4), (5, 5), (6, 5)] {
+            let mut points = line.clone();
+            refine_line(&fluid, &mut points, 0.03, max_points);
+            assert_eq!(points.len(), len, "at most {} points", max_points);
+            if len == 4 {
+                assert!(close(points[1].unwrap(), (0.1, 0.05)));
+            }
+        }
+
+        // none next to a particle which left, nor in the obstacle
+        let mut points = vec![
+            Some((0.05, 0.05)),
+            None,
+            Some((0.15, 0.105)),
+            Some((0.26, 0.105)),
+        ];
+        refine_line(&fluid, &mut points, 0.03, 100);
+        assert_eq!(points.len(), 4);
+
+        // nor between close neighbors
+        let mut points = line.clone();
+        refine_line(&fluid, &mut points, 0.2, 100);
+        assert_eq!(points, line);
+    }
+
+    #[test]
+    fn restart_from_the_seeds() {
+        let fluid = channel();
+        let mut flowlines = Flowlines::new(Integrator::Rk2);
+        flowlines.max_points = 4;
+        flowlines.add_pathline(0.355, 0.05);
+        flowlines.add_streakline(0.05, 0.15);
+        flowlines.add_timeline(SeedLine {
+            from: (0.05, 0.02),
+            to: (0.05, 0.08),
+            count: 4,
+        });
+        for _ in 0..8 {
+            flowlines.step(&fluid, 0.1);
+        }
+
+        // the pathline left through the outlet, keeping its last points
+        let pathline = &flowlines.pathlines[0];
+        assert!(!pathline.alive);
+        assert_eq!(pathline.points.len(), 4);
+        assert!(close(pathline.points[3], (0.405, 0.05)));
+
+        flowlines.restart();
+        let lines = flowlines.polylines();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], [(0.355, 0.05)]);
+        assert_eq!(
+            lines[1],
+            [(0.05, 0.02), (0.05, 0.04), (0.05, 0.06), (0.05, 0.08)]
+        );
+        assert!(flowlines.pathlines[0].alive);
+        assert!(flowlines.streaklines[0].points.is_empty());
+
+        flowlines.step(&fluid, 0.1);
+        assert!(close(flowlines.pathlines[0].points[1], (0.365, 0.05)));
+    }
+}
@@ -0,0 +1,579 @@
+//! Column kernels of the red-black solver and of the advection
+//!
+//! A column of the grid is contiguous in memory, so the kernels process the
+//! cells of a column a few at a time: they are written once for [Lanes] of any
+//! width, and run with the widest available:
+//! - with the `simd` feature on x86_64: AVX (8 lanes) if the CPU has it, SSE2
+//!   (4 lanes) otherwise
+//! - with the `simd` feature on wasm32, when built with
+//!   `RUSTFLAGS="-C target-feature=+simd128"`: simd128 (4 lanes)
+//! - otherwise: `f32`, one cell at a time
+//!
+//! Each lane does the same operations, in the same order, as the scalar code:
+//! the results do not depend on the width.
+use std::ops::{Add, Mul, Range, Sub};
+
+use crate::simu::Fluid;
+
+#[cfg(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128"))]
+mod wasm;
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+mod x86;
+
+/// Largest number of lanes
+const MAX_LANES: usize = 8;
+
+/// A few `f32` processed at once
+pub(crate) trait Lanes:
+    Copy + Add<Output = Self> + Sub<Output = Self> + Mul<Output = Self>
+{
+    const LANES: usize;
+
+    fn splat(x: f32) -> Self;
+    /// Load the values `j..j + LANES` of `data`.
+    fn load(data: &[f32], j: usize) -> Self;
+    /// Store the lanes into the values `j..j + LANES` of `data`.
+    fn store(self, data: &mut [f32], j: usize);
+
+    fn min(self, other: Self) -> Self;
+    fn max(self, other: Self) -> Self;
+    fn abs(self) -> Self;
+    /// Round down; only valid for non-negative values.
+    fn floor(self) -> Self;
+    /// `a` where `self` is not 0, `b` elsewhere
+    fn select(self, a: Self, b: Self) -> Self;
+
+    /// Lanes with the values `f(0)`, `f(1)`...
+    #[inline(always)]
+    fn from_fn(f: impl Fn(usize) -> f32) -> Self {
+        let mut values = [0.; MAX_LANES];
+        for (k, value) in values[..Self::LANES].iter_mut().enumerate() {
+            *value = f(k);
+        }
+        Self::load(&values, 0)
+    }
+
+    /// The lanes, followed by zeros
+    #[inline(always)]
+    fn to_array(self) -> [f32; MAX_LANES] {
+        let mut values = [0.; MAX_LANES];
+        self.store(&mut values, 0);
+        values
+    }
+}
+
+impl Lanes for f32 {
+    const LANES: usize = 1;
+
+    #[inline(always)]
+    fn splat(x: f32) -> Self {
+        x
+    }
+
+    #[inline(always)]
+    fn load(data: &[f32], j: usize) -> Self {
+        data[j]
+    }
+
+    #[inline(always)]
+    fn store(self, data: &mut [f32], j: usize) {
+        data[j] = self;
+    }
+
+    #[inline(always)]
+    fn min(self, other: Self) -> Self {
+        f32::min(self, other)
+    }
+
+    #[inline(always)]
+    fn max(self, other: Self) -> Self {
+        f32::max(self, other)
+    }
+
+    #[inline(always)]
+    fn abs(self) -> Self {
+        f32::abs(self)
+    }
+
+    #[inline(always)]
+    fn floor(self) -> Self {
+        f32::floor(self)
+    }
+
+    #[inline(always)]
+    fn select(self, a: Self, b: Self) -> Self {
+        if self != 0. {
+            a
+        } else {
+            b
+        }
+    }
+
+    // without going through a buffer, which the size-optimized builds keep
+
+    #[inline(always)]
+    fn from_fn(f: impl Fn(usize) -> f32) -> Self {
+        f(0)
+    }
+
+    #[inline(always)]
+    fn to_array(self) -> [f32; MAX_LANES] {
+        [self, 0., 0., 0., 0., 0., 0., 0.]
+    }
+}
+
+/// A computation on the cells of a column
+pub(crate) trait Kernel {
+    /// Cells of the column to process
+    fn range(&self) -> Range<usize>;
+
+    /// Process the cells `j..j + L::LANES`.
+    fn at<L: Lanes>(&mut self, j: usize);
+
+    #[inline(always)]
+    fn run<L: Lanes>(&mut self) {
+        let Range { start, end } = self.range();
+
+        let mut j = start;
+        while j + L::LANES <= end {
+            self.at::<L>(j);
+            j += L::LANES;
+        }
+        // the rest one by one
+        while j < end {
+            self.at::<f32>(j);
+            j += 1;
+        }
+    }
+}
+
+/// Run `kernel` with the widest lanes available.
+pub(crate) fn run<K: Kernel>(kernel: &mut K) {
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    if is_x86_feature_detected!("avx") {
+        // SAFETY: the CPU has AVX
+        unsafe { x86::run_avx(kernel) };
+    } else {
+        kernel.run::<x86::F32x4>();
+    }
+
+    #[cfg(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128"))]
+    kernel.run::<wasm::F32x4>();
+
+    #[cfg(not(all(
+        feature = "simd",
+        any(
+            target_arch = "x86_64",
+            all(target_arch = "wasm32", target_feature = "simd128")
+        )
+    )))]
+    kernel.run::<f32>();
+}
+
+/// Relaxation of the cells of one color in a column
+pub(crate) struct Relax<'a> {
+    /// pressure of the column
+    pub p: &'a mut [f32],
+    /// correction of the cells of the column, 0 for the cells not relaxed
+    pub correction: &'a mut [f32],
+    /// x-component of the velocity in the column and in the next one
+    pub u: &'a [f32],
+    pub u_next: &'a [f32],
+    /// y-component of the velocity in the column
+    pub v: &'a [f32],
+    /// `-over_relaxation / number of fluid neighbors` for the cells relaxed, 0 elsewhere
+    pub weight: &'a [f32],
+    /// density * h / dt
+    pub cp: f32,
+    /// largest divergence of the cells relaxed, if tracked
+    pub max_div: Option<f32>,
+}
+
+impl Kernel for Relax<'_> {
+    fn range(&self) -> Range<usize> {
+        1..self.p.len() - 1
+    }
+
+    #[inline(always)]
+    fn at<L: Lanes>(&mut self, j: usize) {
+        // divergence of velocity in staggered grid
+        let div = L::load(self.u_next, j) - L::load(self.u, j) + L::load(self.v, j + 1)
+            - L::load(self.v, j);
+
+        let weight = L::load(self.weight, j);
+        let correction = div * weight;
+
+        (L::load(self.p, j) + L::splat(self.cp) * correction).store(self.p, j);
+        correction.store(self.correction, j);
+
+        if let Some(max_div) = &mut self.max_div {
+            let div = weight.select(div.abs(), L::splat(0.)).to_array();
+            *max_div = div[..L::LANES].iter().fold(*max_div, |a, &b| a.max(b));
+        }
+    }
+}
+
+/// Correction of the x-component of the velocity in a column, from the
+/// corrections of the cells on each side
+pub(crate) struct CorrectU<'a> {
+    pub u: &'a mut [f32],
+    /// corrections and obstacle fields of the cells to the left and in the column
+    pub correction_left: &'a [f32],
+    pub correction: &'a [f32],
+    pub s_left: &'a [f32],
+    pub s: &'a [f32],
+}
+
+impl Kernel for CorrectU<'_> {
+    fn range(&self) -> Range<usize> {
+        1..self.u.len() - 1
+    }
+
+    #[inline(always)]
+    fn at<L: Lanes>(&mut self, j: usize) {
+        let u = L::load(self.u, j)
+            + (L::load(self.correction_left, j) * L::load(self.s, j)
+                - L::load(self.correction, j) * L::load(self.s_left, j));
+        u.store(self.u, j);
+    }
+}
+
+/// Correction of the y-component of the velocity in a column, from the
+/// corrections of the cells below and above
+pub(crate) struct CorrectV<'a> {
+    pub v: &'a mut [f32],
+    /// corrections and obstacle field of the cells of the column
+    pub correction: &'a [f32],
+    pub s: &'a [f32],
+}
+
+impl Kernel for CorrectV<'_> {
+    fn range(&self) -> Range<usize> {
+        1..self.v.len()
+    }
+
+    #[inline(always)]
+    fn at<L: Lanes>(&mut self, j: usize) {
+        let v = L::load(self.v, j)
+            + (L::load(self.correction, j - 1) * L::load(self.s, j)
+                - L::load(self.correction, j) * L::load(self.s, j - 1));
+        v.store(self.v, j);
+    }
+}
+
+/// Bilinear interpolation of `field` at (x, y), offset by (dx, dy) in the
+/// cells, as in `Fluid::sample_field`
+#[inline(always)]
+fn sample<L: Lanes>(fluid: &Fluid, field: &[f32], (dx, dy): (f32, f32), x: L, y: L) -> L {
+    let n = fluid.num_y;
+    let h = fluid.h;
+    let h1 = 1.0 / h;
+
+    let x = x.min(L::splat(fluid.num_x as f32 * h)).max(L::splat(h));
+    let y = y.min(L::splat(fluid.num_y as f32 * h)).max(L::splat(h));
+
+    let (dx, dy) = (L::splat(dx), L::splat(dy));
+    let (h, h1) = (L::splat(h), L::splat(h1));
+    let x_max = L::splat(fluid.num_x as f32 - 1.);
+    let y_max = L::splat(fluid.num_y as f32 - 1.);
+    let one = L::splat(1.);
+
+    let x0 = ((x - dx) * h1).floor().min(x_max);
+    let tx = ((x - dx) - x0 * h) * h1;
+    let x1 = (x0 + one).min(x_max);
+
+    let y0 = ((y - dy) * h1).floor().min(y_max);
+    let ty = ((y - dy) - y0 * h) * h1;
+    let y1 = (y0 + one).min(y_max);
+
+    let sx = one - tx;
+    let sy = one - ty;
+
+    // the values at the corners are gathered one by one
+    let (x0, x1, y0, y1) = (x0.to_array(), x1.to_array(), y0.to_array(), y1.to_array());
+    let corner = |x: &[f32; MAX_LANES], y: &[f32; MAX_LANES]| {
+        L::from_fn(|k| field[x[k] as usize * n + y[k] as usize])
+    };
+
+    sx * sy * corner(&x0, &y0)
+        + tx * sy * corner(&x1, &y0)
+        + tx * ty * corner(&x1, &y1)
+        + sx * ty * corner(&x0, &y1)
+}
+
+/// Advection of the x-component of the velocity in column `i`
+pub(crate) struct AdvectU<'a> {
+    pub fluid: &'a Fluid,
+    pub i: usize,
+    pub dt: f32,
+    /// advected x-component of the column
+    pub new_u: &'a mut [f32],
+}
+
+impl Kernel for AdvectU<'_> {
+    fn range(&self) -> Range<usize> {
+        1..self.fluid.num_y - 1
+    }
+
+    #[inline(always)]
+    fn at<L: Lanes>(&mut self, j: usize) {
+        let fluid = self.fluid;
+        let (n, i, h) = (fluid.num_y, self.i, fluid.h);
+        let h2 = 0.5 * h;
+
+        let x = L::splat(i as f32 * h);
+        let y = L::from_fn(|k| (j + k) as f32 * h + h2);
+
+        let u = L::load(&fluid.u, i * n + j);
+        let v = (L::load(&fluid.v, (i - 1) * n + j)
+            + L::load(&fluid.v, i * n + j)
+            + L::load(&fluid.v, (i - 1) * n + j + 1)
+            + L::load(&fluid.v, i * n + j + 1))
+            * L::splat(0.25);
+
+        // backward step
+        let dt = L::splat(self.dt);
+        let x = x - dt * u;
+        let y = y - dt * v;
+
+        let u = sample(fluid, &fluid.u, (0., h2), x, y);
+
+        // only where neither the cell nor the one to the left is an obstacle
+        let old = L::load(self.new_u, j);
+        let s = L::load(&fluid.s, i * n + j);
+        let s_left = L::load(&fluid.s, (i - 1) * n + j);
+        s.select(s_left.select(u, old), old).store(self.new_u, j);
+    }
+}
+
+/// Advection of the y-component of the velocity in column `i`
+pub(crate) struct AdvectV<'a> {
+    pub fluid: &'a Fluid,
+    pub i: usize,
+    pub dt: f32,
+    /// advected y-component of the column
+    pub new_v: &'a mut [f32],
+}
+
+impl Kernel for AdvectV<'_> {
+    fn range(&self) -> Range<usize> {
+        1..self.fluid.num_y
+    }
+
+    #[inline(always)]
+    fn at<L: Lanes>(&mut self, j: usize) {
+        let fluid = self.fluid;
+        let (n, i, h) = (fluid.num_y, self.i, fluid.h);
+        let h2 = 0.5 * h;
+
+        let x = L::splat(i as f32 * h + h2);
+        let y = L::from_fn(|k| (j + k) as f32 * h);
+
+        let u = (L::load(&fluid.u, i * n + j - 1)
+            + L::load(&fluid.u, i * n + j)
+            + L::load(&fluid.u, (i + 1) * n + j - 1)
+            + L::load(&fluid.u, (i + 1) * n + j))
+            * L::splat(0.25);
+        let v = L::load(&fluid.v, i * n + j);
+
+        // backward step
+        let dt = L::splat(self.dt);
+        let x = x - dt * u;
+        let y = y - dt * v;
+
+        let v = sample(fluid, &fluid.v, (h2, 0.), x, y);
+
+        // only where neither the cell nor the one below is an obstacle
+        let old = L::load(self.new_v, j);
+        let s = L::load(&fluid.s, i * n + j);
+        let s_below = L::load(&fluid.s, i * n + j - 1);
+        s.select(s_below.select(v, old), old).store(self.new_v, j);
+    }
+}
+
+/// Advection of the smoke in column `i`
+pub(crate) struct AdvectSmoke<'a> {
+    pub fluid: &'a Fluid,
+    pub i: usize,
+    pub dt: f32,
+    /// advected smoke of the column
+    pub new_m: &'a mut [f32],
+}
+
+impl Kernel for AdvectSmoke<'_> {
+    fn range(&self) -> Range<usize> {
+        1..self.fluid.num_y - 1
+    }
+
+    #[inline(always)]
+    fn at<L: Lanes>(&mut self, j: usize) {
+        let fluid = self.fluid;
+        let (n, i, h) = (fluid.num_y, self.i, fluid.h);
+        // as in `Fluid::advect_smoke`
+        let h2 = 1.0 / (2.0 * h);
+
+        let half = L::splat(0.5);
+        let u = (L::load(&fluid.u, i * n + j) + L::load(&fluid.u, (i + 1) * n + j)) * half;
+        let v = (L::load(&fluid.v, i * n + j) + L::load(&fluid.v, i * n + j + 1)) * half;
+
+        let dt = L::splat(self.dt);
+        let x = L::splat(i as f32 * h + h2) - dt * u;
+        let y = L::from_fn(|k| (j + k) as f32 * h + h2) - dt * v;
+
+        let m = sample(fluid, &fluid.m, (0.5 * h, 0.5 * h), x, y);
+
+        let old = L::load(self.new_m, j);
+        L::load(&fluid.s, i * n + j)
+            .select(m, old)
+            .store(self.new_m, j);
+    }
+}
+
+#[cfg(all(test, feature = "simd", target_arch = "x86_64"))]
+mod tests {
+    use super::*;
+    use crate::simu::apply_scenario;
+
+    #[derive(Clone, Copy, Debug)]
+    enum Width {
+        Scalar,
+        Sse2,
+        Avx,
+    }
+
+    fn run_with<K: Kernel>(kernel: &mut K, width: Width) {
+        match width {
+            Width::Scalar => kernel.run::<f32>(),
+            Width::Sse2 => kernel.run::<x86::F32x4>(),
+            // SAFETY: only tested when the CPU has AVX
+            Width::Avx => unsafe { x86::run_avx(kernel) },
+        }
+    }
+
+    fn bits(field: &[f32]) -> Vec<u32> {
+        field.iter().map(|x| x.to_bits()).collect()
+    }
+
+    /// A flow past an obstacle, with a number of cells per column that is
+    /// not a multiple of the lanes
+    fn fluid() -> Fluid {
+        let mut fluid = Fluid::create(0., 1.5, 40, 29, 0.02, 1000.);
+        apply_scenario(&mut fluid, "cylindrical", 1.5);
+        for _ in 0..5 {
+            fluid.simulate(1. / 60., 20, 1.9);
+        }
+        fluid
+    }
+
+    /// The outputs of every kernel on every column, as bits
+    fn outputs(fluid: &Fluid, width: Width) -> Vec<Vec<u32>> {
+        let (num_x, n) = (fluid.num_x, fluid.num_y);
+        let s = &fluid.s;
+        let mut outputs = vec![];
+
+        // one color of the checkerboard
+        let mut weight = vec![0.; num_x * n];
+        for i in 1..num_x - 1 {
+            for j in 1..n - 1 {
+                if (i + j) % 2 == 0 && s[i * n + j] != 0. {
+                    weight[i * n + j] = -1.9 / 4.;
+                }
+            }
+        }
+
+        let mut p = fluid.p.clone();
+        let mut corrections = vec![0.; num_x * n];
+        let mut max_div = vec![];
+        for i in 1..num_x - 1 {
+            let mut relax = Relax {
+                p: &mut p[i * n..(i + 1) * n],
+                correction: &mut corrections[i * n..(i + 1) * n],
+                u: &fluid.u[i * n..(i + 1) * n],
+                u_next: &fluid.u[(i + 1) * n..(i + 2) * n],
+                v: &fluid.v[i * n..(i + 1) * n],
+                weight: &weight[i * n..(i + 1) * n],
+                cp: 3.,
+                max_div: Some(0.),
+            };
+            run_with(&mut relax, width);
+            max_div.push(relax.max_div.unwrap());
+        }
+        outputs.extend([bits(&p), bits(&corrections), bits(&max_div)]);
+
+        let mut u = fluid.u.clone();
+        let mut v = fluid.v.clone();
+        for i in 1..num_x - 1 {
+            run_with(
+                &mut CorrectU {
+                    u: &mut u[i * n..(i + 1) * n],
+                    correction_left: &corrections[(i - 1) * n..i * n],
+                    correction: &corrections[i * n..(i + 1) * n],
+                    s_left: &s[(i - 1) * n..i * n],
+                    s: &s[i * n..(i + 1) * n],
+                },
+                width,
+            );
+            run_with(
+                &mut CorrectV {
+                    v: &mut v[i * n..(i + 1) * n],
+                    correction: &corrections[i * n..(i + 1) * n],
+                    s: &s[i * n..(i + 1) * n],
+                },
+                width,
+            );
+        }
+        outputs.extend([bits(&u), bits(&v)]);
+
+        let (mut new_u, mut new_v, mut new_m) = (u.clone(), v.clone(), fluid.m.clone());
+        for i in 1..num_x - 1 {
+            let dt = 1. / 30.;
+            let column = i * n..(i + 1) * n;
+            run_with(
+                &mut AdvectU {
+                    fluid,
+                    i,
+                    dt,
+                    new_u: &mut new_u[column.clone()],
+                },
+                width,
+            );
+            run_with(
+                &mut AdvectV {
+                    fluid,
+                    i,
+                    dt,
+                    new_v: &mut new_v[column.clone()],
+                },
+                width,
+            );
+            run_with(
+                &mut AdvectSmoke {
+                    fluid,
+                    i,
+                    dt,
+                    new_m: &mut new_m[column],
+                },
+                width,
+            );
+        }
+        outputs.extend([bits(&new_u), bits(&new_v), bits(&new_m)]);
+
+        outputs
+    }
+
+    #[test]
+    fn independent_of_lane_width() {
+        let fluid = fluid();
+        let reference = outputs(&fluid, Width::Scalar);
+
+        let mut widths = vec![Width::Sse2];
+        if is_x86_feature_detected!("avx") {
+            widths.push(Width::Avx);
+        }
+        for width in widths {
+            for (k, (a, b)) in outputs(&fluid, width).iter().zip(&reference).enumerate() {
+                assert_eq!(a, b, "output {} with {:?}", k, width);
+            }
+        }
+    }
+}
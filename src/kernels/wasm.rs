@@ -0,0 +1,87 @@
+//! simd128 lanes
+use std::arch::wasm32::*;
+use std::ops::{Add, Mul, Sub};
+
+use super::Lanes;
+
+/// 4 lanes with simd128, when the module is built for it
+#[derive(Clone, Copy)]
+pub(super) struct F32x4(v128);
+
+impl Add for F32x4 {
+    type Output = Self;
+
+    #[inline(always)]
+    fn add(self, other: Self) -> Self {
+        Self(f32x4_add(self.0, other.0))
+    }
+}
+
+impl Sub for F32x4 {
+    type Output = Self;
+
+    #[inline(always)]
+    fn sub(self, other: Self) -> Self {
+        Self(f32x4_sub(self.0, other.0))
+    }
+}
+
+impl Mul for F32x4 {
+    type Output = Self;
+
+    #[inline(always)]
+    fn mul(self, other: Self) -> Self {
+        Self(f32x4_mul(self.0, other.0))
+    }
+}
+
+impl Lanes for F32x4 {
+    const LANES: usize = 4;
+
+    #[inline(always)]
+    fn splat(x: f32) -> Self {
+        Self(f32x4_splat(x))
+    }
+
+    #[inline(always)]
+    fn load(data: &[f32], j: usize) -> Self {
+        let data = &data[j..j + 4];
+        // SAFETY: `data` has 4 values, the load is unaligned
+        Self(unsafe { v128_load(data.as_ptr() as *const v128) })
+    }
+
+    #[inline(always)]
+    fn store(self, data: &mut [f32], j: usize) {
+        let data = &mut data[j..j + 4];
+        // SAFETY: `data` has 4 values, the store is unaligned
+        unsafe { v128_store(data.as_mut_ptr() as *mut v128, self.0) }
+    }
+
+    // `other` if either is NaN, as `minps` and `f32::min` for the values clamped
+
+    #[inline(always)]
+    fn min(self, other: Self) -> Self {
+        Self(f32x4_pmin(other.0, self.0))
+    }
+
+    #[inline(always)]
+    fn max(self, other: Self) -> Self {
+        Self(f32x4_pmax(other.0, self.0))
+    }
+
+    #[inline(always)]
+    fn abs(self) -> Self {
+        Self(f32x4_abs(self.0))
+    }
+
+    #[inline(always)]
+    fn floor(self) -> Self {
+        Self(f32x4_floor(self.0))
+    }
+
+    #[inline(always)]
+    fn select(self, a: Self, b: Self) -> Self {
+        let mask = f32x4_ne(self.0, f32x4_splat(0.));
+        Self(v128_bitselect(a.0, b.0, mask))
+    }
+}
@@ -0,0 +1,199 @@
+//! SSE2 and AVX lanes
+use std::arch::x86_64::*;
+use std::ops::{Add, Mul, Sub};
+
+use super::{Kernel, Lanes};
+
+/// 4 lanes with SSE2
+///
+/// SSE2 is part of x86_64: the intrinsics can always be called.
+#[derive(Clone, Copy)]
+pub(super) struct F32x4(__m128);
+
+impl Add for F32x4 {
+    type Output = Self;
+
+    #[inline(always)]
+    fn add(self, other: Self) -> Self {
+        // SAFETY: see [F32x4]
+        Self(unsafe { _mm_add_ps(self.0, other.0) })
+    }
+}
+
+impl Sub for F32x4 {
+    type Output = Self;
+
+    #[inline(always)]
+    fn sub(self, other: Self) -> Self {
+        // SAFETY: see [F32x4]
+        Self(unsafe { _mm_sub_ps(self.0, other.0) })
+    }
+}
+
+impl Mul for F32x4 {
+    type Output = Self;
+
+    #[inline(always)]
+    fn mul(self, other: Self) -> Self {
+        // SAFETY: see [F32x4]
+        Self(unsafe { _mm_mul_ps(self.0, other.0) })
+    }
+}
+
+impl Lanes for F32x4 {
+    const LANES: usize = 4;
+
+    #[inline(always)]
+    fn splat(x: f32) -> Self {
+        // SAFETY: see [F32x4]
+        Self(unsafe { _mm_set1_ps(x) })
+    }
+
+    #[inline(always)]
+    fn load(data: &[f32], j: usize) -> Self {
+        let data = &data[j..j + 4];
+        // SAFETY: `data` has 4 values, and see [F32x4]
+        Self(unsafe { _mm_loadu_ps(data.as_ptr()) })
+    }
+
+    #[inline(always)]
+    fn store(self, data: &mut [f32], j: usize) {
+        let data = &mut data[j..j + 4];
+        // SAFETY: `data` has 4 values, and see [F32x4]
+        unsafe { _mm_storeu_ps(data.as_mut_ptr(), self.0) }
+    }
+
+    #[inline(always)]
+    fn min(self, other: Self) -> Self {
+        // SAFETY: see [F32x4]
+        Self(unsafe { _mm_min_ps(self.0, other.0) })
+    }
+
+    #[inline(always)]
+    fn max(self, other: Self) -> Self {
+        // SAFETY: see [F32x4]
+        Self(unsafe { _mm_max_ps(self.0, other.0) })
+    }
+
+    #[inline(always)]
+    fn abs(self) -> Self {
+        // SAFETY: see [F32x4]
+        Self(unsafe { _mm_andnot_ps(_mm_set1_ps(-0.), self.0) })
+    }
+
+    #[inline(always)]
+    fn floor(self) -> Self {
+        // truncation, as SSE2 has no rounding
+        // SAFETY: see [F32x4]
+        Self(unsafe { _mm_cvtepi32_ps(_mm_cvttps_epi32(self.0)) })
+    }
+
+    #[inline(always)]
+    fn select(self, a: Self, b: Self) -> Self {
+        // SAFETY: see [F32x4]
+        unsafe {
+            let mask = _mm_cmpneq_ps(self.0, _mm_setzero_ps());
+            Self(_mm_or_ps(_mm_and_ps(mask, a.0), _mm_andnot_ps(mask, b.0)))
+        }
+    }
+}
+
+/// 8 lanes with AVX
+///
+/// Only used by [run_avx]: the intrinsics are inlined in a function compiled
+/// for AVX, called once the CPU is known to have it.
+#[derive(Clone, Copy)]
+pub(super) struct F32x8(__m256);
+
+impl Add for F32x8 {
+    type Output = Self;
+
+    #[inline(always)]
+    fn add(self, other: Self) -> Self {
+        // SAFETY: see [F32x8]
+        Self(unsafe { _mm256_add_ps(self.0, other.0) })
+    }
+}
+
+impl Sub for F32x8 {
+    type Output = Self;
+
+    #[inline(always)]
+    fn sub(self, other: Self) -> Self {
+        // SAFETY: see [F32x8]
+        Self(unsafe { _mm256_sub_ps(self.0, other.0) })
+    }
+}
+
+impl Mul for F32x8 {
+    type Output = Self;
+
+    #[inline(always)]
+    fn mul(self, other: Self) -> Self {
+        // SAFETY: see [F32x8]
+        Self(unsafe { _mm256_mul_ps(self.0, other.0) })
+    }
+}
+
+impl Lanes for F32x8 {
+    const LANES: usize = 8;
+
+    #[inline(always)]
+    fn splat(x: f32) -> Self {
+        // SAFETY: see [F32x8]
+        Self(unsafe { _mm256_set1_ps(x) })
+    }
+
+    #[inline(always)]
+    fn load(data: &[f32], j: usize) -> Self {
+        let data = &data[j..j + 8];
+        // SAFETY: `data` has 8 values, and see [F32x8]
+        Self(unsafe { _mm256_loadu_ps(data.as_ptr()) })
+    }
+
+    #[inline(always)]
+    fn store(self, data: &mut [f32], j: usize) {
+        let data = &mut data[j..j + 8];
+        // SAFETY: `data` has 8 values, and see [F32x8]
+        unsafe { _mm256_storeu_ps(data.as_mut_ptr(), self.0) }
+    }
+
+    #[inline(always)]
+    fn min(self, other: Self) -> Self {
+        // SAFETY: see [F32x8]
+        Self(unsafe { _mm256_min_ps(self.0, other.0) })
+    }
+
+    #[inline(always)]
+    fn max(self, other: Self) -> Self {
+        // SAFETY: see [F32x8]
+        Self(unsafe { _mm256_max_ps(self.0, other.0) })
+    }
+
+    #[inline(always)]
+    fn abs(self) -> Self {
+        // SAFETY: see [F32x8]
+        Self(unsafe { _mm256_andnot_ps(_mm256_set1_ps(-0.), self.0) })
+    }
+
+    #[inline(always)]
+    fn floor(self) -> Self {
+        // SAFETY: see [F32x8]
+        Self(unsafe { _mm256_floor_ps(self.0) })
+    }
+
+    #[inline(always)]
+    fn select(self, a: Self, b: Self) -> Self {
+        // SAFETY: see [F32x8]
+        unsafe {
+            let mask = _mm256_cmp_ps::<_CMP_NEQ_UQ>(self.0, _mm256_setzero_ps());
+            Self(_mm256_blendv_ps(b.0, a.0, mask))
+        }
+    }
+}
+
+/// Run `kernel` with 8 lanes.
+#[target_feature(enable = "avx")]
+pub(super) fn run_avx<K: Kernel>(kernel: &mut K) {
+    kernel.run::<F32x8>();
+}
@@ -1,138 +1,136 @@
 //! This is the main entry point for the WASM module.
+pub mod checkpoint;
+pub mod export;
+pub mod flowlines;
+#[cfg(any(feature = "parallel", feature = "simd"))]
+mod kernels;
+pub mod lic;
+pub mod overlay;
+pub mod particles;
+pub mod replay;
 pub mod simu;
+pub mod simulation;
+pub mod streamlines;
 pub mod utils;
 pub mod visualization;
+pub mod webgl;
+pub mod worker;
 
-use std::cell::RefCell;
-use std::rc::Rc;
-use std::vec;
-
-use simu::DrawOptions;
-use simu::ObstacleType;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 
-use web_time::Instant;
-
 use crate::simu::Fluid;
+use crate::simulation::Simulation;
 
-fn window() -> web_sys::Window {
-    web_sys::window().expect("no global `window` exists")
+/// Return the wasm memory.
+///
+/// Together with the `Fluid::*_ptr` accessors and `Fluid::field_len`, this lets JS
+/// build its own `Float32Array`s over the simulation fields.
+#[wasm_bindgen]
+pub fn memory() -> JsValue {
+    wasm_bindgen::memory()
 }
 
-fn request_animation_frame(f: &Closure<dyn FnMut()>) {
-    window()
-        .request_animation_frame(f.as_ref().unchecked_ref())
-        .expect("should register `requestAnimationFrame` OK");
+/// Call `f` whenever `element` fires `event`.
+fn listen(element: &web_sys::EventTarget, event: &str, f: impl FnMut() + 'static) {
+    let closure = Closure::<dyn FnMut()>::new(f);
+    element
+        .add_event_listener_with_callback(event, closure.as_ref().unchecked_ref())
+        .expect("should register event listener OK");
+    // the listener lives as long as the page
+    closure.forget();
 }
 
 /// Run the simulation with the given parameters.
+///
+/// The controls are bound to the simulation through event listeners; pages
+/// that want more control should use [Simulation] directly.
 #[wasm_bindgen]
 #[allow(clippy::too_many_arguments)]
 pub fn run_with_selector(
     num_iters: u32,
     over_relaxation: f32,
-    mut fluid: Fluid,
+    fluid: Fluid,
     canvas: web_sys::HtmlCanvasElement,
     scenario_selector: web_sys::HtmlSelectElement,
     pressure_checkbox: web_sys::HtmlInputElement,
     streamlines_checkbox: web_sys::HtmlInputElement,
-    streamlines_num_segs: web_sys::HtmlInputElement,
+    streamlines_max_length: web_sys::HtmlInputElement,
     streamlines_spacing: web_sys::HtmlInputElement,
     in_vel: web_sys::HtmlInputElement,
     colormap_selector: web_sys::HtmlSelectElement,
     sim_to_canvas_ratio: u32,
 ) -> Result<(), JsValue> {
-    let f = Rc::new(RefCell::new(None));
-    let g = f.clone();
-
-    let previous_frame = Rc::new(RefCell::new(Instant::now()));
-
-    let scenario = Rc::new(RefCell::new(None::<(String, f32)>));
-
-    let context = canvas
-        .get_context("2d")
-        .unwrap()
-        .unwrap()
-        .dyn_into::<web_sys::CanvasRenderingContext2d>()
-        .unwrap();
-
-    *g.borrow_mut() = Some(Closure::new(move || {
-        let now = Instant::now();
-        let dt = now.duration_since(*previous_frame.borrow()).as_secs_f32();
-        *previous_frame.borrow_mut() = now;
-
-        if dt != 0.0 {
-            // Update the fluid.
-            fluid.simulate(dt, num_iters, over_relaxation);
+    let simulation = Simulation::new(
+        fluid,
+        canvas,
+        num_iters,
+        over_relaxation,
+        sim_to_canvas_ratio,
+    )?;
+
+    let update_scenario = {
+        let simulation = simulation.clone();
+        let scenario_selector = scenario_selector.clone();
+        move || {
+            if let Err(e) = simulation.set_scenario(&scenario_selector.value()) {
+                web_sys::console::error_1(&e);
+            }
         }
-
-        let pressure = pressure_checkbox.checked();
-
-        let streamlines = streamlines_checkbox.checked();
-
-        let colormap_value = colormap_selector.value();
-
-        let streamlines_num_segs = streamlines_num_segs.value_as_number() as usize;
-        let streamlines_spacing = streamlines_spacing.value_as_number() as usize;
-
-        let in_vel = in_vel.value_as_number() as f32;
-
-        let options = DrawOptions {
-            pressure,
-            obstacle: true,
-            streamlines,
-            streamlines_num_segs,
-            streamlines_spacing,
-            colormap: colormap_value,
-        };
-
-        // What scenario are we in?
-        let scenario_value = scenario_selector.value();
-
-        // If the scenario is not set or has changed, update the fluid.
-        let mut scenario = scenario.borrow_mut();
-        match scenario.as_ref() {
-            Some((sv, in_vel_)) if (sv == scenario_value.as_str()) && (*in_vel_ == in_vel) => {}
-            _ => {
-                scenario.replace((scenario_value.clone(), in_vel));
-
-                match scenario_value.as_str() {
-                    "rectangular" => {
-                        fluid.clear_obstacles();
-                        fluid.vortex_shedding(
-                            in_vel,
-                            vec![ObstacleType::Rectangular {
-                                x: 0.2,
-                                y: 0.5,
-                                w: 0.1,
-                                h: 0.3,
-                            }],
-                        );
-                    }
-                    _ => {
-                        fluid.clear_obstacles();
-                        fluid.vortex_shedding(
-                            in_vel,
-                            vec![ObstacleType::Circular {
-                                x: 0.5,
-                                y: 0.5,
-                                r: 0.2,
-                            }],
-                        );
-                    }
-                }
+    };
+
+    let update_in_vel = {
+        let simulation = simulation.clone();
+        let in_vel = in_vel.clone();
+        move || simulation.set_in_vel(in_vel.value_as_number() as f32)
+    };
+
+    let update_colormap = {
+        let simulation = simulation.clone();
+        let colormap_selector = colormap_selector.clone();
+        move || {
+            if let Err(e) = simulation.set_colormap(&colormap_selector.value()) {
+                web_sys::console::error_1(&e);
             }
         }
+    };
+
+    let update_draw_options = {
+        let simulation = simulation.clone();
+        let pressure_checkbox = pressure_checkbox.clone();
+        let streamlines_checkbox = streamlines_checkbox.clone();
+        let streamlines_max_length = streamlines_max_length.clone();
+        let streamlines_spacing = streamlines_spacing.clone();
+        move || {
+            let result = simulation.set_draw_options(
+                pressure_checkbox.checked(),
+                streamlines_checkbox.checked(),
+                streamlines_max_length.value_as_number() as f32,
+                streamlines_spacing.value_as_number() as f32,
+            );
+            if let Err(e) = result {
+                web_sys::console::error_1(&e);
+            }
+        }
+    };
+
+    // pick up the initial state of the controls
+    update_in_vel();
+    update_scenario();
+    update_colormap();
+    update_draw_options();
+
+    listen(&scenario_selector, "change", update_scenario);
+    listen(&in_vel, "input", update_in_vel);
+    listen(&colormap_selector, "change", update_colormap);
+    for element in [
+        &pressure_checkbox,
+        &streamlines_checkbox,
+        &streamlines_max_length,
+        &streamlines_spacing,
+    ] {
+        listen(element, "input", update_draw_options.clone());
+    }
 
-        fluid
-            .render(options, dt, sim_to_canvas_ratio, &context)
-            .expect("draw failed");
-
-        // Schedule ourself for another requestAnimationFrame callback.
-        request_animation_frame(f.borrow().as_ref().unwrap());
-    }));
-
-    request_animation_frame(g.borrow().as_ref().unwrap());
     Ok(())
 }
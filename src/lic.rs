@@ -0,0 +1,258 @@
+//! Line integral convolution (LIC)
+//!
+//! A white noise texture is averaged along the streamlines through each texel,
+//! which smears the noise in the direction of the flow: the result shows the
+//! direction of the velocity everywhere, without picking seed points. With a
+//! phase, the kernel is a ripple moving downstream so that successive frames
+//! show the flow moving.
+use crate::simu::Fluid;
+use crate::visualization::{Image, Interpolation, ScalarField};
+
+/// Parameters of [lic]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LicOptions {
+    /// Length of the kernel on each side of a texel (in cells)
+    pub length: f32,
+    /// Number of texels per cell
+    pub resolution: usize,
+    /// Phase of the animated kernel, in `[0, 1)`; `None` for a box kernel
+    pub phase: Option<f32>,
+}
+
+/// Intensities in `[0, 1]` covering the interior cells, rows from the top
+pub struct LicTexture {
+    pub(crate) data: Vec<f32>,
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+}
+
+impl LicTexture {
+    /// Sample the texture at (x, y), in texels from the top left corner.
+    pub(crate) fn sample(&self, x: f32, y: f32) -> f32 {
+        let x = (x - 0.5).clamp(0., (self.width - 1) as f32);
+        let y = (y - 0.5).clamp(0., (self.height - 1) as f32);
+
+        let (i, j) = (x.floor() as usize, y.floor() as usize);
+        let (i1, j1) = ((i + 1).min(self.width - 1), (j + 1).min(self.height - 1));
+        let (tx, ty) = (x - i as f32, y - j as f32);
+
+        let at = |i: usize, j: usize| self.data[j * self.width + i];
+        let top = at(i, j) + (at(i1, j) - at(i, j)) * tx;
+        let bottom = at(i, j1) + (at(i1, j1) - at(i, j1)) * tx;
+        top + (bottom - top) * ty
+    }
+}
+
+/// The last texture computed by [lic], kept until the fluid steps or the
+/// options change
+#[derive(Default)]
+pub struct LicCache {
+    /// (step, num_x, num_y, options) the texture was computed for
+    key: Option<(u64, usize, usize, LicOptions)>,
+    texture: Option<LicTexture>,
+}
+
+impl LicCache {
+    /// Returns the texture of `fluid`, and true if it was just computed.
+    pub fn get(&mut self, fluid: &Fluid, options: &LicOptions) -> (&LicTexture, bool) {
+        let key = Some((fluid.step, fluid.num_x, fluid.num_y, *options));
+        let computed = self.key != key || self.texture.is_none();
+        if computed {
+            self.texture = Some(lic(fluid, options));
+            self.key = key;
+        }
+        (self.texture.as_ref().unwrap(), computed)
+    }
+
+    /// Forget the texture: the velocity changed without a step.
+    pub fn clear(&mut self) {
+        self.key = None;
+        self.texture = None;
+    }
+}
+
+/// White noise in `[0, 1)`, fixed for a texel so that the texture does not flicker
+fn noise(x: i32, y: i32) -> f32 {
+    let mut h = (x as u32).wrapping_mul(0x8da6_b343) ^ (y as u32).wrapping_mul(0xd816_3841);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x2c1b_3c6d);
+    h ^= h >> 12;
+    h = h.wrapping_mul(0x297a_2d39);
+    h ^= h >> 15;
+    (h >> 8) as f32 / (1 << 24) as f32
+}
+
+/// Compute the LIC texture of the velocity field.
+pub fn lic(fluid: &Fluid, options: &LicOptions) -> LicTexture {
+    let resolution = options.resolution.max(1);
+    let width = (fluid.num_x - 2) * resolution;
+    let height = (fluid.num_y - 2) * resolution;
+    let r = resolution as f32;
+
+    let (uc, vc) = fluid.cell_centered_velocity();
+    let u = ScalarField::new(&uc, fluid.num_x, fluid.num_y);
+    let v = ScalarField::new(&vc, fluid.num_x, fluid.num_y);
+
+    // unit velocity at the texel centers, in texel coordinates (y down); `None`
+    // in the obstacles and at stagnation points, where the lines stop
+    let mut directions = Vec::with_capacity(width * height);
+    for ty in 0..height {
+        // texel center to cell coordinates, y pointing up (see ScalarField)
+        let y = fluid.num_y as f32 - 1.5 - (ty as f32 + 0.5) / r;
+
+        for tx in 0..width {
+            let x = (tx as f32 + 0.5) / r + 0.5;
+
+            let solid = fluid.s[x.round() as usize * fluid.num_y + y.round() as usize] == 0.;
+            let du = u.sample(x, y, Interpolation::Bilinear);
+            let dv = v.sample(x, y, Interpolation::Bilinear);
+            let speed = (du * du + dv * dv).sqrt();
+
+            directions.push(if solid || speed == 0. || !speed.is_finite() {
+                None
+            } else {
+                Some((du / speed, -dv / speed))
+            });
+        }
+    }
+
+    let direction_at = |x: f32, y: f32| {
+        if x < 0. || y < 0. || x >= width as f32 || y >= height as f32 {
+            return None;
+        }
+        directions[y as usize * width + x as usize]
+    };
+
+    // half a texel per step
+    let step = 0.5;
+    let steps = (options.length * r / step).ceil().max(1.) as usize;
+
+    let weights: Vec<f32> = (0..=steps)
+        .flat_map(|k| [k as f32, -(k as f32)])
+        .map(|s| match options.phase {
+            None => 1.,
+            Some(phase) => {
+                // Hann window times a ripple moving downstream
+                let t = s / steps as f32;
+                let window = 0.5 * (1. + (std::f32::consts::PI * t).cos());
+                let ripple = 0.5 * (1. + (2. * std::f32::consts::PI * (2. * t + phase)).cos());
+                window * ripple
+            }
+        })
+        .collect();
+    // weight of the k-th step downstream (+) or upstream (-)
+    let weight = |k: usize, downstream: bool| weights[2 * k + !downstream as usize];
+
+    let mut data = vec![0.5; width * height];
+
+    for ty in 0..height {
+        for tx in 0..width {
+            if directions[ty * width + tx].is_none() {
+                continue;
+            }
+
+            let mut sum = weight(0, true) * noise(tx as i32, ty as i32);
+            let mut total = weight(0, true);
+
+            for downstream in [true, false] {
+                let sign = if downstream { 1. } else { -1. };
+                let (mut x, mut y) = (tx as f32 + 0.5, ty as f32 + 0.5);
+
+                for k in 1..=steps {
+                    let Some((dx, dy)) = direction_at(x, y) else {
+                        break;
+                    };
+                    x += sign * step * dx;
+                    y += sign * step * dy;
+
+                    if direction_at(x, y).is_none() {
+                        break;
+                    }
+
+                    let w = weight(k, downstream);
+                    sum += w * noise(x as i32, y as i32);
+                    total += w;
+                }
+            }
+
+            data[ty * width + tx] = if total > 0. { sum / total } else { 0.5 };
+        }
+    }
+
+    // averaging shrinks the contrast of the noise: stretch it back
+    let count = data.len() as f32;
+    let mean = data.iter().sum::<f32>() / count;
+    let std = (data.iter().map(|x| (x - mean) * (x - mean)).sum::<f32>() / count).sqrt();
+    if std > 0. {
+        let k = 0.2 / std;
+        for x in &mut data {
+            *x = (0.5 + (*x - mean) * k).clamp(0., 1.);
+        }
+    }
+
+    LicTexture {
+        data,
+        width,
+        height,
+    }
+}
+
+/// Modulate the image by the texture: colored pixels are darkened where the
+/// texture is dark, transparent ones are painted in gray.
+pub(crate) fn apply(texture: &LicTexture, image: &mut Image) {
+    let (width, height) = image.size();
+    let sx = texture.width as f32 / width as f32;
+    let sy = texture.height as f32 / height as f32;
+
+    for py in 0..height {
+        for px in 0..width {
+            let g = texture.sample((px as f32 + 0.5) * sx, (py as f32 + 0.5) * sy);
+            let [r, g_, b, a] = image.pixel(px, py);
+
+            let color = if a == 0 {
+                let g = (g * 255.).round() as u8;
+                [g, g, g, 255]
+            } else {
+                let k = 0.3 + 0.7 * g;
+                let scale = |c: u8| (c as f32 * k).round() as u8;
+                [scale(r), scale(g_), scale(b), a]
+            };
+            image.put_pixel(px, py, color);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simu::apply_scenario;
+
+    #[test]
+    fn cache() {
+        let mut fluid = Fluid::create(0., 1.5, 20, 10, 0.01, 1000.);
+        apply_scenario(&mut fluid, "cylindrical", 1.5);
+        fluid.simulate(1. / 60., 20, 1.9);
+
+        let mut options = LicOptions {
+            length: 5.,
+            resolution: 2,
+            phase: Some(0.25),
+        };
+        let mut cache = LicCache::default();
+        let (texture, computed) = cache.get(&fluid, &options);
+        assert!(computed);
+        assert_eq!(texture.data, lic(&fluid, &options).data);
+        assert!(!cache.get(&fluid, &options).1);
+
+        // a step, new options and a cleared cache all recompute the texture
+        fluid.simulate(1. / 60., 20, 1.9);
+        assert!(cache.get(&fluid, &options).1);
+        options.phase = Some(0.5);
+        assert!(cache.get(&fluid, &options).1);
+        assert!(!cache.get(&fluid, &options).1);
+        cache.clear();
+        let (texture, computed) = cache.get(&fluid, &options);
+        assert!(computed);
+        assert_eq!(texture.data, lic(&fluid, &options).data);
+    }
+}
@@ -0,0 +1,351 @@
+//! Annotations drawn over the rendered field
+//!
+//! The colorbar, the axes and the HUD are drawn into the [Image] itself, so
+//! they look the same on the canvas, with WebGL and in exported images.
+use crate::simu::{DrawOptions, Fluid};
+use crate::visualization::{text_size, Colormap, Image};
+
+/// Background of the labels
+const BACKGROUND: [u8; 4] = [255, 255, 255, 170];
+/// Text and ticks
+const FOREGROUND: [u8; 4] = [0, 0, 0, 255];
+
+/// Draw the overlays enabled in `options`.
+///
+/// `range` is the range of the colormap and `fps` is shown in the HUD when known.
+pub(crate) fn draw(
+    fluid: &Fluid,
+    image: &mut Image,
+    options: &DrawOptions,
+    colormap: &dyn Colormap,
+    range: (f32, f32),
+    fps: Option<f32>,
+) {
+    let left = draw_static(fluid, image, options, colormap, range);
+    if options.hud {
+        Hud::new(fluid, options, fps, left, image.size().1).draw(image, (0, 0));
+    }
+}
+
+/// Draw the overlays which only change with the options and the range: the
+/// axes and the colorbar.
+///
+/// Returns the width taken by the y axis on the left, where the HUD starts.
+pub(crate) fn draw_static(
+    fluid: &Fluid,
+    image: &mut Image,
+    options: &DrawOptions,
+    colormap: &dyn Colormap,
+    range: (f32, f32),
+) -> usize {
+    let scale = font_scale(image.size().1);
+
+    let mut left = 0;
+    if options.axes {
+        left = draw_axes(fluid, image, scale);
+    }
+    if options.colorbar && options.pressure {
+        draw_colorbar(image, options, colormap, range, scale);
+    }
+    left
+}
+
+/// Font pixels are doubled on images of `height` pixels or more.
+fn font_scale(height: usize) -> usize {
+    if height >= 400 {
+        2
+    } else {
+        1
+    }
+}
+
+/// Format a value with 3 significant digits.
+pub(crate) fn format_value(x: f32) -> String {
+    let a = x.abs();
+
+    if a == 0. {
+        "0".to_string()
+    } else if !a.is_finite() {
+        format!("{}", x)
+    } else if (0.01..10000.).contains(&a) {
+        let decimals = (2 - a.log10().floor() as i32).max(0) as usize;
+        format!("{:.*}", decimals, x)
+    } else {
+        format!("{:.2e}", x)
+    }
+}
+
+/// Return a round step (1, 2 or 5 times a power of 10) splitting `length` in
+/// about `count` intervals.
+fn nice_step(length: f32, count: usize) -> f32 {
+    let raw = length / count as f32;
+    let magnitude = 10_f32.powf(raw.log10().floor());
+
+    let step = [1., 2., 5., 10.]
+        .into_iter()
+        .find(|&k| k * magnitude >= raw)
+        .unwrap_or(10.);
+    step * magnitude
+}
+
+/// Draw `text` on a translucent box; (x, y) is the top left corner of the text.
+fn label(image: &mut Image, x: isize, y: isize, text: &str, scale: usize) {
+    let (w, h) = text_size(text, scale);
+    let pad = scale as isize;
+
+    image.fill_rect(x - pad, y - pad, w + 2 * scale, h + 2 * scale, BACKGROUND);
+    image.draw_text(x, y, text, FOREGROUND, scale);
+}
+
+/// Vertical colorbar on the right, with the unit of the field on top.
+fn draw_colorbar(
+    image: &mut Image,
+    options: &DrawOptions,
+    colormap: &dyn Colormap,
+    (min, max): (f32, f32),
+    scale: usize,
+) {
+    const TICKS: usize = 5;
+
+    let (width, height) = image.size();
+
+    let title = match options.field.unit() {
+        "" => options.field.to_string(),
+        unit => format!("{} ({})", options.field, unit),
+    };
+    let labels: Vec<String> = (0..TICKS)
+        .map(|k| format_value(max - (max - min) * k as f32 / (TICKS - 1) as f32))
+        .collect();
+
+    let pad = 4 * scale;
+    let bar_w = 10 * scale;
+    let bar_h = height / 2;
+    let (_, text_h) = text_size("0", scale);
+    let labels_w = labels
+        .iter()
+        .map(|l| text_size(l, scale).0)
+        .max()
+        .unwrap_or(0);
+
+    let box_w = (pad + bar_w + pad + labels_w + pad).max(text_size(&title, scale).0 + 2 * pad);
+    let box_h = pad + text_h + pad + text_h / 2 + bar_h + text_h / 2 + pad;
+    let box_x = width.saturating_sub(box_w + 2 * pad) as isize;
+    let box_y = ((height.saturating_sub(box_h)) / 2) as isize;
+
+    image.fill_rect(box_x, box_y, box_w, box_h, BACKGROUND);
+    image.draw_text(
+        box_x + pad as isize,
+        box_y + pad as isize,
+        &title,
+        FOREGROUND,
+        scale,
+    );
+
+    let bar_x = box_x + pad as isize;
+    let bar_y = box_y + (pad + text_h + pad + text_h / 2) as isize;
+
+    for row in 0..bar_h {
+        let x = max - (max - min) * row as f32 / (bar_h - 1).max(1) as f32;
+        image.fill_rect(
+            bar_x,
+            bar_y + row as isize,
+            bar_w,
+            1,
+            colormap.get_color(x, min, max),
+        );
+    }
+
+    for (k, text) in labels.iter().enumerate() {
+        let y = bar_y + (bar_h.saturating_sub(1) * k / (TICKS - 1)) as isize;
+
+        image.draw_text(
+            bar_x + (bar_w + pad) as isize,
+            y - (text_h / 2) as isize,
+            text,
+            FOREGROUND,
+            scale,
+        );
+    }
+}
+
+/// Ticks in meters along the bottom and left edges.
+///
+/// Returns the width taken by the y axis.
+fn draw_axes(fluid: &Fluid, image: &mut Image, scale: usize) -> usize {
+    let h = fluid.h;
+    let r = image.resolution() as f32;
+    let (width, height) = image.size();
+    let tick = 5 * scale;
+    let (_, text_h) = text_size("0", scale);
+
+    // the image covers [h, (num_x - 1) h] x [h, (num_y - 1) h]
+    let (x_min, x_max) = (h, (fluid.num_x - 1) as f32 * h);
+    let (y_min, y_max) = (h, (fluid.num_y - 1) as f32 * h);
+    let step = nice_step(x_max - x_min, 8);
+
+    let mut x = (x_min / step).ceil() * step;
+    let mut last = String::new();
+    let mut end = 0;
+    while x <= x_max {
+        let px = ((x / h - 1.) * r) as isize;
+        let text = format_value(x);
+        let (w, _) = text_size(&text, scale);
+
+        image.fill_rect(px, height as isize - tick as isize, scale, tick, FOREGROUND);
+        // centered on the tick, kept inside the image
+        let lx = (px - (w / 2) as isize)
+            .min(width as isize - (w + scale) as isize)
+            .max(scale as isize);
+        label(
+            image,
+            lx,
+            height as isize - (tick + 2 * scale + text_h) as isize,
+            &text,
+            scale,
+        );
+
+        end = lx as usize + w;
+        last = text;
+        x += step;
+    }
+    if !last.is_empty() && end + text_size(" (M)", scale).0 + scale < width {
+        let y = height as isize - (tick + 2 * scale + text_h) as isize;
+        label(image, (end + 6 * scale) as isize, y, "(M)", scale);
+    }
+
+    let mut left = 0;
+    let mut y = (y_min / step).ceil() * step;
+    while y <= y_max {
+        let py = height as isize - ((y / h - 1.) * r) as isize;
+        let text = format_value(y);
+
+        // leave the corner to the x axis
+        if py < height as isize - (tick + 3 * text_h) as isize {
+            image.fill_rect(0, py, tick, scale, FOREGROUND);
+            let ly = (py - (text_h / 2) as isize).max(scale as isize);
+            label(image, (tick + 2 * scale) as isize, ly, &text, scale);
+
+            left = left.max(tick + 3 * scale + text_size(&text, scale).0);
+        }
+
+        y += step;
+    }
+
+    left
+}
+
+/// Step, simulated time, solver residual, Reynolds number and frame rate, in
+/// a box at the top left
+pub(crate) struct Hud {
+    lines: Vec<String>,
+    scale: usize,
+    /// top left corner and size of the box, in pixels
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Hud {
+    /// Lay out the HUD of an image `image_height` pixels high, `left` pixels
+    /// from the edge.
+    pub(crate) fn new(
+        fluid: &Fluid,
+        options: &DrawOptions,
+        fps: Option<f32>,
+        left: usize,
+        image_height: usize,
+    ) -> Self {
+        let mut lines = vec![
+            format!("STEP {}", fluid.step),
+            format!("T {} S", format_value(fluid.time as f32)),
+            format!("RESIDUAL {} 1/S", format_value(fluid.residual)),
+            format!(
+                "RE {}",
+                format_value(fluid.reynolds_number(options.viscosity))
+            ),
+            format!("IN_VEL {} M/S", format_value(fluid.in_vel)),
+            format!("GRAVITY {} M/S2", format_value(fluid.gravity)),
+        ];
+        if let Some(fps) = fps {
+            lines.push(format!("{:.1} FPS", fps));
+        }
+
+        let scale = font_scale(image_height);
+        let pad = 4 * scale;
+        let (_, text_h) = text_size("0", scale);
+        let line_h = text_h + 3 * scale;
+
+        Self {
+            x: left + pad,
+            y: pad,
+            width: lines
+                .iter()
+                .map(|l| text_size(l, scale).0)
+                .max()
+                .unwrap_or(0)
+                + 2 * pad,
+            height: lines.len() * line_h - 3 * scale + 2 * pad,
+            lines,
+            scale,
+        }
+    }
+
+    /// Draw the HUD into `image`, whose top left corner is at `origin` in the
+    /// image the HUD was laid out for.
+    pub(crate) fn draw(&self, image: &mut Image, origin: (usize, usize)) {
+        let scale = self.scale;
+        let pad = 4 * scale;
+        let (_, text_h) = text_size("0", scale);
+        let line_h = text_h + 3 * scale;
+        let x = self.x as isize - origin.0 as isize;
+        let y = self.y as isize - origin.1 as isize;
+
+        image.fill_rect(x, y, self.width, self.height, BACKGROUND);
+        for (k, line) in self.lines.iter().enumerate() {
+            image.draw_text(
+                x + pad as isize,
+                y + (pad + k * line_h) as isize,
+                line,
+                FOREGROUND,
+                scale,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flowlines::Flowlines;
+    use crate::lic::LicCache;
+    use crate::particles::Particles;
+
+    #[test]
+    fn tiny_images() {
+        for num_y in 1..=6 {
+            let fluid = Fluid::create(0., 1., 20, num_y, 0.01, 1000.);
+            let mut options = DrawOptions::new(&fluid);
+            options.colorbar = true;
+            options.axes = true;
+            options.hud = true;
+            let field = fluid.scalar_field(options.field);
+
+            for ratio in 1..=2 {
+                let image = fluid
+                    .render_frame(
+                        &options,
+                        &field,
+                        (0., 1.),
+                        Some(60.),
+                        ratio,
+                        &Particles::default(),
+                        &Flowlines::default(),
+                        &mut LicCache::default(),
+                    )
+                    .unwrap();
+                assert_eq!(image.size(), (20 * ratio as usize, num_y * ratio as usize));
+            }
+        }
+    }
+}
@@ -0,0 +1,746 @@
+//! Tracer particles advected by the flow
+//!
+//! Massless particles follow the velocity of the fluid, which shows how the
+//! flow transports matter when it is unsteady (where the streamlines of each
+//! frame do not tell). They are emitted from the inlet, from fixed points or
+//! placed by the user, and are removed when they leave the domain or touch an
+//! obstacle.
+//!
+//! Inertial particles have their own velocity instead, which relaxes towards
+//! the velocity of the fluid through the drag (Stokes drag with the
+//! Schiller-Naumann correction) while gravity pulls them down; they bounce on or
+//! stick to the walls and obstacles.
+use std::collections::VecDeque;
+use std::str::FromStr;
+
+use crate::simu::Fluid;
+use crate::visualization::Image;
+
+/// Time integration of the particle positions
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Integrator {
+    /// Midpoint method
+    Rk2,
+    /// Classic 4th order Runge-Kutta
+    Rk4,
+}
+
+impl FromStr for Integrator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rk2" => Ok(Integrator::Rk2),
+            "rk4" => Ok(Integrator::Rk4),
+            _ => Err(format!("unknown integrator: {}", s)),
+        }
+    }
+}
+
+/// How the particles are drawn
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParticleStyle {
+    Dots,
+    /// The last positions of each particle, fading out
+    Trails,
+}
+
+impl FromStr for ParticleStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dots" => Ok(ParticleStyle::Dots),
+            "trails" => Ok(ParticleStyle::Trails),
+            _ => Err(format!("unknown particle style: {}", s)),
+        }
+    }
+}
+
+/// Where particles are emitted
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Source {
+    /// Random heights along the inlet, on the left
+    Inlet,
+    /// A fixed point, in physical coordinates
+    Point { x: f32, y: f32 },
+}
+
+/// What an inertial particle does when it hits a wall or an obstacle
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Wall {
+    /// Bounce back, keeping `restitution` times the normal velocity
+    Bounce { restitution: f32 },
+    /// Stay at rest where it hit
+    Stick,
+}
+
+impl FromStr for Wall {
+    type Err = String;
+
+    /// Parse `bounce` (elastic) or `stick`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bounce" => Ok(Wall::Bounce { restitution: 1. }),
+            "stick" => Ok(Wall::Stick),
+            _ => Err(format!("unknown wall behavior: {}", s)),
+        }
+    }
+}
+
+/// Physical properties of a spherical inertial particle
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Inertia {
+    /// Diameter (in m)
+    pub diameter: f32,
+    /// Density (in kg/m³)
+    pub density: f32,
+    pub wall: Wall,
+}
+
+impl Inertia {
+    /// Mass (in kg)
+    pub fn mass(&self) -> f32 {
+        self.density * std::f32::consts::PI * self.diameter.powi(3) / 6.
+    }
+}
+
+/// Emits particles at a steady rate.
+#[derive(Clone, Debug)]
+pub struct Emitter {
+    pub source: Source,
+    /// Particles per second
+    pub rate: f32,
+    /// Properties of the particles emitted, `None` for tracers
+    pub inertia: Option<Inertia>,
+    /// fraction of a particle not emitted yet
+    pending: f32,
+}
+
+impl Emitter {
+    /// An emitter of tracers, see [Emitter::with_inertia].
+    ///
+    /// The rate must be finite and non-negative.
+    pub fn new(source: Source, rate: f32) -> Result<Self, String> {
+        if !rate.is_finite() || rate < 0. {
+            return Err(format!("invalid emission rate: {}", rate));
+        }
+
+        Ok(Emitter {
+            source,
+            rate,
+            inertia: None,
+            pending: 0.,
+        })
+    }
+
+    /// Emit inertial particles instead of tracers.
+    pub fn with_inertia(mut self, inertia: Inertia) -> Self {
+        self.inertia = Some(inertia);
+        self
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Particle {
+    /// Position, in physical coordinates
+    pub position: (f32, f32),
+    /// Time since the particle was emitted (in s)
+    pub age: f32,
+    /// `None` for a tracer
+    pub inertia: Option<Inertia>,
+    /// velocity of an inertial particle, `None` until it takes the velocity
+    /// of the fluid at its first step
+    velocity: Option<(f32, f32)>,
+    /// true once an inertial particle stuck to a wall
+    stuck: bool,
+    /// previous positions, oldest first
+    trail: VecDeque<(f32, f32)>,
+}
+
+impl Particle {
+    fn new(position: (f32, f32), inertia: Option<Inertia>) -> Self {
+        Particle {
+            position,
+            age: 0.,
+            inertia,
+            velocity: None,
+            stuck: false,
+            trail: VecDeque::new(),
+        }
+    }
+
+    /// Velocity of an inertial particle (zero before its first step), or of
+    /// the fluid it follows for a tracer.
+    pub fn velocity(&self) -> (f32, f32) {
+        self.velocity.unwrap_or((0., 0.))
+    }
+
+    /// True if the particle stuck to a wall
+    pub fn is_stuck(&self) -> bool {
+        self.stuck
+    }
+}
+
+/// A set of tracer particles and their emitters
+#[derive(Clone, Debug)]
+pub struct Particles {
+    particles: Vec<Particle>,
+    emitters: Vec<Emitter>,
+    pub integrator: Integrator,
+    /// Maximum number of particles; the oldest ones are removed first
+    pub max_particles: usize,
+    /// Number of previous positions kept for the trails
+    pub trail_length: usize,
+    /// Kinematic viscosity of the fluid (in m²/s), for the drag
+    pub viscosity: f32,
+    /// Vertical acceleration of the inertial particles (in m/s²)
+    pub gravity: f32,
+    /// state of the random generator
+    seed: u32,
+}
+
+impl Default for Particles {
+    fn default() -> Self {
+        Particles::new(Integrator::Rk2)
+    }
+}
+
+impl Particles {
+    pub fn new(integrator: Integrator) -> Self {
+        Particles {
+            particles: vec![],
+            emitters: vec![],
+            integrator,
+            max_particles: 10_000,
+            trail_length: 20,
+            // water
+            viscosity: 1.0e-6,
+            gravity: -9.81,
+            seed: 0x2545_f491,
+        }
+    }
+
+    pub fn particles(&self) -> &[Particle] {
+        &self.particles
+    }
+
+    pub fn emitters(&self) -> &[Emitter] {
+        &self.emitters
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.particles.is_empty()
+    }
+
+    pub fn add_emitter(&mut self, emitter: Emitter) {
+        self.emitters.push(emitter);
+    }
+
+    /// Remove the emitters; the particles already emitted are kept.
+    pub fn clear_emitters(&mut self) {
+        self.emitters.clear();
+    }
+
+    /// Remove the particles; the emitters are kept.
+    pub fn clear(&mut self) {
+        self.particles.clear();
+    }
+
+    /// Add a particle at (x, y), in physical coordinates; a tracer if
+    /// `inertia` is `None`.
+    pub fn spawn(&mut self, x: f32, y: f32, inertia: Option<Inertia>) {
+        self.particles.push(Particle::new((x, y), inertia));
+        self.enforce_limit();
+    }
+
+    /// Add `count` particles at random within `radius` of (x, y), at most
+    /// `max_particles`.
+    pub fn spawn_around(
+        &mut self,
+        x: f32,
+        y: f32,
+        radius: f32,
+        count: usize,
+        inertia: Option<Inertia>,
+    ) {
+        for _ in 0..count.min(self.max_particles) {
+            // uniform in the disk
+            let r = radius * self.random().sqrt();
+            let angle = 2. * std::f32::consts::PI * self.random();
+            let position = (x + r * angle.cos(), y + r * angle.sin());
+            self.particles.push(Particle::new(position, inertia));
+        }
+        self.enforce_limit();
+    }
+
+    /// Uniform in `[0, 1)` (xorshift)
+    fn random(&mut self) -> f32 {
+        let mut x = self.seed;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.seed = x;
+        (x >> 8) as f32 / (1 << 24) as f32
+    }
+
+    fn enforce_limit(&mut self) {
+        if self.particles.len() > self.max_particles {
+            // particles are in emission order
+            let excess = self.particles.len() - self.max_particles;
+            self.particles.drain(..excess);
+        }
+    }
+
+    /// Emit new particles, then move all of them by `dt`.
+    pub fn step(&mut self, fluid: &Fluid, dt: f32) {
+        self.emit(fluid, dt);
+
+        let integrator = self.integrator;
+        let trail_length = self.trail_length;
+        let (viscosity, gravity) = (self.viscosity, self.gravity);
+
+        self.particles.retain_mut(|particle| {
+            if particle.stuck {
+                return true;
+            }
+
+            let position = match particle.inertia {
+                None => match advect(fluid, particle.position, dt, integrator) {
+                    Some((x, y)) => {
+                        let (x0, y0) = particle.position;
+                        particle.velocity = Some(((x - x0) / dt, (y - y0) / dt));
+                        (x, y)
+                    }
+                    None => return false,
+                },
+                Some(inertia) => {
+                    match move_inertial(fluid, particle, inertia, dt, viscosity, gravity) {
+                        Some(position) => position,
+                        None => return false,
+                    }
+                }
+            };
+
+            if trail_length > 0 {
+                if particle.trail.len() == trail_length {
+                    particle.trail.pop_front();
+                }
+                particle.trail.push_back(particle.position);
+            }
+            particle.position = position;
+            particle.age += dt;
+
+            true
+        });
+    }
+
+    /// Emit the particles due over `dt`, at most `max_particles` per emitter.
+    fn emit(&mut self, fluid: &Fluid, dt: f32) {
+        let h = fluid.h;
+        // the fluid spans [h, (num_y - 1) h] vertically
+        let height = (fluid.num_y - 2) as f32 * h;
+
+        for k in 0..self.emitters.len() {
+            let emitter = &mut self.emitters[k];
+            emitter.pending += emitter.rate * dt;
+            let count = emitter.pending.floor();
+            emitter.pending -= count;
+            let (source, inertia) = (emitter.source, emitter.inertia);
+
+            // the older particles would be removed right away anyway
+            let count = (count as usize).min(self.max_particles);
+
+            for _ in 0..count {
+                let position = match source {
+                    Source::Inlet => (1.5 * h, h + height * self.random()),
+                    Source::Point { x, y } => (x, y),
+                };
+                if fluid.is_fluid(position.0, position.1) {
+                    self.particles.push(Particle::new(position, inertia));
+                }
+            }
+        }
+
+        self.enforce_limit();
+    }
+
+    /// Draw the particles with a dark outline: tracers in white, particles
+    /// heavier than water in orange and lighter ones in blue.
+    pub(crate) fn draw(&self, fluid: &Fluid, image: &mut Image, style: ParticleStyle) {
+        let r = image.resolution() as f32;
+        let radius = (0.25 * r).clamp(1.5, 4.);
+
+        for particle in &self.particles {
+            if style == ParticleStyle::Trails {
+                let count = particle.trail.len();
+                let mut previous = None;
+
+                for (k, &p) in particle
+                    .trail
+                    .iter()
+                    .chain([&particle.position])
+                    .enumerate()
+                {
+                    let p = fluid.to_image(image, p);
+                    if let Some(a) = previous {
+                        // fade out towards the tail
+                        let alpha = (255 * k / (count + 1)) as u8;
+                        image.draw_line(a, p, [255, 255, 255, alpha]);
+                    }
+                    previous = Some(p);
+                }
+            }
+
+            let color = match particle.inertia {
+                None => [255, 255, 255, 255],
+                Some(inertia) if inertia.density > fluid.density => [255, 140, 0, 255],
+                Some(_) => [80, 160, 255, 255],
+            };
+
+            let (x, y) = fluid.to_image(image, particle.position);
+            image.fill_circle((x, y), radius + 1., [0, 0, 0, 160]);
+            image.fill_circle((x, y), radius, color);
+        }
+    }
+}
+
+/// Move a particle at `p` by `dt`; `None` if it leaves the fluid.
+pub(crate) fn advect(
+    fluid: &Fluid,
+    (x, y): (f32, f32),
+    dt: f32,
+    integrator: Integrator,
+) -> Option<(f32, f32)> {
+    let velocity = |(x, y): (f32, f32)| fluid.velocity(x, y);
+    let at = |(u, v): (f32, f32), t: f32| (x + u * t, y + v * t);
+
+    let next = match integrator {
+        Integrator::Rk2 => {
+            let k1 = velocity((x, y));
+            let k2 = velocity(at(k1, 0.5 * dt));
+            at(k2, dt)
+        }
+        Integrator::Rk4 => {
+            let k1 = velocity((x, y));
+            let k2 = velocity(at(k1, 0.5 * dt));
+            let k3 = velocity(at(k2, 0.5 * dt));
+            let k4 = velocity(at(k3, dt));
+            let u = (k1.0 + 2. * k2.0 + 2. * k3.0 + k4.0) / 6.;
+            let v = (k1.1 + 2. * k2.1 + 2. * k3.1 + k4.1) / 6.;
+            at((u, v), dt)
+        }
+    };
+
+    fluid.is_fluid(next.0, next.1).then_some(next)
+}
+
+/// Drag correction of Schiller and Naumann for the particle Reynolds number `re`
+///
+/// Beyond `re = 1000` the drag coefficient is taken constant (0.44).
+fn drag_correction(re: f32) -> f32 {
+    if re < 1000. {
+        1. + 0.15 * re.powf(0.687)
+    } else {
+        0.44 * re / 24.
+    }
+}
+
+/// Move an inertial particle by `dt` and update its velocity; `None` if it
+/// leaves the domain.
+///
+/// With the fluid velocity `u` taken constant over the step, the velocity
+/// `dv/dt = (u - v) / tau + g` relaxes exponentially, which is integrated
+/// exactly: the update stays stable for small particles whose response time
+/// `tau` is much shorter than `dt`.
+fn move_inertial(
+    fluid: &Fluid,
+    particle: &mut Particle,
+    inertia: Inertia,
+    dt: f32,
+    viscosity: f32,
+    gravity: f32,
+) -> Option<(f32, f32)> {
+    let (x, y) = particle.position;
+    let (u, v) = fluid.velocity(x, y);
+    let (vx, vy) = particle.velocity.unwrap_or((u, v));
+
+    // Stokes response time, corrected for the particle Reynolds number
+    let d = inertia.diameter.max(f32::MIN_POSITIVE);
+    let dynamic_viscosity = viscosity * fluid.density;
+    let slip = ((u - vx).powi(2) + (v - vy).powi(2)).sqrt();
+    let re = slip * d / viscosity;
+    let tau = inertia.density * d * d / (18. * dynamic_viscosity) / drag_correction(re);
+
+    // gravity net of buoyancy
+    let g = gravity * (1. - fluid.density / inertia.density);
+
+    // terminal velocity, and how much of the difference remains after dt
+    let (tx, ty) = (u, v + g * tau);
+    let decay = (-dt / tau).exp();
+    let relax = tau * (1. - decay);
+
+    let next = (
+        x + tx * dt + (vx - tx) * relax,
+        y + ty * dt + (vy - ty) * relax,
+    );
+    let velocity = (tx + (vx - tx) * decay, ty + (vy - ty) * decay);
+
+    if fluid.is_fluid(next.0, next.1) {
+        particle.velocity = Some(velocity);
+        return Some(next);
+    }
+    if !hits_wall(fluid, next) {
+        // out through the outlet
+        return None;
+    }
+
+    match inertia.wall {
+        Wall::Stick => {
+            particle.velocity = Some((0., 0.));
+            particle.stuck = true;
+        }
+        Wall::Bounce { restitution } => {
+            // mirror the components that cross the wall, keep the motion along it
+            let mut across_x = hits_wall(fluid, (next.0, y));
+            let mut across_y = hits_wall(fluid, (x, next.1));
+            if !across_x && !across_y {
+                // into a corner
+                (across_x, across_y) = (true, true);
+            }
+
+            // below this rebound, the particle rests on the wall
+            let rest = (g * dt).abs();
+            let (mut position, mut velocity) = (next, velocity);
+            if across_x {
+                (position.0, velocity.0) =
+                    bounce(x, next.0, velocity.0, restitution, rest, fluid.h);
+            }
+            if across_y {
+                (position.1, velocity.1) =
+                    bounce(y, next.1, velocity.1, restitution, rest, fluid.h);
+            }
+            particle.velocity = Some(velocity);
+
+            if fluid.is_fluid(position.0, position.1) {
+                return Some(position);
+            }
+        }
+    }
+
+    // stay in the fluid, where the particle was
+    Some((x, y))
+}
+
+/// Bounce off a wall along one axis, going from `from` in the fluid to `to`
+/// in the wall, with cells of size `h`.
+///
+/// Returns the coordinate and the velocity after the bounce: mirrored about
+/// the wall and scaled by `restitution`, or back at `from` and at rest if the
+/// rebound speed is under `rest`.
+fn bounce(from: f32, to: f32, velocity: f32, restitution: f32, rest: f32, h: f32) -> (f32, f32) {
+    let velocity = -restitution * velocity;
+    if velocity.abs() < rest {
+        return (from, 0.);
+    }
+
+    // face of the cell of `from` on the side of `to`
+    let cell = (from / h).floor();
+    let face = if to < from { cell * h } else { (cell + 1.) * h };
+    (face + restitution * (face - to), velocity)
+}
+
+/// Returns true if (x, y) is in a solid cell, borders included.
+fn hits_wall(fluid: &Fluid, (x, y): (f32, f32)) -> bool {
+    if x < 0. || y < 0. {
+        return false;
+    }
+
+    let (i, j) = ((x / fluid.h) as usize, (y / fluid.h) as usize);
+    i < fluid.num_x && j < fluid.num_y && fluid.s[i * fluid.num_y + j] == 0.
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A channel of 40 by 20 cells of 1 cm, with walls at the bottom, the top
+    /// and on the left, open on the right, and a uniform velocity (u, v).
+    fn channel(u: f32, v: f32) -> Fluid {
+        let mut fluid = Fluid::create(0., u, 40, 20, 0.01, 1000.);
+        let n = fluid.num_y;
+        for i in 0..fluid.num_x {
+            for j in 0..n {
+                let wall = i == 0 || j == 0 || j == n - 1;
+                fluid.s[i * n + j] = if wall { 0. } else { 1. };
+            }
+        }
+        fluid.u.fill(u);
+        fluid.v.fill(v);
+        fluid
+    }
+
+    /// Sand in water
+    fn sand(wall: Wall) -> Option<Inertia> {
+        Some(Inertia {
+            diameter: 0.5e-3,
+            density: 2650.,
+            wall,
+        })
+    }
+
+    #[test]
+    fn terminal_settling_velocity() {
+        let fluid = channel(0., 0.);
+        let mut particles = Particles::default();
+        particles.spawn(0.2, 0.18, sand(Wall::Stick));
+        for _ in 0..30 {
+            particles.step(&fluid, 1. / 60.);
+        }
+
+        // Stokes drag corrected by Schiller and Naumann, solved by fixed point
+        let (d, nu, g) = (0.5e-3, 1.0e-6, 9.81 * (1. - 1000. / 2650.));
+        let mut expected = 0.;
+        for _ in 0..50 {
+            let re = expected * d / nu;
+            expected = g * 2650. * d * d / (18. * nu * 1000.) / drag_correction(re);
+        }
+        assert!((expected - 0.078_f32).abs() < 0.002, "{}", expected);
+
+        let (vx, vy) = particles.particles()[0].velocity();
+        assert_eq!(vx, 0.);
+        assert!((vy + expected).abs() < 1e-3 * expected, "{}", vy);
+    }
+
+    #[test]
+    fn stick_stops_at_the_wall() {
+        let fluid = channel(0.05, 0.);
+        let mut particles = Particles::default();
+        particles.spawn(0.05, 0.05, sand(Wall::Stick));
+        for _ in 0..60 {
+            particles.step(&fluid, 1. / 60.);
+        }
+
+        let particle = particles.particles()[0].clone();
+        assert!(particle.is_stuck());
+        assert_eq!(particle.velocity(), (0., 0.));
+        assert!(fluid.is_fluid(particle.position.0, particle.position.1));
+        assert!(particle.position.1 < 0.011, "{:?}", particle.position);
+
+        for _ in 0..60 {
+            particles.step(&fluid, 1. / 60.);
+        }
+        assert_eq!(particles.particles()[0].position, particle.position);
+    }
+
+    #[test]
+    fn bounce_slides_along_the_floor() {
+        let fluid = channel(0.05, 0.);
+        let mut particles = Particles::default();
+        particles.spawn(0.05, 0.05, sand(Wall::Bounce { restitution: 0.5 }));
+
+        // on the floor after a second
+        for _ in 0..60 {
+            particles.step(&fluid, 1. / 60.);
+        }
+        let (x, y) = particles.particles()[0].position;
+        assert!(y < 0.011, "{:?}", (x, y));
+
+        // then dragged downstream at about the speed of the flow
+        for _ in 0..60 {
+            particles.step(&fluid, 1. / 60.);
+        }
+        let particle = &particles.particles()[0];
+        let (x1, y1) = particle.position;
+        assert!(x1 - x > 0.04, "{} -> {}", x, x1);
+        assert!((0.01..0.011).contains(&y1), "{}", y1);
+        assert_eq!(particle.velocity().1, 0.);
+    }
+
+    #[test]
+    fn emission_rate() {
+        let fluid = channel(0., 0.);
+        let mut particles = Particles::default();
+        particles.add_emitter(Emitter::new(Source::Point { x: 0.1, y: 0.1 }, 1.5).unwrap());
+
+        // 0.375 particle per step, the fractions carried over to the next steps
+        for n in 1..=16 {
+            particles.step(&fluid, 0.25);
+            assert_eq!(particles.particles().len(), (0.375 * n as f32) as usize);
+        }
+        assert!(particles
+            .particles()
+            .iter()
+            .all(|p| p.position == (0.1, 0.1)));
+
+        assert!(Emitter::new(Source::Inlet, -1.).is_err());
+        assert!(Emitter::new(Source::Inlet, f32::NAN).is_err());
+    }
+
+    #[test]
+    fn oldest_particles_removed_first() {
+        let fluid = channel(0., 0.);
+        let mut particles = Particles::new(Integrator::Rk2);
+        particles.max_particles = 3;
+        particles.add_emitter(Emitter::new(Source::Inlet, 4.).unwrap());
+
+        for _ in 0..10 {
+            particles.step(&fluid, 0.25);
+        }
+        let ages: Vec<f32> = particles.particles().iter().map(|p| p.age).collect();
+        assert_eq!(ages, [0.75, 0.5, 0.25]);
+
+        // at most `max_particles` emitted at once
+        particles.clear_emitters();
+        particles.add_emitter(Emitter::new(Source::Inlet, 1e6).unwrap());
+        particles.step(&fluid, 1.);
+        assert_eq!(particles.particles().len(), 3);
+        assert!(particles.particles().iter().all(|p| p.age == 1.));
+
+        particles.spawn_around(0.2, 0.1, 0.02, 100, None);
+        assert_eq!(particles.particles().len(), 3);
+        assert!(particles.particles().iter().all(|p| p.age == 0.));
+    }
+
+    #[test]
+    fn removed_at_the_outlet_and_in_obstacles() {
+        let mut fluid = channel(0.5, 0.);
+        // an obstacle in the cell (20, 10)
+        fluid.s[20 * fluid.num_y + 10] = 0.;
+
+        let mut particles = Particles::default();
+        // leaves through the outlet at x = 0.41
+        particles.spawn(0.39, 0.05, None);
+        particles.spawn(0.39, 0.07, sand(Wall::Bounce { restitution: 1. }));
+        // runs into the obstacle
+        particles.spawn(0.185, 0.105, None);
+        // goes past it
+        particles.spawn(0.185, 0.125, None);
+
+        particles.step(&fluid, 0.04);
+        assert_eq!(particles.particles().len(), 1);
+        let (x, y) = particles.particles()[0].position;
+        assert!((x - 0.205).abs() < 1e-5 && (y - 0.125).abs() < 1e-5);
+    }
+
+    #[test]
+    fn integrators_follow_a_uniform_flow() {
+        let fluid = channel(0.03, 0.01);
+        for integrator in [Integrator::Rk2, Integrator::Rk4] {
+            let mut particles = Particles::new(integrator);
+            particles.spawn(0.1, 0.1, None);
+            for _ in 0..10 {
+                particles.step(&fluid, 0.1);
+            }
+
+            let particle = &particles.particles()[0];
+            let (x, y) = particle.position;
+            assert!((x - 0.13).abs() < 1e-5, "{:?}: {}", integrator, x);
+            assert!((y - 0.11).abs() < 1e-5, "{:?}: {}", integrator, y);
+            let (u, v) = particle.velocity();
+            assert!((u - 0.03).abs() < 1e-4 && (v - 0.01).abs() < 1e-4);
+            assert!((particle.age - 1.).abs() < 1e-5);
+            assert_eq!(particle.trail.len(), 10);
+        }
+    }
+}
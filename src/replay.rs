@@ -0,0 +1,352 @@
+//! Replay recording
+//!
+//! A [Recorder] keeps snapshots of the simulation taken every few steps in a
+//! ring buffer, so that a session can be scrubbed backwards and forwards, or
+//! exported as a sequence of checkpoints.
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+
+use crate::checkpoint::CheckpointError;
+use crate::simu::Fluid;
+
+/// Magic number at the start of an exported replay.
+const MAGIC: &[u8; 4] = b"FLRP";
+
+/// Current version of the replay format.
+pub const VERSION: u32 = 1;
+
+/// How the fields of a snapshot are stored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Precision {
+    /// Full `f32` values
+    Full,
+    /// `bfloat16` values: half the memory, about 3 significant digits
+    Half,
+}
+
+/// A field of a snapshot
+enum Values {
+    Full(Vec<f32>),
+    Half(Vec<u16>),
+}
+
+impl Values {
+    fn encode(data: &[f32], precision: Precision) -> Self {
+        match precision {
+            Precision::Full => Values::Full(data.to_vec()),
+            Precision::Half => Values::Half(
+                data.iter()
+                    .map(|x| {
+                        // round to nearest, ties to even
+                        let bits = x.to_bits();
+                        let rounding = 0x7fff + ((bits >> 16) & 1);
+                        (bits.wrapping_add(rounding) >> 16) as u16
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    fn decode(&self) -> Vec<f32> {
+        match self {
+            Values::Full(data) => data.clone(),
+            Values::Half(data) => data
+                .iter()
+                .map(|&x| f32::from_bits((x as u32) << 16))
+                .collect(),
+        }
+    }
+}
+
+/// The state of the simulation at some step
+struct Snapshot {
+    step: u64,
+    time: f64,
+    gravity: f32,
+    in_vel: f32,
+    num_iters: u32,
+    over_relaxation: f32,
+    /// u, v, p, s, m
+    fields: [Values; 5],
+}
+
+/// Records snapshots of a simulation in a ring buffer.
+pub struct Recorder {
+    capacity: usize,
+    interval: u32,
+    precision: Precision,
+
+    /// (num_x, num_y, h, density) of the recorded simulation
+    grid: Option<(usize, usize, f32, f32)>,
+
+    snapshots: VecDeque<Snapshot>,
+    /// number of calls to [Recorder::record] since the last snapshot
+    since_last: u32,
+}
+
+impl Recorder {
+    /// Create a recorder keeping the last `capacity` snapshots, taken every `interval` steps.
+    pub fn new(capacity: usize, interval: u32, precision: Precision) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            interval: interval.max(1),
+            precision,
+            grid: None,
+            snapshots: VecDeque::new(),
+            since_last: 0,
+        }
+    }
+
+    /// Record the simulation if `interval` steps have passed since the last snapshot.
+    ///
+    /// Recording a simulation on a different grid clears the recorder.
+    pub fn record(&mut self, fluid: &Fluid) {
+        let grid = (fluid.num_x, fluid.num_y, fluid.h, fluid.density);
+        if self.grid != Some(grid) {
+            self.clear();
+            self.grid = Some(grid);
+        }
+
+        if !self.snapshots.is_empty() && self.since_last + 1 < self.interval {
+            self.since_last += 1;
+            return;
+        }
+        self.since_last = 0;
+
+        if self.snapshots.len() == self.capacity {
+            self.snapshots.pop_front();
+        }
+
+        let precision = self.precision;
+        self.snapshots.push_back(Snapshot {
+            step: fluid.step,
+            time: fluid.time,
+            gravity: fluid.gravity,
+            in_vel: fluid.in_vel,
+            num_iters: fluid.num_iters,
+            over_relaxation: fluid.over_relaxation,
+            fields: [
+                Values::encode(&fluid.u, precision),
+                Values::encode(&fluid.v, precision),
+                Values::encode(&fluid.p, precision),
+                // the obstacle field must stay exact
+                Values::encode(&fluid.s, Precision::Full),
+                Values::encode(&fluid.m, precision),
+            ],
+        });
+    }
+
+    /// Remove all the snapshots.
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+        self.since_last = 0;
+    }
+
+    /// Keep only the `len` oldest snapshots.
+    pub fn truncate(&mut self, len: usize) {
+        self.snapshots.truncate(len);
+        self.since_last = 0;
+    }
+
+    /// Number of snapshots
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    /// Return true if there is no snapshot
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    /// Return the simulation step of the `index`-th snapshot (oldest first).
+    pub fn step_at(&self, index: usize) -> Option<u64> {
+        self.snapshots.get(index).map(|s| s.step)
+    }
+
+    /// Rebuild the simulation from the `index`-th snapshot (oldest first).
+    pub fn get(&self, index: usize) -> Option<Fluid> {
+        let snapshot = self.snapshots.get(index)?;
+        let (num_x, num_y, h, density) = self.grid?;
+
+        let mut fluid = Fluid::create(
+            snapshot.gravity,
+            snapshot.in_vel,
+            num_x - 2,
+            num_y - 2,
+            h,
+            density,
+        );
+        fluid.num_iters = snapshot.num_iters;
+        fluid.over_relaxation = snapshot.over_relaxation;
+        fluid.step = snapshot.step;
+        fluid.time = snapshot.time;
+
+        let [u, v, p, s, m] = &snapshot.fields;
+        fluid.u = u.decode();
+        fluid.v = v.decode();
+        fluid.p = p.decode();
+        fluid.s = s.decode();
+        fluid.m = m.decode();
+
+        Some(fluid)
+    }
+
+    /// Write the snapshots as a sequence of checkpoints (oldest first).
+    pub fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        w.write_all(&VERSION.to_le_bytes())?;
+        w.write_all(&(self.len() as u32).to_le_bytes())?;
+
+        for index in 0..self.len() {
+            let checkpoint = self.get(index).expect("index is in range").to_checkpoint();
+            w.write_all(&(checkpoint.len() as u64).to_le_bytes())?;
+            w.write_all(&checkpoint)?;
+        }
+
+        Ok(())
+    }
+
+    /// Read a replay written by [Recorder::write], with full precision and room for
+    /// `capacity` snapshots (at least the ones read).
+    pub fn read<R: Read>(r: &mut R, capacity: usize) -> Result<Recorder, CheckpointError> {
+        let mut buf = [0_u8; 4];
+        r.read_exact(&mut buf)?;
+        if &buf != MAGIC {
+            return Err(CheckpointError::BadMagic);
+        }
+
+        r.read_exact(&mut buf)?;
+        let version = u32::from_le_bytes(buf);
+        if version != VERSION {
+            return Err(CheckpointError::UnsupportedVersion(version));
+        }
+
+        r.read_exact(&mut buf)?;
+        let count = u32::from_le_bytes(buf) as usize;
+
+        let mut recorder = Recorder::new(capacity.max(count), 1, Precision::Full);
+        for _ in 0..count {
+            let mut len = [0_u8; 8];
+            r.read_exact(&mut len)?;
+
+            let mut snapshot = r.take(u64::from_le_bytes(len));
+            let fluid = Fluid::read_checkpoint(&mut snapshot)?;
+            // the next snapshot would be read from the wrong offset
+            if snapshot.limit() != 0 {
+                return Err(CheckpointError::Io(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "snapshot length does not match its checkpoint",
+                )));
+            }
+            recorder.record(&fluid);
+        }
+
+        Ok(recorder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simu::apply_scenario;
+
+    fn fluid() -> Fluid {
+        let mut fluid = Fluid::create(0., 1.5, 20, 10, 0.01, 1000.);
+        apply_scenario(&mut fluid, "cylindrical", 1.5);
+        fluid
+    }
+
+    fn steps(recorder: &Recorder) -> Vec<u64> {
+        (0..recorder.len())
+            .map(|k| recorder.step_at(k).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn ring_buffer() {
+        let mut fluid = fluid();
+        let mut recorder = Recorder::new(3, 2, Precision::Full);
+        for _ in 0..10 {
+            fluid.simulate(1. / 60., 10, 1.9);
+            recorder.record(&fluid);
+        }
+        // from the first step, every other step, the oldest evicted first
+        assert_eq!(steps(&recorder), [5, 7, 9]);
+
+        recorder.truncate(1);
+        assert_eq!(steps(&recorder), [5]);
+
+        // another grid starts over
+        recorder.record(&Fluid::create(0., 1., 8, 8, 0.01, 1000.));
+        assert_eq!(steps(&recorder), [0]);
+    }
+
+    #[test]
+    fn half_precision() {
+        let one_and = |ulps: u32| f32::from_bits(1_f32.to_bits() + (ulps << 15));
+        let data = [
+            0.,
+            -2.5,
+            // ties go to the even neighbor
+            one_and(1),
+            one_and(3),
+            // otherwise to the nearest one
+            one_and(5) + f32::EPSILON,
+            f32::INFINITY,
+        ];
+        let expected = [0., -2.5, 1., one_and(4), one_and(6), f32::INFINITY];
+
+        assert_eq!(Values::encode(&data, Precision::Half).decode(), expected);
+        assert_eq!(Values::encode(&data, Precision::Full).decode(), data);
+
+        let mut fluid = fluid();
+        fluid.simulate(1. / 60., 10, 1.9);
+        let mut recorder = Recorder::new(1, 1, Precision::Half);
+        recorder.record(&fluid);
+        let restored = recorder.get(0).unwrap();
+        // the obstacles are kept exact, the other fields within 2^-8
+        assert_eq!(restored.s, fluid.s);
+        for (a, b) in restored.u.iter().zip(&fluid.u) {
+            assert!((a - b).abs() <= b.abs() / 256.);
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut fluid = fluid();
+        let mut recorder = Recorder::new(4, 1, Precision::Full);
+        for _ in 0..3 {
+            fluid.simulate(1. / 60., 10, 1.9);
+            recorder.record(&fluid);
+        }
+
+        let mut data = vec![];
+        recorder.write(&mut data).unwrap();
+        let read = Recorder::read(&mut &data[..], 2).unwrap();
+
+        assert_eq!(steps(&read), [1, 2, 3]);
+        for k in 0..3 {
+            let (a, b) = (read.get(k).unwrap(), recorder.get(k).unwrap());
+            assert_eq!(a.to_checkpoint(), b.to_checkpoint());
+        }
+    }
+
+    #[test]
+    fn length_mismatch() {
+        let mut recorder = Recorder::new(2, 1, Precision::Full);
+        recorder.record(&fluid());
+        recorder.record(&fluid());
+
+        let mut data = vec![];
+        recorder.write(&mut data).unwrap();
+
+        // one more byte announced for the first snapshot than its checkpoint has
+        let len = u64::from_le_bytes(data[12..20].try_into().unwrap());
+        data[12..20].copy_from_slice(&(len + 1).to_le_bytes());
+
+        assert!(matches!(
+            Recorder::read(&mut &data[..], 2),
+            Err(CheckpointError::Io(e)) if e.kind() == io::ErrorKind::InvalidData
+        ));
+    }
+}
@@ -2,9 +2,18 @@
 //!
 //! From https://www.youtube.com/watch?v=iKAVRgIrUOU&list=PL-GwXAGjZ9fUf_7_MiBbPuLSJVp_3Edmq&index=1&t=6s
 //! Code from https://www.youtube.com/redirect?event=video_description&redir_token=QUFFLUhqazhqYnZnQVliZFVwSjdzMVdnSnpfbGJYdkRCZ3xBQ3Jtc0tueVZhRGl4TVdhM25Xa0JEcXRPcmNqNzVpR1VkX3FINzUzZktVY1IxS3I2MWpXNDJfdm9XeExDUTFlbUwwVDY5WW1rZkY4TkR1eE9mTWZIclpDU0ZaVFBIM19qNGdxTjBfZGZGTU9STFVwU1V2a2JmOA&q=https%3A%2F%2Fmatthias-research.github.io%2Fpages%2FtenMinutePhysics%2Findex.html
-use std::{convert::TryInto, vec};
-
-use crate::visualization;
+use std::{convert::TryInto, fmt, str::FromStr, vec};
+
+use crate::flowlines::Flowlines;
+#[cfg(any(feature = "parallel", feature = "simd"))]
+use crate::kernels::{self, AdvectSmoke, AdvectU, AdvectV, CorrectU, CorrectV, Relax};
+use crate::lic::{self, LicCache, LicOptions};
+use crate::overlay;
+use crate::particles::{ParticleStyle, Particles};
+use crate::streamlines::{self, SeedLine, StreamlineOptions};
+use crate::visualization::{self, ColorRange, Interpolation, ScalarField};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
 use wasm_bindgen::prelude::*;
 use web_sys::CanvasRenderingContext2d;
 
@@ -16,6 +25,7 @@ use web_sys::CanvasRenderingContext2d;
 ///
 /// [new_u] and [new_v] are the velocities at t+dt.
 ///
+/// ```text
 ///      v_{i,j+1}
 /// -----X-----
 /// |         |
@@ -31,76 +41,119 @@ use web_sys::CanvasRenderingContext2d;
 ///    | u[1]      u[num_y+1]               |
 ///    | u[0]      u[num_y]                 i
 ///    0-----> X                            |
+/// ```
 #[wasm_bindgen]
 pub struct Fluid {
     /// gravity
-    gravity: f32,
+    pub(crate) gravity: f32,
     /// in_vel - horizontal velocity at the inlet
-    in_vel: f32,
+    pub(crate) in_vel: f32,
 
     /// density of the fluid
-    density: f32,
+    pub(crate) density: f32,
 
-    num_x: usize,
-    num_y: usize,
+    pub(crate) num_x: usize,
+    pub(crate) num_y: usize,
 
     // cell size (in meters)
-    h: f32,
+    pub(crate) h: f32,
 
     /// x-component of velocity at t
     /// u_{i,j} = u[i * num_y + j ]
-    u: Vec<f32>,
+    pub(crate) u: Vec<f32>,
     /// y-component of velocity at t
     /// v_{i,j} = v[i * num_y + j ]
-    v: Vec<f32>,
+    pub(crate) v: Vec<f32>,
 
     /// x-component of velocity at t+dt
-    new_u: Vec<f32>,
+    pub(crate) new_u: Vec<f32>,
     /// y-component of velocity at t+dt
-    new_v: Vec<f32>,
+    pub(crate) new_v: Vec<f32>,
 
     /// pressure field
-    p: Vec<f32>,
+    pub(crate) p: Vec<f32>,
 
     /// obstacle field
     /// s == 0 => obstacle
     /// s == 1 => fluid
-    s: Vec<f32>,
+    pub(crate) s: Vec<f32>,
 
     /// smoke field at t
-    m: Vec<f32>,
+    pub(crate) m: Vec<f32>,
     /// smoke field at t+dt
-    new_m: Vec<f32>,
+    pub(crate) new_m: Vec<f32>,
+
+    /// number of iterations of the pressure solver used by the last step
+    pub(crate) num_iters: u32,
+    /// over-relaxation of the pressure solver used by the last step
+    pub(crate) over_relaxation: f32,
+
+    /// number of steps simulated so far
+    pub(crate) step: u64,
+    /// simulated time (in seconds)
+    pub(crate) time: f64,
+    /// largest divergence (in 1/s) left by the last iteration of the pressure solver
+    pub(crate) residual: f32,
 }
 
 enum Field {
     U,
     V,
+    // the smoke is advected by the kernels with these features
+    #[cfg_attr(any(feature = "parallel", feature = "simd"), allow(dead_code))]
     S,
 }
 
+/// Call `f` with the index and the values of each column of a field.
+///
+/// With the `parallel` feature, the columns are processed on the rayon thread
+/// pool. Each column is computed independently, so that the result does not
+/// depend on the number of threads.
+fn for_each_column(field: &mut [f32], num_y: usize, f: impl Fn(usize, &mut [f32]) + Send + Sync) {
+    #[cfg(feature = "parallel")]
+    field
+        .par_chunks_mut(num_y)
+        .enumerate()
+        .for_each(|(i, column)| f(i, column));
+
+    #[cfg(not(feature = "parallel"))]
+    field
+        .chunks_mut(num_y)
+        .enumerate()
+        .for_each(|(i, column)| f(i, column));
+}
+
 impl Fluid {
     fn integrate(&mut self, dt: f32, gravity: f32) {
         let n = self.num_y;
+        let s = &self.s;
 
-        // ignore i == 0 but not i == num_x-1
-        for i in 1..self.num_x {
+        for_each_column(&mut self.v, n, |i, v| {
+            // ignore i == 0 but not i == num_x-1
+            if i == 0 {
+                return;
+            }
             // ignore j == 0 and j == num_y-1 as well
-            for j in 1..(self.num_y - 1) {
+            for j in 1..(n - 1) {
                 // if it is not an obstacle and the cell below is not an obstacle
-                if self.s[i * n + j] != 0. && self.s[i * n + j - 1] != 0. {
-                    self.v[i * n + j] += dt * gravity;
+                if s[i * n + j] != 0. && s[i * n + j - 1] != 0. {
+                    v[j] += dt * gravity;
                 }
             }
-        }
+        });
     }
 
+    #[cfg(not(any(feature = "parallel", feature = "simd")))]
     fn solve_incompressibility(&mut self, over_relaxation: f32, num_iters: u32, dt: f32) {
         let n = self.num_y;
 
         let cp = self.density * self.h / dt;
 
-        for _ in 0..num_iters {
+        let mut residual = 0_f32;
+
+        for iter in 0..num_iters {
+            let last = iter + 1 == num_iters;
+
             // iterate over the interior cells
             for i in 1..self.num_x - 1 {
                 for j in 1..self.num_y - 1 {
@@ -126,6 +179,10 @@ impl Fluid {
                     let div = self.u[(i + 1) * n + j] - self.u[i * n + j] + self.v[i * n + j + 1]
                         - self.v[i * n + j];
 
+                    if last {
+                        residual = residual.max(div.abs());
+                    }
+
                     // distribute the divergence to the neighbors
                     let p = -div / s;
                     // accelerate convergence with over relaxation
@@ -142,6 +199,113 @@ impl Fluid {
                 }
             }
         }
+
+        self.residual = residual / self.h;
+    }
+
+    /// Pressure solve with the cells in red-black (checkerboard) order.
+    ///
+    /// The cells of a color share no face: they are all corrected at once, from
+    /// the velocities left by the other color, column by column (see
+    /// [crate::kernels]). The corrections are then added to the faces, so that
+    /// the result depends neither on the number of threads with the `parallel`
+    /// feature, nor on the SIMD lanes with the `simd` feature.
+    #[cfg(any(feature = "parallel", feature = "simd"))]
+    fn solve_incompressibility(&mut self, over_relaxation: f32, num_iters: u32, dt: f32) {
+        let n = self.num_y;
+        let num_x = self.num_x;
+
+        let cp = self.density * self.h / dt;
+
+        // -over_relaxation / number of neighbors we can exchange flow with, for the
+        // interior fluid cells of each color, 0 elsewhere
+        let mut weights = [vec![0.; num_x * n], vec![0.; num_x * n]];
+        for i in 1..num_x - 1 {
+            for j in 1..n - 1 {
+                let s = &self.s;
+                let sum =
+                    s[(i - 1) * n + j] + s[(i + 1) * n + j] + s[i * n + j - 1] + s[i * n + j + 1];
+                if s[i * n + j] != 0. && sum != 0. {
+                    weights[(i + j) % 2][i * n + j] = -over_relaxation / sum;
+                }
+            }
+        }
+
+        let mut residual = 0_f32;
+        // over-relaxed correction of the cells of the color being solved, 0 elsewhere
+        let mut corrections = vec![0.; num_x * n];
+
+        for iter in 0..num_iters {
+            let last = iter + 1 == num_iters;
+
+            for weights in &weights {
+                let (u, v) = (&self.u, &self.v);
+
+                let relax = |i: usize, p: &mut [f32], correction: &mut [f32]| {
+                    // iterate over the interior columns
+                    if i == 0 || i >= num_x - 1 {
+                        correction.fill(0.);
+                        return 0.;
+                    }
+
+                    let mut relax = Relax {
+                        p,
+                        correction,
+                        u: &u[i * n..(i + 1) * n],
+                        u_next: &u[(i + 1) * n..(i + 2) * n],
+                        v: &v[i * n..(i + 1) * n],
+                        weight: &weights[i * n..(i + 1) * n],
+                        cp,
+                        max_div: last.then_some(0.),
+                    };
+                    kernels::run(&mut relax);
+                    relax.max_div.unwrap_or(0.)
+                };
+
+                #[cfg(feature = "parallel")]
+                let max_div = self
+                    .p
+                    .par_chunks_mut(n)
+                    .zip(corrections.par_chunks_mut(n))
+                    .enumerate()
+                    .map(|(i, (p, correction))| relax(i, p, correction))
+                    .reduce(|| 0., f32::max);
+                #[cfg(not(feature = "parallel"))]
+                let max_div = self
+                    .p
+                    .chunks_mut(n)
+                    .zip(corrections.chunks_mut(n))
+                    .enumerate()
+                    .map(|(i, (p, correction))| relax(i, p, correction))
+                    .fold(0., f32::max);
+
+                residual = residual.max(max_div);
+
+                // correct velocity field: a face is shared with a cell of each color
+                let (corrections, s) = (&corrections, &self.s);
+                for_each_column(&mut self.u, n, |i, u| {
+                    if i == 0 || i >= num_x {
+                        return;
+                    }
+                    kernels::run(&mut CorrectU {
+                        u,
+                        correction_left: &corrections[(i - 1) * n..i * n],
+                        correction: &corrections[i * n..(i + 1) * n],
+                        s_left: &s[(i - 1) * n..i * n],
+                        s: &s[i * n..(i + 1) * n],
+                    });
+                });
+                for_each_column(&mut self.v, n, |i, v| {
+                    kernels::run(&mut CorrectV {
+                        v,
+                        correction: &corrections[i * n..(i + 1) * n],
+                        s: &s[i * n..(i + 1) * n],
+                    });
+                });
+            }
+        }
+
+        self.residual = residual / self.h;
     }
 
     fn extrapolate(&mut self) {
@@ -202,6 +366,24 @@ impl Fluid {
             + sx * ty * f[x0 as usize * n + y1 as usize]
     }
 
+    /// Velocity at (x, y), in physical coordinates.
+    pub(crate) fn velocity(&self, x: f32, y: f32) -> (f32, f32) {
+        (
+            self.sample_field(x, y, Field::U),
+            self.sample_field(x, y, Field::V),
+        )
+    }
+
+    /// Returns true if (x, y) is in an interior cell that is not an obstacle.
+    pub(crate) fn is_fluid(&self, x: f32, y: f32) -> bool {
+        let h = self.h;
+        let inside =
+            x >= h && y >= h && x < (self.num_x - 1) as f32 * h && y < (self.num_y - 1) as f32 * h;
+
+        inside && self.s[(x / h) as usize * self.num_y + (y / h) as usize] != 0.
+    }
+
+    #[cfg(not(any(feature = "parallel", feature = "simd")))]
     fn avg_u(&self, i: usize, j: usize) -> f32 {
         let n = self.num_y;
         (self.u[i * n + j - 1]
@@ -211,6 +393,7 @@ impl Fluid {
             * 0.25
     }
 
+    #[cfg(not(any(feature = "parallel", feature = "simd")))]
     fn avg_v(&self, i: usize, j: usize) -> f32 {
         let n = self.num_y;
         (self.v[(i - 1) * n + j]
@@ -220,207 +403,613 @@ impl Fluid {
             * 0.25
     }
 
+    #[cfg(not(any(feature = "parallel", feature = "simd")))]
     fn advect_velocity(&mut self, dt: f32) {
-        self.new_u = self.u.clone();
-        self.new_v = self.v.clone();
+        let mut new_u = std::mem::take(&mut self.new_u);
+        let mut new_v = std::mem::take(&mut self.new_v);
+        new_u.clone_from(&self.u);
+        new_v.clone_from(&self.v);
 
         let n = self.num_y;
+        let num_x = self.num_x;
         let h = self.h;
         let h2 = 0.5 * h;
+        let this = &*self;
 
-        for i in 1..self.num_x {
-            for j in 1..self.num_y {
-                // u component
-                if self.s[i * n + j] != 0. && self.s[(i - 1) * n + j] != 0. && j < self.num_y - 1 {
+        // u component
+        for_each_column(&mut new_u, n, |i, column| {
+            if i == 0 {
+                return;
+            }
+            for (j, new_u) in column.iter_mut().enumerate().take(n - 1).skip(1) {
+                if this.s[i * n + j] != 0. && this.s[(i - 1) * n + j] != 0. {
                     let x = i as f32 * h;
                     let y = j as f32 * h + h2;
 
-                    let u = self.u[i * n + j];
-                    let v = self.avg_v(i, j);
+                    let u = this.u[i * n + j];
+                    let v = this.avg_v(i, j);
 
                     // backward step
                     let x = x - dt * u;
                     let y = y - dt * v;
 
                     // sample the velocity field
-                    let u = self.sample_field(x, y, Field::U);
+                    let u = this.sample_field(x, y, Field::U);
 
                     // update the velocity field
-                    self.new_u[i * n + j] = u;
+                    *new_u = u;
                 }
+            }
+        });
 
-                // v component
-                if self.s[i * n + j] != 0. && self.s[i * n + j - 1] != 0. && i < self.num_x - 1 {
+        // v component
+        for_each_column(&mut new_v, n, |i, column| {
+            if i == 0 || i >= num_x - 1 {
+                return;
+            }
+            for (j, new_v) in column.iter_mut().enumerate().skip(1) {
+                if this.s[i * n + j] != 0. && this.s[i * n + j - 1] != 0. {
                     let x = i as f32 * h + h2;
                     let y = j as f32 * h;
 
-                    let u = self.avg_u(i, j);
-                    let v = self.v[i * n + j];
+                    let u = this.avg_u(i, j);
+                    let v = this.v[i * n + j];
 
                     // backward step
                     let x = x - dt * u;
                     let y = y - dt * v;
 
                     // sample the velocity field
-                    let v = self.sample_field(x, y, Field::V);
+                    let v = this.sample_field(x, y, Field::V);
 
                     // update the velocity field
-                    self.new_v[i * n + j] = v;
+                    *new_v = v;
                 }
             }
-        }
+        });
 
-        self.u = self.new_u.clone();
-        self.v = self.new_v.clone();
+        self.u.clone_from(&new_u);
+        self.v.clone_from(&new_v);
+        self.new_u = new_u;
+        self.new_v = new_v;
     }
 
+    #[cfg(not(any(feature = "parallel", feature = "simd")))]
     fn advect_smoke(&mut self, dt: f32) {
-        self.new_m = self.m.clone();
+        let mut new_m = std::mem::take(&mut self.new_m);
+        new_m.clone_from(&self.m);
 
         let n = self.num_y;
+        let num_x = self.num_x;
         let h = self.h;
         let h2 = 1.0 / (2.0 * h);
+        let this = &*self;
 
-        for i in 1..self.num_x - 1 {
-            for j in 1..self.num_y - 1 {
-                if self.s[i * n + j] != 0. {
-                    let u = (self.u[i * n + j] + self.u[(i + 1) * n + j]) * 0.5;
-                    let v = (self.v[i * n + j] + self.v[i * n + j + 1]) * 0.5;
+        for_each_column(&mut new_m, n, |i, column| {
+            if i == 0 || i >= num_x - 1 {
+                return;
+            }
+            for (j, new_m) in column.iter_mut().enumerate().take(n - 1).skip(1) {
+                if this.s[i * n + j] != 0. {
+                    let u = (this.u[i * n + j] + this.u[(i + 1) * n + j]) * 0.5;
+                    let v = (this.v[i * n + j] + this.v[i * n + j + 1]) * 0.5;
 
                     let x = i as f32 * h + h2 - dt * u;
                     let y = j as f32 * h + h2 - dt * v;
 
                     // update the velocity field
-                    self.new_m[i * n + j] = self.sample_field(x, y, Field::S);
+                    *new_m = this.sample_field(x, y, Field::S);
                 }
             }
+        });
+
+        self.m.clone_from(&new_m);
+        self.new_m = new_m;
+    }
+
+    /// Advection of the velocity with the column kernels (see [crate::kernels]).
+    #[cfg(any(feature = "parallel", feature = "simd"))]
+    fn advect_velocity(&mut self, dt: f32) {
+        let mut new_u = std::mem::take(&mut self.new_u);
+        let mut new_v = std::mem::take(&mut self.new_v);
+        new_u.clone_from(&self.u);
+        new_v.clone_from(&self.v);
+
+        let n = self.num_y;
+        let num_x = self.num_x;
+        let this = &*self;
+
+        for_each_column(&mut new_u, n, |i, new_u| {
+            if i == 0 {
+                return;
+            }
+            kernels::run(&mut AdvectU {
+                fluid: this,
+                i,
+                dt,
+                new_u,
+            });
+        });
+        for_each_column(&mut new_v, n, |i, new_v| {
+            if i == 0 || i >= num_x - 1 {
+                return;
+            }
+            kernels::run(&mut AdvectV {
+                fluid: this,
+                i,
+                dt,
+                new_v,
+            });
+        });
+
+        self.u.clone_from(&new_u);
+        self.v.clone_from(&new_v);
+        self.new_u = new_u;
+        self.new_v = new_v;
+    }
+
+    /// Advection of the smoke with the column kernels (see [crate::kernels]).
+    #[cfg(any(feature = "parallel", feature = "simd"))]
+    fn advect_smoke(&mut self, dt: f32) {
+        let mut new_m = std::mem::take(&mut self.new_m);
+        new_m.clone_from(&self.m);
+
+        let n = self.num_y;
+        let num_x = self.num_x;
+        let this = &*self;
+
+        for_each_column(&mut new_m, n, |i, new_m| {
+            if i == 0 || i >= num_x - 1 {
+                return;
+            }
+            kernels::run(&mut AdvectSmoke {
+                fluid: this,
+                i,
+                dt,
+                new_m,
+            });
+        });
+
+        self.m.clone_from(&new_m);
+        self.new_m = new_m;
+    }
+
+    /// Return the velocity at the cell centers, averaged from the staggered grid.
+    ///
+    /// Both components use the same `i * num_y + j` layout as the other fields.
+    pub fn cell_centered_velocity(&self) -> (Vec<f32>, Vec<f32>) {
+        let n = self.num_y;
+
+        let mut uc = vec![0.0; self.num_x * self.num_y];
+        let mut vc = vec![0.0; self.num_x * self.num_y];
+
+        for i in 0..self.num_x {
+            for j in 0..self.num_y {
+                // the last column and row have no face on their far side
+                let u1 = if i + 1 < self.num_x {
+                    self.u[(i + 1) * n + j]
+                } else {
+                    self.u[i * n + j]
+                };
+                let v1 = if j + 1 < self.num_y {
+                    self.v[i * n + j + 1]
+                } else {
+                    self.v[i * n + j]
+                };
+
+                uc[i * n + j] = 0.5 * (self.u[i * n + j] + u1);
+                vc[i * n + j] = 0.5 * (self.v[i * n + j] + v1);
+            }
         }
 
-        self.m = self.new_m.clone();
+        (uc, vc)
     }
 
-    /// Return the pressure field
-    pub fn pressure(&self) -> Vec<f32> {
-        self.p.clone()
+    /// Return `quantity` at the cell centers, in the `i * num_y + j` layout.
+    pub fn scalar_field(&self, quantity: Quantity) -> Vec<f32> {
+        match quantity {
+            Quantity::Pressure => self.p.clone(),
+            Quantity::Speed => self.speed(),
+            Quantity::U => self.cell_centered_velocity().0,
+            Quantity::V => self.cell_centered_velocity().1,
+            Quantity::Vorticity => self.vorticity(),
+            Quantity::Divergence => self.divergence(),
+            Quantity::Smoke => self.m.clone(),
+        }
     }
 
-    /// Render the simulation on the given canvas.
-    pub fn render(
+    /// Return the range of `field` over the interior fluid cells.
+    pub(crate) fn field_range(&self, field: &[f32]) -> (f32, f32) {
+        let n = self.num_y;
+
+        let mut range = (f32::MAX, f32::MIN);
+        for i in 1..self.num_x - 1 {
+            for j in 1..self.num_y - 1 {
+                if self.s[i * n + j] != 0. {
+                    let x = field[i * n + j];
+                    range = (range.0.min(x), range.1.max(x));
+                }
+            }
+        }
+
+        range
+    }
+
+    /// Trace the streamlines, as polylines in physical coordinates.
+    ///
+    /// See [streamlines::streamlines].
+    pub fn streamlines(&self, options: &DrawOptions) -> Vec<Vec<(f32, f32)>> {
+        let streamline_options = StreamlineOptions {
+            spacing: options.streamlines_spacing,
+            step: options.streamlines_step,
+            max_length: options.streamlines_max_length,
+            min_speed: options.streamlines_min_speed,
+            seeds: options.streamlines_seeds.clone(),
+        };
+
+        streamlines::streamlines(self, &streamline_options)
+    }
+
+    /// Return the range of the colormap for `field`.
+    ///
+    /// `previous` is the range used for the previous frame, which
+    /// [ColorRange::Smoothed] moves from.
+    pub fn color_range(
         &self,
-        options: DrawOptions,
-        dt: f32,
-        sim_to_canvas_ratio: u32,
-        ctx: &CanvasRenderingContext2d,
-    ) -> Result<(), JsValue> {
-        // let _timer = Timer::new("Fluid::draw");
+        field: &[f32],
+        mode: ColorRange,
+        previous: Option<(f32, f32)>,
+    ) -> (f32, f32) {
+        match mode {
+            ColorRange::Auto => self.field_range(field),
+            ColorRange::Fixed { min, max } => (min, max),
+            ColorRange::Symmetric => {
+                let (min, max) = self.field_range(field);
+                let m = min.abs().max(max.abs());
+                (-m, m)
+            }
+            ColorRange::Percentile { percent } => {
+                let n = self.num_y;
+
+                let mut values = vec![];
+                for i in 1..self.num_x - 1 {
+                    for j in 1..self.num_y - 1 {
+                        if self.s[i * n + j] != 0. {
+                            values.push(field[i * n + j]);
+                        }
+                    }
+                }
+                if values.is_empty() {
+                    return self.field_range(field);
+                }
 
-        let data = self.pressure();
+                let last = values.len() - 1;
+                let k = ((percent.clamp(0., 50.) / 100. * last as f32) as usize).min(last / 2);
 
-        let (min_p, max_p) = data.iter().fold((f32::MAX, f32::MIN), |(min, max), &x| {
-            (min.min(x), max.max(x))
-        });
+                let (_, &mut min, _) = values.select_nth_unstable_by(k, f32::total_cmp);
+                let (_, &mut max, _) = values.select_nth_unstable_by(last - k, f32::total_cmp);
+                (min, max)
+            }
+            ColorRange::Smoothed { rate } => {
+                let (min, max) = self.field_range(field);
+                match previous {
+                    Some((previous_min, previous_max)) => {
+                        let rate = rate.clamp(0., 1.);
+                        (
+                            previous_min + (min - previous_min) * rate,
+                            previous_max + (max - previous_max) * rate,
+                        )
+                    }
+                    None => (min, max),
+                }
+            }
+        }
+    }
+
+    /// Trace `count` iso-lines of a cell-centered field (see [Fluid::scalar_field]),
+    /// as segments in physical coordinates.
+    ///
+    /// The levels are evenly spaced inside `(min, max)`.
+    pub fn contours(
+        &self,
+        field: &[f32],
+        (min, max): (f32, f32),
+        count: usize,
+    ) -> Vec<[(f32, f32); 2]> {
+        let h = self.h;
+        let field = ScalarField::new(field, self.num_x, self.num_y);
+
+        // cell centers to real world coordinates
+        let to_real = |(x, y): (f32, f32)| ((x + 0.5) * h, (y + 0.5) * h);
+
+        let mut segments = vec![];
+        for k in 0..count {
+            let level = min + (max - min) * (k + 1) as f32 / (count + 1) as f32;
+
+            let skip = |i, j| self.s[i * self.num_y + j] == 0.;
+            for [a, b] in visualization::contour_segments(&field, level, skip) {
+                segments.push([to_real(a), to_real(b)]);
+            }
+        }
 
+        segments
+    }
+
+    /// Render a cell-centered field, the obstacles and the lines to an image.
+    ///
+    /// The image covers the interior cells, with y pointing up: the first row of
+    /// the image is the top of the domain (`j = num_y - 2`), as drawn by WebGL
+    /// and in the exported images. The LIC texture is taken from `lic`, and only
+    /// computed if it is outdated.
+    pub(crate) fn render_image(
+        &self,
+        options: &DrawOptions,
+        colormap: &dyn visualization::Colormap,
+        field: &[f32],
+        (min, max): (f32, f32),
+        sim_to_canvas_ratio: u32,
+        lic: &mut LicCache,
+    ) -> visualization::Image {
         let n = self.num_y;
 
         let mut image =
             visualization::Image::new(self.num_x - 2, self.num_y - 2, sim_to_canvas_ratio as usize);
 
-        // pressure
-        if options.pressure {
-            let colormap: Box<dyn visualization::Colormap> =
-                visualization::colormap(options.colormap.as_str());
+        // image rows go down, y goes up
+        let row = |j: usize| self.num_y - 2 - j;
 
+        if options.interpolation != Interpolation::Nearest {
+            self.paint_smooth(&mut image, options, colormap, field, (min, max));
+        }
+        // field
+        else if options.pressure {
             for i in 1..self.num_x - 1 {
                 for j in 1..self.num_y - 1 {
-                    let p = self.p[i * n + j];
-                    let color = colormap.get_color(p, min_p, max_p);
+                    let x = field[i * n + j];
+                    let color = colormap.get_color(x, min, max);
 
-                    image.paint(i - 1, j - 1, color);
+                    image.paint(i - 1, row(j), color);
                 }
             }
         }
 
         // draw the obstacle
-        if options.obstacle {
+        if options.obstacle && options.interpolation == Interpolation::Nearest {
             for i in 1..self.num_x - 1 {
                 for j in 1..self.num_y - 1 {
                     if self.s[i * n + j] == 0. {
                         let color = [0, 0, 0, 255];
 
-                        image.paint(i - 1, j - 1, color);
+                        image.paint(i - 1, row(j), color);
                     }
                 }
             }
         }
 
-        let (_width, height) = image.size();
-
-        let data = image.try_into()?;
-        let r = ctx.put_image_data(&data, 0.0, 0.0);
-
-        let text = format!(
-            "min: {:>8.1}\tmax: {:>8.1}\t{:>8.1} fps\tin_vel: {:>4.2}\t gravity: {:>4.2}",
-            min_p,
-            max_p,
-            1. / dt,
-            self.in_vel,
-            self.gravity
-        );
+        // flow texture
+        if options.lic {
+            let lic_options = LicOptions {
+                length: options.lic_length,
+                resolution: (sim_to_canvas_ratio as usize).clamp(1, 4),
+                phase: options.lic_phase,
+            };
+            lic::apply(lic.get(self, &lic_options).0, &mut image);
+        }
 
-        let _ = ctx.fill_text(&text, 12., 12.);
+        // draw the iso-lines
+        if options.contours > 0 {
+            for [a, b] in self.contours(field, (min, max), options.contours) {
+                let (a, b) = (self.to_image(&image, a), self.to_image(&image, b));
+                image.draw_line(a, b, [0, 0, 0, 180]);
+            }
+        }
 
         // draw stream line
         if options.streamlines {
-            let h = self.h;
-            let h2 = 0.5 * h;
+            for line in self.streamlines(options) {
+                for segment in line.windows(2) {
+                    let a = self.to_image(&image, segment[0]);
+                    let b = self.to_image(&image, segment[1]);
+                    image.draw_line(a, b, [255, 0, 0, 255]);
+                }
+            }
+        }
 
-            let real_to_canvas = sim_to_canvas_ratio as f64 / self.h as f64;
+        if options.quiver {
+            self.draw_quiver(&mut image, options, colormap);
+        }
 
-            let seg_len = 0.01;
+        image
+    }
 
-            ctx.set_stroke_style(&"rgba(255, 0, 0, 1.)".into());
-            ctx.set_line_width(0.8);
+    /// Convert real world coordinates to pixels of an image of the interior cells.
+    pub(crate) fn to_image(&self, image: &visualization::Image, (x, y): (f32, f32)) -> (f32, f32) {
+        let r = image.resolution() as f32;
+        let (_width, height) = image.size();
 
-            for i in (1..self.num_x - 1).step_by(options.streamlines_spacing) {
-                for j in (1..self.num_y - 1).step_by(options.streamlines_spacing) {
-                    // center of the cell - real world coordinates
-                    let mut x = i as f32 * h + h2;
-                    let mut y = j as f32 * h + h2;
+        // the image starts at the first interior cell, y goes up
+        ((x / self.h - 1.) * r, height as f32 - (y / self.h - 1.) * r)
+    }
 
-                    // center of the cell - canvas coordinates
-                    let cx = x as f64 * real_to_canvas;
-                    let cy = height as f64 - y as f64 * real_to_canvas;
-                    ctx.begin_path();
-                    ctx.move_to(cx, cy);
+    /// Return the velocity at the center of every `spacing`-th fluid cell, as
+    /// (position, velocity) in physical units.
+    pub fn quiver(&self, spacing: usize) -> Vec<((f32, f32), (f32, f32))> {
+        let n = self.num_y;
+        let h = self.h;
+        let spacing = spacing.max(1);
+        let (uc, vc) = self.cell_centered_velocity();
 
-                    for _k in 0..options.streamlines_num_segs {
-                        let u = self.sample_field(x, y, Field::U);
-                        let v = self.sample_field(x, y, Field::V);
+        let mut arrows = vec![];
 
-                        let l = (u * u + v * v).sqrt();
+        // centered in the domain
+        let offset_x = 1 + (self.num_x - 2 - 1) % spacing / 2;
+        let offset_y = 1 + (self.num_y - 2 - 1) % spacing / 2;
 
-                        // next point - real world coordinates
-                        x += u / l * seg_len;
-                        y += v / l * seg_len;
+        for i in (offset_x..self.num_x - 1).step_by(spacing) {
+            for j in (offset_y..self.num_y - 1).step_by(spacing) {
+                if self.s[i * n + j] == 0. {
+                    continue;
+                }
 
-                        if x > self.num_x as f32 * h {
-                            break;
-                        }
+                let position = ((i as f32 + 0.5) * h, (j as f32 + 0.5) * h);
+                arrows.push((position, (uc[i * n + j], vc[i * n + j])));
+            }
+        }
 
-                        let cx = x as f64 * real_to_canvas;
-                        let cy = height as f64 - y as f64 * real_to_canvas;
-                        ctx.line_to(cx, cy);
-                    }
-                    ctx.stroke();
+        arrows
+    }
+
+    /// Arrows of the quiver plot, as (from, to, speed) in physical units, with
+    /// the largest speed; `None` if the fluid is at rest.
+    ///
+    /// The longest arrow spans `options.quiver_scale * options.quiver_spacing` cells.
+    pub(crate) fn quiver_arrows(&self, options: &DrawOptions) -> Option<(Vec<Arrow>, f32)> {
+        let arrows = self.quiver(options.quiver_spacing);
+
+        let speed = |(u, v): (f32, f32)| (u * u + v * v).sqrt();
+        let max_speed = arrows
+            .iter()
+            .map(|&(_, velocity)| speed(velocity))
+            .fold(0., f32::max);
+        if max_speed == 0. || !max_speed.is_finite() {
+            return None;
+        }
+
+        let length =
+            options.quiver_scale * options.quiver_spacing.max(1) as f32 * self.h / max_speed;
+
+        let arrows = arrows
+            .into_iter()
+            .map(|((x, y), (u, v))| ((x, y), (x + u * length, y + v * length), speed((u, v))))
+            .collect();
+        Some((arrows, max_speed))
+    }
+
+    /// Draw velocity arrows, scaled and colored by speed.
+    pub(crate) fn draw_quiver(
+        &self,
+        image: &mut visualization::Image,
+        options: &DrawOptions,
+        colormap: &dyn visualization::Colormap,
+    ) {
+        let Some((arrows, max_speed)) = self.quiver_arrows(options) else {
+            return;
+        };
+
+        for (from, to, speed) in arrows {
+            let from = self.to_image(image, from);
+            let to = self.to_image(image, to);
+            let color = colormap.get_color(speed, 0., max_speed);
+
+            // a shadow keeps the arrows visible over the field
+            let shadow = |(x, y): (f32, f32)| (x + 1., y + 1.);
+            image.draw_arrow(shadow(from), shadow(to), [0, 0, 0, 140]);
+            image.draw_arrow(from, to, color);
+        }
+    }
+
+    /// Paint a cell-centered field and the obstacles, sampled at each pixel.
+    fn paint_smooth(
+        &self,
+        image: &mut visualization::Image,
+        options: &DrawOptions,
+        colormap: &dyn visualization::Colormap,
+        field: &[f32],
+        (min, max): (f32, f32),
+    ) {
+        let field = ScalarField::new(field, self.num_x, self.num_y);
+        let solid = ScalarField::new(&self.s, self.num_x, self.num_y);
+
+        let r = image.resolution() as f32;
+        let (width, height) = image.size();
+
+        for py in 0..height {
+            // pixel center to cell coordinates, y pointing up
+            let y = self.num_y as f32 - 1.5 - (py as f32 + 0.5) / r;
+
+            for px in 0..width {
+                let x = (px as f32 + 0.5) / r + 0.5;
+
+                // the obstacle outline is always bilinear: bicubic would overshoot
+                if options.obstacle && solid.sample(x, y, Interpolation::Bilinear) < 0.5 {
+                    image.put_pixel(px, py, [0, 0, 0, 255]);
+                } else if options.pressure {
+                    let value = field.sample(x, y, options.interpolation);
+                    image.put_pixel(px, py, colormap.get_color(value, min, max));
                 }
             }
         }
+    }
+
+    /// Render the simulation to an image, as it is drawn on the canvas.
+    ///
+    /// `field` is the field selected by `options` (see [Fluid::scalar_field]) and
+    /// `range` the range of the colormap (see [Fluid::color_range]). The frame
+    /// rate shown in the HUD is `fps`, if any. The LIC texture is kept in `lic`
+    /// for the next frames.
+    #[allow(clippy::too_many_arguments)]
+    pub fn render_frame(
+        &self,
+        options: &DrawOptions,
+        field: &[f32],
+        range: (f32, f32),
+        fps: Option<f32>,
+        sim_to_canvas_ratio: u32,
+        particles: &Particles,
+        flowlines: &Flowlines,
+        lic: &mut LicCache,
+    ) -> Result<visualization::Image, String> {
+        let colormap = visualization::colormap(&options.colormap)?;
+
+        let mut image =
+            self.render_image(options, &*colormap, field, range, sim_to_canvas_ratio, lic);
+        flowlines.draw(self, &mut image);
+        particles.draw(self, &mut image, options.particle_style);
+        overlay::draw(self, &mut image, options, &*colormap, range, fps);
 
-        r
+        Ok(image)
+    }
+
+    /// Render the simulation on the given canvas.
+    ///
+    /// See [Fluid::render_frame].
+    #[allow(clippy::too_many_arguments)]
+    pub fn render(
+        &self,
+        options: &DrawOptions,
+        field: &[f32],
+        range: (f32, f32),
+        dt: f32,
+        sim_to_canvas_ratio: u32,
+        particles: &Particles,
+        flowlines: &Flowlines,
+        lic: &mut LicCache,
+        ctx: &CanvasRenderingContext2d,
+    ) -> Result<(), JsValue> {
+        // let _timer = Timer::new("Fluid::draw");
+
+        let image = self
+            .render_frame(
+                options,
+                field,
+                range,
+                Some(1. / dt),
+                sim_to_canvas_ratio,
+                particles,
+                flowlines,
+                lic,
+            )
+            .map_err(|e| JsValue::from_str(&e))?;
+
+        let data = image.try_into()?;
+        ctx.put_image_data(&data, 0.0, 0.0)
     }
 
     /// Simulate the fluid for the given time step.
     pub fn simulate(&mut self, dt: f32, num_iters: u32, over_relaxation: f32) {
+        self.num_iters = num_iters;
+        self.over_relaxation = over_relaxation;
+
         self.integrate(dt, self.gravity);
 
         self.p.fill(0.);
@@ -429,6 +1018,9 @@ impl Fluid {
         self.extrapolate();
         self.advect_velocity(dt);
         self.advect_smoke(dt);
+
+        self.step += 1;
+        self.time += dt as f64;
     }
 
     pub fn add_obstacle(&mut self, obstacle: impl Obstacle) {
@@ -634,6 +1226,11 @@ impl Fluid {
             s,
             m,
             new_m,
+            num_iters: 0,
+            over_relaxation: 1.0,
+            step: 0,
+            time: 0.0,
+            residual: 0.0,
         }
     }
 
@@ -643,15 +1240,363 @@ impl Fluid {
 
         self.s.fill(FLUID);
     }
+
+    /// Number of cells in the x-direction (including the 2 border cells)
+    pub fn num_x(&self) -> usize {
+        self.num_x
+    }
+
+    /// Number of cells in the y-direction (including the 2 border cells)
+    pub fn num_y(&self) -> usize {
+        self.num_y
+    }
+
+    /// Cell size (in meters)
+    pub fn h(&self) -> f32 {
+        self.h
+    }
+
+    /// Number of steps simulated so far
+    pub fn step_count(&self) -> u64 {
+        self.step
+    }
+
+    /// Simulated time (in seconds)
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    /// Largest divergence (in 1/s) left by the pressure solver at the last step
+    pub fn residual(&self) -> f32 {
+        self.residual
+    }
+
+    /// Reynolds number of the flow around the obstacles, for the kinematic
+    /// viscosity `viscosity` (in m²/s).
+    ///
+    /// The characteristic length is the height of the obstacles, or of the
+    /// domain if there is none.
+    pub fn reynolds_number(&self, viscosity: f32) -> f32 {
+        let n = self.num_y;
+
+        let rows = (1..self.num_y - 1)
+            .filter(|&j| (1..self.num_x - 1).any(|i| self.s[i * n + j] == 0.))
+            .count();
+        let rows = if rows == 0 { self.num_y - 2 } else { rows };
+
+        self.in_vel.abs() * rows as f32 * self.h / viscosity
+    }
+
+    /// Number of values in each field (`num_x * num_y`)
+    pub fn field_len(&self) -> usize {
+        self.num_x * self.num_y
+    }
+
+    /// Pointer to the x-component of the (staggered) velocity in wasm memory,
+    /// [Fluid::field_len] values.
+    ///
+    /// The steps update the fields in place, so the pointer stays valid as long
+    /// as this fluid is alive. A typed array built on it over `memory.buffer`
+    /// must still be rebuilt whenever the wasm memory grows, as for
+    /// [Fluid::u_view].
+    pub fn u_ptr(&self) -> *const f32 {
+        self.u.as_ptr()
+    }
+
+    /// Pointer to the y-component of the (staggered) velocity in wasm memory.
+    ///
+    /// See [Fluid::u_ptr] for how long the pointer stays valid.
+    pub fn v_ptr(&self) -> *const f32 {
+        self.v.as_ptr()
+    }
+
+    /// Pointer to the pressure field in wasm memory.
+    ///
+    /// See [Fluid::u_ptr] for how long the pointer stays valid.
+    pub fn p_ptr(&self) -> *const f32 {
+        self.p.as_ptr()
+    }
+
+    /// Pointer to the obstacle field in wasm memory.
+    ///
+    /// See [Fluid::u_ptr] for how long the pointer stays valid.
+    pub fn s_ptr(&self) -> *const f32 {
+        self.s.as_ptr()
+    }
+
+    /// Pointer to the smoke field in wasm memory.
+    ///
+    /// See [Fluid::u_ptr] for how long the pointer stays valid.
+    pub fn m_ptr(&self) -> *const f32 {
+        self.m.as_ptr()
+    }
+
+    /// Zero-copy view of the x-component of the (staggered) velocity.
+    ///
+    /// The view is only valid until the next call into the module: simulating,
+    /// restoring or any allocation that grows the wasm memory invalidates it.
+    pub fn u_view(&self) -> js_sys::Float32Array {
+        // SAFETY: the view is handed to JS with the documented lifetime caveat
+        unsafe { js_sys::Float32Array::view(&self.u) }
+    }
+
+    /// Zero-copy view of the y-component of the (staggered) velocity.
+    ///
+    /// See [Fluid::u_view] for how long the view stays valid.
+    pub fn v_view(&self) -> js_sys::Float32Array {
+        // SAFETY: see u_view
+        unsafe { js_sys::Float32Array::view(&self.v) }
+    }
+
+    /// Zero-copy view of the pressure field.
+    ///
+    /// See [Fluid::u_view] for how long the view stays valid.
+    pub fn p_view(&self) -> js_sys::Float32Array {
+        // SAFETY: see u_view
+        unsafe { js_sys::Float32Array::view(&self.p) }
+    }
+
+    /// Zero-copy view of the obstacle field.
+    ///
+    /// See [Fluid::u_view] for how long the view stays valid.
+    pub fn s_view(&self) -> js_sys::Float32Array {
+        // SAFETY: see u_view
+        unsafe { js_sys::Float32Array::view(&self.s) }
+    }
+
+    /// Zero-copy view of the smoke field.
+    ///
+    /// See [Fluid::u_view] for how long the view stays valid.
+    pub fn m_view(&self) -> js_sys::Float32Array {
+        // SAFETY: see u_view
+        unsafe { js_sys::Float32Array::view(&self.m) }
+    }
+
+    /// Return the pressure field
+    pub fn pressure(&self) -> Vec<f32> {
+        self.p.clone()
+    }
+
+    /// Return the x-component of the velocity at the cell centers
+    pub fn cell_u(&self) -> Vec<f32> {
+        self.cell_centered_velocity().0
+    }
+
+    /// Return the y-component of the velocity at the cell centers
+    pub fn cell_v(&self) -> Vec<f32> {
+        self.cell_centered_velocity().1
+    }
+
+    /// Return the speed |u| at the cell centers
+    pub fn speed(&self) -> Vec<f32> {
+        let (uc, vc) = self.cell_centered_velocity();
+
+        uc.iter()
+            .zip(vc.iter())
+            .map(|(u, v)| (u * u + v * v).sqrt())
+            .collect()
+    }
+
+    /// Return the vorticity (curl of the velocity) at the cell centers.
+    ///
+    /// Border cells are set to 0.
+    pub fn vorticity(&self) -> Vec<f32> {
+        let n = self.num_y;
+        let h1 = 1.0 / (2.0 * self.h);
+
+        let (uc, vc) = self.cell_centered_velocity();
+
+        let mut w = vec![0.0; self.num_x * self.num_y];
+
+        for i in 1..self.num_x - 1 {
+            for j in 1..self.num_y - 1 {
+                let dv_dx = (vc[(i + 1) * n + j] - vc[(i - 1) * n + j]) * h1;
+                let du_dy = (uc[i * n + j + 1] - uc[i * n + j - 1]) * h1;
+
+                w[i * n + j] = dv_dx - du_dy;
+            }
+        }
+
+        w
+    }
+
+    /// Return the divergence of the velocity at the cell centers.
+    ///
+    /// The solver drives it to 0: what is left shows how well it converged.
+    /// Border and obstacle cells are set to 0.
+    pub fn divergence(&self) -> Vec<f32> {
+        let n = self.num_y;
+        let h1 = 1.0 / self.h;
+
+        let mut d = vec![0.0; self.num_x * self.num_y];
+
+        for i in 1..self.num_x - 1 {
+            for j in 1..self.num_y - 1 {
+                if self.s[i * n + j] == 0. {
+                    continue;
+                }
+
+                d[i * n + j] = (self.u[(i + 1) * n + j] - self.u[i * n + j]
+                    + self.v[i * n + j + 1]
+                    - self.v[i * n + j])
+                    * h1;
+            }
+        }
+
+        d
+    }
+}
+
+/// A scalar quantity that can be drawn, computed at the cell centers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Quantity {
+    Pressure,
+    /// Velocity magnitude |u|
+    Speed,
+    /// x-component of the velocity
+    U,
+    /// y-component of the velocity
+    V,
+    /// Curl of the velocity
+    Vorticity,
+    /// Divergence of the velocity, to check the pressure solver
+    Divergence,
+    Smoke,
+}
+
+impl FromStr for Quantity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pressure" => Ok(Quantity::Pressure),
+            "speed" => Ok(Quantity::Speed),
+            "u" => Ok(Quantity::U),
+            "v" => Ok(Quantity::V),
+            "vorticity" => Ok(Quantity::Vorticity),
+            "divergence" => Ok(Quantity::Divergence),
+            "smoke" => Ok(Quantity::Smoke),
+            _ => Err(format!("unknown field: {}", s)),
+        }
+    }
+}
+
+impl Quantity {
+    /// Unit of the quantity, empty if it has none
+    pub fn unit(&self) -> &'static str {
+        match self {
+            Quantity::Pressure => "Pa",
+            Quantity::Speed | Quantity::U | Quantity::V => "m/s",
+            Quantity::Vorticity | Quantity::Divergence => "1/s",
+            Quantity::Smoke => "",
+        }
+    }
 }
 
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Quantity::Pressure => "pressure",
+            Quantity::Speed => "speed",
+            Quantity::U => "u",
+            Quantity::V => "v",
+            Quantity::Vorticity => "vorticity",
+            Quantity::Divergence => "divergence",
+            Quantity::Smoke => "smoke",
+        };
+        f.write_str(name)
+    }
+}
+
+/// An arrow of the quiver plot: from, to (in m) and speed (in m/s)
+pub(crate) type Arrow = ((f32, f32), (f32, f32), f32);
+
+#[derive(Clone)]
 pub struct DrawOptions {
+    /// Draw the scalar field selected by `field`
     pub pressure: bool,
+    /// The scalar field to draw
+    pub field: Quantity,
     pub obstacle: bool,
     pub streamlines: bool,
-    pub streamlines_spacing: usize,
-    pub streamlines_num_segs: usize,
+    /// Distance between the evenly-spaced streamlines (in m)
+    pub streamlines_spacing: f32,
+    /// Distance between the points of a streamline (in m)
+    pub streamlines_step: f32,
+    /// Maximum length of a streamline (in m)
+    pub streamlines_max_length: f32,
+    /// Speed (in m/s) below which a streamline stops
+    pub streamlines_min_speed: f32,
+    /// Seeds of the streamlines; evenly spaced if empty
+    pub streamlines_seeds: Vec<SeedLine>,
     pub colormap: String,
+    /// How the field is sampled between the cell centers
+    pub interpolation: Interpolation,
+    /// Number of iso-lines of the field (0 => none)
+    pub contours: usize,
+    /// Draw a colorbar for the field
+    pub colorbar: bool,
+    /// Draw the axes, in meters
+    pub axes: bool,
+    /// Draw the step, time, solver residual and Reynolds number
+    pub hud: bool,
+    /// How the range of the colormap is chosen
+    pub color_range: ColorRange,
+    /// Draw velocity arrows
+    pub quiver: bool,
+    /// Number of cells between the arrows
+    pub quiver_spacing: usize,
+    /// Length of the longest arrow, relative to `quiver_spacing`
+    pub quiver_scale: f32,
+    /// Draw a line integral convolution of the velocity over the field
+    pub lic: bool,
+    /// Length of the LIC kernel on each side (in cells)
+    pub lic_length: f32,
+    /// Phase of the animated LIC kernel (`None` => not animated)
+    pub lic_phase: Option<f32>,
+    /// How the tracer particles are drawn
+    pub particle_style: ParticleStyle,
+    /// Kinematic viscosity (in m²/s) for the Reynolds number in the HUD
+    ///
+    /// The solver itself is inviscid.
+    pub viscosity: f32,
+}
+
+impl DrawOptions {
+    /// The options the web page starts with: the pressure in the `jet`
+    /// colormap, the obstacle, evenly-spaced streamlines, the colorbar and the HUD.
+    pub fn new(fluid: &Fluid) -> Self {
+        let h = fluid.h;
+        let width = fluid.num_x as f32 * h;
+
+        DrawOptions {
+            pressure: true,
+            field: Quantity::Pressure,
+            obstacle: true,
+            streamlines: true,
+            streamlines_spacing: 6. * h,
+            streamlines_step: 0.5 * h,
+            streamlines_max_length: width,
+            streamlines_min_speed: 1.0e-3,
+            streamlines_seeds: vec![],
+            colormap: "jet".to_string(),
+            interpolation: Interpolation::Nearest,
+            contours: 0,
+            colorbar: true,
+            axes: false,
+            hud: true,
+            // water
+            viscosity: 1.0e-6,
+            color_range: ColorRange::Auto,
+            quiver: false,
+            quiver_spacing: 8,
+            quiver_scale: 1.,
+            lic: false,
+            lic_length: 10.,
+            lic_phase: None,
+            particle_style: ParticleStyle::Dots,
+        }
+    }
 }
 
 /// Obstacle type
@@ -673,6 +1618,29 @@ impl From<ObstacleType> for Box<dyn Obstacle> {
     }
 }
 
+/// Scenarios known by [apply_scenario]
+pub const SCENARIOS: [&str; 2] = ["cylindrical", "rectangular"];
+
+/// Reset the fluid to the given scenario.
+pub fn apply_scenario(fluid: &mut Fluid, scenario: &str, in_vel: f32) {
+    let obstacle = match scenario {
+        "rectangular" => ObstacleType::Rectangular {
+            x: 0.2,
+            y: 0.5,
+            w: 0.1,
+            h: 0.3,
+        },
+        _ => ObstacleType::Circular {
+            x: 0.5,
+            y: 0.5,
+            r: 0.2,
+        },
+    };
+
+    fluid.clear_obstacles();
+    fluid.vortex_shedding(in_vel, vec![obstacle]);
+}
+
 /// Obstacle
 pub trait Obstacle {
     /// Return true if the given point is inside the obstacle
@@ -731,3 +1699,105 @@ impl Obstacle for CircularObstacle {
         d < self.r
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields_stay_in_place() {
+        let mut fluid = Fluid::create(0., 1.5, 20, 10, 0.01, 1000.);
+        apply_scenario(&mut fluid, "cylindrical", 1.5);
+
+        let pointers = [
+            fluid.u_ptr(),
+            fluid.v_ptr(),
+            fluid.p_ptr(),
+            fluid.s_ptr(),
+            fluid.m_ptr(),
+        ];
+        for _ in 0..3 {
+            fluid.simulate(1. / 60., 10, 1.9);
+        }
+        assert_eq!(
+            [
+                fluid.u_ptr(),
+                fluid.v_ptr(),
+                fluid.p_ptr(),
+                fluid.s_ptr(),
+                fluid.m_ptr(),
+            ],
+            pointers
+        );
+    }
+
+    #[test]
+    fn image_rows_from_the_top() {
+        let mut fluid = Fluid::create(0., 0., 4, 3, 0.01, 1000.);
+        // an obstacle in the top row of interior cells only
+        let n = fluid.num_y;
+        fluid.s.fill(1.);
+        for i in 1..fluid.num_x - 1 {
+            fluid.s[i * n + n - 2] = 0.;
+        }
+
+        let mut options = DrawOptions::new(&fluid);
+        options.pressure = false;
+        options.obstacle = true;
+        options.interpolation = Interpolation::Nearest;
+        let colormap = visualization::colormap(&options.colormap).unwrap();
+        let field = fluid.scalar_field(options.field);
+        let image = fluid.render_image(
+            &options,
+            &*colormap,
+            &field,
+            (0., 1.),
+            1,
+            &mut LicCache::default(),
+        );
+
+        assert_eq!(image.size(), (4, 3));
+        for x in 0..4 {
+            assert_eq!(image.pixel(x, 0), [0, 0, 0, 255]);
+            assert_eq!(image.pixel(x, 2)[3], 0);
+        }
+    }
+
+    /// Run a few steps on a rayon pool of `threads` threads.
+    #[cfg(feature = "parallel")]
+    fn run(threads: usize) -> Fluid {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap();
+
+        pool.install(|| {
+            // an odd number of columns, not a multiple of the number of threads
+            let mut fluid = Fluid::create(0., 1.5, 97, 51, 0.01, 1000.);
+            apply_scenario(&mut fluid, "cylindrical", 1.5);
+            for _ in 0..20 {
+                fluid.simulate(1. / 60., 40, 1.9);
+            }
+            fluid
+        })
+    }
+
+    #[cfg(feature = "parallel")]
+    fn bits(field: &[f32]) -> Vec<u32> {
+        field.iter().map(|x| x.to_bits()).collect()
+    }
+
+    #[test]
+    #[cfg(feature = "parallel")]
+    fn independent_of_thread_count() {
+        let reference = run(1);
+        for threads in [3, 8] {
+            let fluid = run(threads);
+            assert_eq!(bits(&fluid.u), bits(&reference.u));
+            assert_eq!(bits(&fluid.v), bits(&reference.v));
+            assert_eq!(bits(&fluid.p), bits(&reference.p));
+            assert_eq!(bits(&fluid.m), bits(&reference.m));
+            assert_eq!(fluid.residual.to_bits(), reference.residual.to_bits());
+        }
+    }
+}
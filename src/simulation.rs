@@ -0,0 +1,1357 @@
+//! A running simulation driven from JS
+//!
+//! [Simulation] owns the [Fluid], the canvas it renders to and the
+//! `requestAnimationFrame` loop. The embedding page drives it through
+//! explicit setters and gets notified through callbacks registered with
+//! [Simulation::on], so it can be used with any UI framework.
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::str::FromStr;
+
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement};
+use web_time::Instant;
+
+use crate::export::animation::{AnimationFormat, FrameRecorder};
+use crate::export::svg;
+use crate::flowlines::Flowlines;
+use crate::lic::LicCache;
+use crate::particles::{Emitter, Inertia, Integrator, ParticleStyle, Particles, Source, Wall};
+use crate::replay::{Precision, Recorder};
+use crate::simu::{apply_scenario, DrawOptions, Fluid, Quantity, SCENARIOS};
+use crate::streamlines::SeedLine;
+use crate::visualization::{self, ColorRange, Image, Interpolation};
+use crate::webgl::WebGlRenderer;
+use crate::worker::SolverWorker;
+
+fn window() -> web_sys::Window {
+    web_sys::window().expect("no global `window` exists")
+}
+
+fn request_animation_frame(f: &Closure<dyn FnMut()>) -> i32 {
+    window()
+        .request_animation_frame(f.as_ref().unchecked_ref())
+        .expect("should register `requestAnimationFrame` OK")
+}
+
+/// Return true if `target` takes the keys typed in it: a form field or an
+/// editable element.
+fn is_editable(target: &web_sys::EventTarget) -> bool {
+    let Some(element) = target.dyn_ref::<web_sys::HtmlElement>() else {
+        return false;
+    };
+
+    matches!(
+        element.tag_name().to_ascii_lowercase().as_str(),
+        "input" | "textarea" | "select"
+    ) || element.is_content_editable()
+}
+
+/// How the simulation is drawn on the canvas
+enum Renderer {
+    Canvas2d(CanvasRenderingContext2d),
+    WebGl2(Box<WebGlRenderer>),
+}
+
+impl Renderer {
+    /// Create the renderer `name` (`2d` or `webgl2`) for `canvas`.
+    ///
+    /// Falls back to `2d` when WebGL2 is not available.
+    fn new(canvas: &HtmlCanvasElement, name: &str) -> Result<Renderer, JsValue> {
+        match name {
+            "2d" => {}
+            "webgl2" => match WebGlRenderer::new(canvas) {
+                Ok(renderer) => return Ok(Renderer::WebGl2(Box::new(renderer))),
+                Err(e) => web_sys::console::warn_2(&"falling back to the 2d renderer:".into(), &e),
+            },
+            _ => return Err(JsValue::from_str(&format!("unknown renderer: {}", name))),
+        }
+
+        let context = canvas
+            .get_context("2d")?
+            .ok_or_else(|| JsValue::from_str("canvas has no 2d context"))?
+            .dyn_into::<CanvasRenderingContext2d>()?;
+
+        Ok(Renderer::Canvas2d(context))
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Renderer::Canvas2d(_) => "2d",
+            Renderer::WebGl2(_) => "webgl2",
+        }
+    }
+}
+
+/// State shared between the [Simulation] handle and the animation loop
+struct State {
+    fluid: Fluid,
+    canvas: HtmlCanvasElement,
+    renderer: Renderer,
+
+    num_iters: u32,
+    over_relaxation: f32,
+    sim_to_canvas_ratio: u32,
+
+    scenario: String,
+    in_vel: f32,
+    options: DrawOptions,
+    /// range of the colormap used for the last frame
+    range: Option<(f32, f32)>,
+    /// tracer and inertial particles, advected with the live simulation
+    particles: Particles,
+    /// properties of the particles released next, `None` for tracers
+    particle_inertia: Option<Inertia>,
+    /// pathlines, streaklines and timelines, advected with the live simulation
+    flowlines: Flowlines,
+    /// LIC texture of the fluid shown, for the 2d renderer and the images
+    lic: LicCache,
+
+    paused: bool,
+    /// number of steps requested while paused
+    pending_steps: u32,
+
+    previous_frame: Instant,
+
+    /// checkpoint of the fluid when the scenario was last applied
+    initial: Vec<u8>,
+
+    /// snapshots of the session, if it was ever recorded
+    recorder: Option<Recorder>,
+    /// true if new steps are recorded
+    recording: bool,
+    /// index and state of the snapshot being shown instead of the live simulation
+    replay: Option<(usize, Fluid)>,
+    /// animation of the rendered frames, while it is captured
+    capture: Option<FrameRecorder>,
+    /// worker running the steps off the main thread, if any
+    worker: Option<SolverWorker>,
+
+    /// callback of the animation loop, `None` once stopped
+    animation: Option<Closure<dyn FnMut()>>,
+    /// id of the pending `requestAnimationFrame` callback
+    request_id: Option<i32>,
+
+    /// callbacks registered with [Simulation::on]
+    listeners: Vec<(String, js_sys::Function)>,
+}
+
+impl Drop for State {
+    fn drop(&mut self) {
+        // the pending callback would otherwise run a dropped closure
+        if let Some(id) = self.request_id.take() {
+            let _ = window().cancel_animation_frame(id);
+        }
+    }
+}
+
+impl State {
+    /// Advance and render one animation frame.
+    ///
+    /// Returns the events to emit once the state is no longer borrowed.
+    fn frame(&mut self) -> Vec<(&'static str, JsValue)> {
+        let now = Instant::now();
+        let dt = now.duration_since(self.previous_frame).as_secs_f32();
+        self.previous_frame = now;
+
+        if self.worker.as_ref().is_some_and(SolverWorker::failed) {
+            // back to the main thread
+            self.worker = None;
+        }
+
+        // time step of the fields simulated since the last frame, if any
+        let mut step = None;
+        if self.replay.is_some() {
+            // the live simulation is frozen while a snapshot is shown
+        } else if let Some(worker) = &mut self.worker {
+            step = worker.take_received();
+            if !self.paused && dt != 0.0 {
+                worker.step(&self.fluid, dt, self.num_iters, self.over_relaxation);
+            } else if self.pending_steps > 0 && worker.idle() {
+                self.pending_steps -= 1;
+                worker.step(&self.fluid, 1. / 60., self.num_iters, self.over_relaxation);
+            }
+        } else if !self.paused && dt != 0.0 {
+            self.fluid
+                .simulate(dt, self.num_iters, self.over_relaxation);
+            step = Some(dt);
+        } else if self.pending_steps > 0 {
+            self.pending_steps -= 1;
+            // a fixed time step, the wall clock is meaningless while paused
+            self.fluid
+                .simulate(1. / 60., self.num_iters, self.over_relaxation);
+            step = Some(1. / 60.);
+        }
+
+        if let Some(step) = step {
+            self.particles.step(&self.fluid, step);
+            self.flowlines.step(&self.fluid, step);
+        }
+
+        if step.is_some() && self.recording {
+            if let Some(recorder) = &mut self.recorder {
+                recorder.record(&self.fluid);
+            }
+        }
+
+        // the LIC ripple moves downstream by one period per second
+        if let Some(phase) = &mut self.options.lic_phase {
+            *phase = (*phase + dt).rem_euclid(1.);
+        }
+
+        let fluid = match &self.replay {
+            Some((_, fluid)) => fluid,
+            None => &self.fluid,
+        };
+
+        let field = fluid.scalar_field(self.options.field);
+        let range = fluid.color_range(&field, self.options.color_range, self.range);
+        self.range = Some(range);
+
+        match &mut self.renderer {
+            Renderer::Canvas2d(context) => fluid.render(
+                &self.options,
+                &field,
+                range,
+                dt,
+                self.sim_to_canvas_ratio,
+                &self.particles,
+                &self.flowlines,
+                &mut self.lic,
+                context,
+            ),
+            Renderer::WebGl2(renderer) => renderer.render(
+                fluid,
+                &self.options,
+                &field,
+                range,
+                dt,
+                &self.particles,
+                &self.flowlines,
+            ),
+        }
+        .expect("draw failed");
+
+        let info = js_sys::Object::new();
+        let _ = js_sys::Reflect::set(&info, &"step".into(), &(fluid.step_count() as f64).into());
+        let _ = js_sys::Reflect::set(&info, &"time".into(), &fluid.time().into());
+        let _ = js_sys::Reflect::set(&info, &"fps".into(), &(1. / dt).into());
+
+        if step.is_some() {
+            if let Some(mut capture) = self.capture.take() {
+                if let Err(e) = capture.add_frame(|| self.image(Some(1. / dt))) {
+                    web_sys::console::error_1(&format!("frame capture failed: {}", e).into());
+                }
+                self.capture = Some(capture);
+            }
+        }
+
+        vec![("frame", info.into())]
+    }
+
+    /// The live fluid was replaced: the steps in flight are outdated.
+    fn fluid_replaced(&mut self) {
+        if let Some(worker) = &mut self.worker {
+            worker.reload();
+        }
+        self.shown_fluid_changed();
+    }
+
+    /// The fluid shown changed without a step: the LIC textures are outdated.
+    fn shown_fluid_changed(&mut self) {
+        self.lic.clear();
+        if let Renderer::WebGl2(renderer) = &mut self.renderer {
+            renderer.clear_lic();
+        }
+    }
+
+    /// The fluid shown, live or replayed.
+    fn shown_fluid(&self) -> &Fluid {
+        match &self.replay {
+            Some((_, fluid)) => fluid,
+            None => &self.fluid,
+        }
+    }
+
+    /// The fluid shown, with the field drawn and its range.
+    fn shown(&self) -> (&Fluid, Vec<f32>, (f32, f32)) {
+        let fluid = self.shown_fluid();
+
+        let field = fluid.scalar_field(self.options.field);
+        let range = match self.range {
+            Some(range) => range,
+            None => fluid.color_range(&field, self.options.color_range, None),
+        };
+
+        (fluid, field, range)
+    }
+
+    /// Render the fluid shown as it is drawn on the canvas.
+    fn image(&mut self, fps: Option<f32>) -> Result<Image, String> {
+        let mut lic = std::mem::take(&mut self.lic);
+        let (fluid, field, range) = self.shown();
+
+        let image = fluid.render_frame(
+            &self.options,
+            &field,
+            range,
+            fps,
+            self.sim_to_canvas_ratio,
+            &self.particles,
+            &self.flowlines,
+            &mut lic,
+        );
+        self.lic = lic;
+        image
+    }
+}
+
+/// A simulation rendering to a canvas, driven through explicit setters.
+#[wasm_bindgen]
+#[derive(Clone)]
+pub struct Simulation {
+    state: Rc<RefCell<State>>,
+}
+
+impl Simulation {
+    /// Call the listeners of the given events.
+    ///
+    /// Must not be called while the state is borrowed: listeners may call back
+    /// into the simulation.
+    fn emit(&self, events: Vec<(&'static str, JsValue)>) {
+        for (event, payload) in events {
+            let listeners: Vec<js_sys::Function> = self
+                .state
+                .borrow()
+                .listeners
+                .iter()
+                .filter(|(name, _)| name == event)
+                .map(|(_, f)| f.clone())
+                .collect();
+
+            for listener in listeners {
+                if let Err(e) = listener.call1(&JsValue::NULL, &payload) {
+                    web_sys::console::error_2(&format!("`{}` listener failed:", event).into(), &e);
+                }
+            }
+        }
+    }
+
+    /// Start the `requestAnimationFrame` loop.
+    fn start(&self) {
+        // the closure is owned by the state, it must not keep the state alive
+        let state = Rc::downgrade(&self.state);
+
+        let closure = Closure::new(move || {
+            let Some(state) = state.upgrade() else {
+                return;
+            };
+            let simulation = Simulation { state };
+
+            simulation.state.borrow_mut().request_id = None;
+
+            let events = simulation.state.borrow_mut().frame();
+            simulation.emit(events);
+
+            // Schedule ourself for another requestAnimationFrame callback.
+            simulation.schedule();
+        });
+
+        self.state.borrow_mut().animation = Some(closure);
+        self.schedule();
+    }
+
+    /// Request the next animation frame, unless the loop was stopped.
+    fn schedule(&self) {
+        let mut state = self.state.borrow_mut();
+        let state = &mut *state;
+
+        if let Some(closure) = &state.animation {
+            state.request_id = Some(request_animation_frame(closure));
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl Simulation {
+    /// Create a simulation rendering to `canvas` and start its animation loop.
+    ///
+    /// The simulation starts with the `cylindrical` scenario at the inlet velocity of the fluid.
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        mut fluid: Fluid,
+        canvas: web_sys::HtmlCanvasElement,
+        num_iters: u32,
+        over_relaxation: f32,
+        sim_to_canvas_ratio: u32,
+    ) -> Result<Simulation, JsValue> {
+        let renderer = Renderer::new(&canvas, "2d")?;
+
+        let scenario = SCENARIOS[0].to_string();
+        let in_vel = fluid.in_vel;
+        apply_scenario(&mut fluid, &scenario, in_vel);
+        let initial = fluid.to_checkpoint();
+        let h = fluid.h;
+        let options = DrawOptions::new(&fluid);
+
+        let mut flowlines = Flowlines::default();
+        // keep the timelines smooth at the scale of the cells
+        flowlines.refine_distance = 2. * h;
+
+        let state = State {
+            fluid,
+            canvas,
+            renderer,
+            num_iters,
+            over_relaxation,
+            sim_to_canvas_ratio,
+            scenario,
+            in_vel,
+            options,
+            range: None,
+            particles: Particles::default(),
+            particle_inertia: None,
+            flowlines,
+            lic: LicCache::default(),
+            paused: false,
+            pending_steps: 0,
+            previous_frame: Instant::now(),
+            initial,
+            recorder: None,
+            recording: false,
+            replay: None,
+            capture: None,
+            worker: None,
+            animation: None,
+            request_id: None,
+            listeners: vec![],
+        };
+
+        let simulation = Simulation {
+            state: Rc::new(RefCell::new(state)),
+        };
+        simulation.start();
+
+        Ok(simulation)
+    }
+
+    /// Switch to another scenario (`cylindrical` or `rectangular`).
+    ///
+    /// This resets the flow.
+    pub fn set_scenario(&self, scenario: &str) -> Result<(), JsValue> {
+        if !SCENARIOS.contains(&scenario) {
+            return Err(JsValue::from_str(&format!(
+                "unknown scenario: {}",
+                scenario
+            )));
+        }
+
+        let payload = {
+            let mut state = self.state.borrow_mut();
+            let state = &mut *state;
+
+            state.scenario = scenario.to_string();
+            apply_scenario(&mut state.fluid, &state.scenario, state.in_vel);
+            state.initial = state.fluid.to_checkpoint();
+            state.fluid_replaced();
+            state.replay = None;
+            state.particles.clear();
+            state.flowlines.restart();
+
+            let payload = js_sys::Object::new();
+            let _ = js_sys::Reflect::set(&payload, &"scenario".into(), &scenario.into());
+            let _ = js_sys::Reflect::set(&payload, &"in_vel".into(), &state.in_vel.into());
+            payload
+        };
+
+        self.emit(vec![("scenario", payload.into())]);
+        Ok(())
+    }
+
+    /// Set the horizontal velocity at the inlet.
+    ///
+    /// This resets the flow of the current scenario.
+    pub fn set_in_vel(&self, in_vel: f32) {
+        let scenario = {
+            let mut state = self.state.borrow_mut();
+            state.in_vel = in_vel;
+            state.scenario.clone()
+        };
+
+        self.set_scenario(&scenario)
+            .expect("the current scenario is valid");
+    }
+
+    /// Switch to the renderer `name`: `2d` or `webgl2` (falling back to `2d`).
+    ///
+    /// A canvas cannot change its kind of context, so the canvas is replaced by
+    /// a fresh copy in the document. Returns the name of the renderer in use.
+    pub fn set_renderer(&self, name: &str) -> Result<String, JsValue> {
+        let payload = {
+            let mut state = self.state.borrow_mut();
+
+            if state.renderer.name() == name {
+                return Ok(name.to_string());
+            }
+
+            let canvas = window()
+                .document()
+                .ok_or_else(|| JsValue::from_str("no document"))?
+                .create_element("canvas")?
+                .dyn_into::<HtmlCanvasElement>()?;
+            canvas.set_width(state.canvas.width());
+            canvas.set_height(state.canvas.height());
+            canvas.set_id(&state.canvas.id());
+            canvas.set_class_name(&state.canvas.class_name());
+
+            let renderer = Renderer::new(&canvas, name)?;
+
+            state.canvas.replace_with_with_node_1(&canvas)?;
+            state.canvas = canvas;
+            state.renderer = renderer;
+
+            let payload = js_sys::Object::new();
+            let _ =
+                js_sys::Reflect::set(&payload, &"renderer".into(), &state.renderer.name().into());
+            let _ = js_sys::Reflect::set(&payload, &"canvas".into(), &state.canvas);
+            payload
+        };
+
+        self.emit(vec![("renderer", payload.into())]);
+        Ok(self.renderer())
+    }
+
+    /// Name of the renderer in use
+    pub fn renderer(&self) -> String {
+        self.state.borrow().renderer.name().to_string()
+    }
+
+    /// Set the colormap: a built-in one or one registered with `register_colormap`.
+    pub fn set_colormap(&self, colormap: &str) -> Result<(), JsValue> {
+        visualization::colormap(colormap).map_err(|e| JsValue::from_str(&e))?;
+
+        self.state.borrow_mut().options.colormap = colormap.to_string();
+        Ok(())
+    }
+
+    /// Set what is drawn on the canvas.
+    ///
+    /// The maximum length of the streamlines and the distance between them are
+    /// in meters, and must be positive.
+    pub fn set_draw_options(
+        &self,
+        pressure: bool,
+        streamlines: bool,
+        streamlines_max_length: f32,
+        streamlines_spacing: f32,
+    ) -> Result<(), JsValue> {
+        if !streamlines_max_length.is_finite() || streamlines_max_length <= 0. {
+            return Err(JsValue::from_str(
+                "the maximum length of the streamlines must be positive",
+            ));
+        }
+        if !streamlines_spacing.is_finite() || streamlines_spacing <= 0. {
+            return Err(JsValue::from_str(
+                "the spacing of the streamlines must be positive",
+            ));
+        }
+
+        let options = &mut self.state.borrow_mut().options;
+
+        options.pressure = pressure;
+        options.streamlines = streamlines;
+        options.streamlines_max_length = streamlines_max_length;
+        options.streamlines_spacing = streamlines_spacing;
+        Ok(())
+    }
+
+    /// Set the integration of the streamlines: the distance between their
+    /// points (in m) and the speed (in m/s) at which they stop.
+    pub fn set_streamline_integration(&self, step: f32, min_speed: f32) -> Result<(), JsValue> {
+        if !step.is_finite() || step <= 0. {
+            return Err(JsValue::from_str("the step must be positive"));
+        }
+
+        let options = &mut self.state.borrow_mut().options;
+        options.streamlines_step = step;
+        options.streamlines_min_speed = min_speed;
+        Ok(())
+    }
+
+    /// Seed streamlines on the segment from (x0, y0) to (x1, y1), in meters,
+    /// instead of spacing them evenly.
+    pub fn add_streamline_seeds(&self, x0: f32, y0: f32, x1: f32, y1: f32, count: usize) {
+        self.state
+            .borrow_mut()
+            .options
+            .streamlines_seeds
+            .push(SeedLine {
+                from: (x0, y0),
+                to: (x1, y1),
+                count,
+            });
+    }
+
+    /// Remove the seed lines: the streamlines are evenly spaced again.
+    pub fn clear_streamline_seeds(&self) {
+        self.state.borrow_mut().options.streamlines_seeds.clear();
+    }
+
+    /// Set the scalar field drawn: `pressure`, `speed`, `u`, `v`, `vorticity`,
+    /// `divergence` or `smoke`.
+    pub fn set_field(&self, field: &str) -> Result<(), JsValue> {
+        let field = Quantity::from_str(field).map_err(|e| JsValue::from_str(&e))?;
+
+        let mut state = self.state.borrow_mut();
+        state.options.field = field;
+        // the smoothed range would start from another quantity
+        state.range = None;
+        Ok(())
+    }
+
+    /// Set how the range of the colormap is chosen:
+    /// - `auto`: range of the field, every frame
+    /// - `fixed`: from `a` to `b`
+    /// - `symmetric`: range of the field, symmetric around zero
+    /// - `percentile`: range of the field without the `a` % extreme values on each side
+    /// - `smoothed`: range of the field, moving by `a` (in `(0, 1]`) of the way each frame
+    pub fn set_color_range(&self, mode: &str, a: f32, b: f32) -> Result<(), JsValue> {
+        let color_range = match mode {
+            "auto" => ColorRange::Auto,
+            "fixed" if a < b => ColorRange::Fixed { min: a, max: b },
+            "fixed" => return Err(JsValue::from_str("the fixed range is empty")),
+            "symmetric" => ColorRange::Symmetric,
+            "percentile" => ColorRange::Percentile { percent: a },
+            "smoothed" => ColorRange::Smoothed { rate: a },
+            _ => return Err(JsValue::from_str(&format!("unknown color range: {}", mode))),
+        };
+
+        self.state.borrow_mut().options.color_range = color_range;
+        Ok(())
+    }
+
+    /// Return the range of the colormap used for the last frame, as `[min, max]`.
+    pub fn color_range(&self) -> Option<Vec<f32>> {
+        self.state.borrow().range.map(|(min, max)| vec![min, max])
+    }
+
+    /// Set how the field is sampled between the cell centers: `nearest`,
+    /// `bilinear` or `bicubic`.
+    pub fn set_interpolation(&self, interpolation: &str) -> Result<(), JsValue> {
+        let interpolation =
+            Interpolation::from_str(interpolation).map_err(|e| JsValue::from_str(&e))?;
+        self.state.borrow_mut().options.interpolation = interpolation;
+        Ok(())
+    }
+
+    /// Set the number of iso-lines drawn over the field (0 => none).
+    pub fn set_contours(&self, contours: usize) {
+        self.state.borrow_mut().options.contours = contours;
+    }
+
+    /// Set the velocity arrows: one every `spacing` cells, the longest one
+    /// `scale` times as long as the spacing.
+    pub fn set_quiver(&self, enabled: bool, spacing: usize, scale: f32) {
+        let options = &mut self.state.borrow_mut().options;
+
+        options.quiver = enabled;
+        options.quiver_spacing = spacing.max(1);
+        options.quiver_scale = scale;
+    }
+
+    /// Set the line integral convolution of the velocity: `length` is the
+    /// length of the kernel on each side (in cells), and the texture moves with
+    /// the flow if `animate`.
+    pub fn set_lic(&self, enabled: bool, length: f32, animate: bool) {
+        let options = &mut self.state.borrow_mut().options;
+
+        options.lic = enabled;
+        options.lic_length = length.max(0.5);
+        options.lic_phase = match (animate, options.lic_phase) {
+            (false, _) => None,
+            (true, phase) => Some(phase.unwrap_or(0.)),
+        };
+    }
+
+    /// Set how the tracer particles move (`rk2` or `rk4`) and are drawn
+    /// (`dots` or `trails` of `trail_length` positions).
+    pub fn set_particles(
+        &self,
+        integrator: &str,
+        style: &str,
+        trail_length: usize,
+    ) -> Result<(), JsValue> {
+        let integrator = Integrator::from_str(integrator).map_err(|e| JsValue::from_str(&e))?;
+        let style = ParticleStyle::from_str(style).map_err(|e| JsValue::from_str(&e))?;
+
+        let mut state = self.state.borrow_mut();
+        state.particles.integrator = integrator;
+        state.particles.trail_length = trail_length;
+        state.options.particle_style = style;
+        Ok(())
+    }
+
+    /// Release inertial particles from now on: spheres of the given diameter
+    /// (in m) and density (in kg/m³) which `bounce` on the walls, with the
+    /// given restitution (in `[0, 1]`), or `stick` to them.
+    pub fn set_inertial_particles(
+        &self,
+        diameter: f32,
+        density: f32,
+        wall: &str,
+        restitution: f32,
+    ) -> Result<(), JsValue> {
+        if diameter.is_nan() || diameter <= 0. || density.is_nan() || density <= 0. {
+            return Err(JsValue::from_str(
+                "the diameter and density must be positive",
+            ));
+        }
+
+        let wall = match Wall::from_str(wall).map_err(|e| JsValue::from_str(&e))? {
+            Wall::Bounce { .. } if !(0. ..=1.).contains(&restitution) => {
+                return Err(JsValue::from_str("the restitution must be between 0 and 1"));
+            }
+            Wall::Bounce { .. } => Wall::Bounce { restitution },
+            wall => wall,
+        };
+
+        self.state.borrow_mut().particle_inertia = Some(Inertia {
+            diameter,
+            density,
+            wall,
+        });
+        Ok(())
+    }
+
+    /// Release tracers, which follow the flow, from now on.
+    pub fn set_tracer_particles(&self) {
+        self.state.borrow_mut().particle_inertia = None;
+    }
+
+    /// Set the vertical acceleration (in m/s²) of the inertial particles.
+    pub fn set_particle_gravity(&self, gravity: f32) {
+        self.state.borrow_mut().particles.gravity = gravity;
+    }
+
+    /// Emit `rate` particles per second at random heights along the inlet.
+    pub fn add_inlet_emitter(&self, rate: f32) -> Result<(), JsValue> {
+        let emitter = Emitter::new(Source::Inlet, rate).map_err(|e| JsValue::from_str(&e))?;
+        let mut state = self.state.borrow_mut();
+
+        let emitter = match state.particle_inertia {
+            Some(inertia) => emitter.with_inertia(inertia),
+            None => emitter,
+        };
+        state.particles.add_emitter(emitter);
+        Ok(())
+    }
+
+    /// Emit `rate` particles per second at (x, y), in meters.
+    pub fn add_point_emitter(&self, x: f32, y: f32, rate: f32) -> Result<(), JsValue> {
+        let emitter =
+            Emitter::new(Source::Point { x, y }, rate).map_err(|e| JsValue::from_str(&e))?;
+        let mut state = self.state.borrow_mut();
+
+        let emitter = match state.particle_inertia {
+            Some(inertia) => emitter.with_inertia(inertia),
+            None => emitter,
+        };
+        state.particles.add_emitter(emitter);
+        Ok(())
+    }
+
+    /// Add `count` particles within `radius` of (x, y), in meters.
+    pub fn spawn_particles(&self, x: f32, y: f32, radius: f32, count: usize) {
+        let mut state = self.state.borrow_mut();
+        let inertia = state.particle_inertia;
+
+        state.particles.spawn_around(x, y, radius, count, inertia);
+    }
+
+    /// Remove the particles and their emitters.
+    pub fn clear_particles(&self) {
+        let particles = &mut self.state.borrow_mut().particles;
+        particles.clear();
+        particles.clear_emitters();
+    }
+
+    /// Number of particles
+    pub fn particle_count(&self) -> usize {
+        self.state.borrow().particles.particles().len()
+    }
+
+    /// Follow the fluid particle starting at (x, y), in meters.
+    pub fn add_pathline(&self, x: f32, y: f32) {
+        self.state.borrow_mut().flowlines.add_pathline(x, y);
+    }
+
+    /// Draw the line joining the particles released at (x, y), in meters, at
+    /// every step.
+    pub fn add_streakline(&self, x: f32, y: f32) {
+        self.state.borrow_mut().flowlines.add_streakline(x, y);
+    }
+
+    /// Draw the line joining `count` particles released at once along the
+    /// segment from (x0, y0) to (x1, y1), in meters.
+    pub fn add_timeline(&self, x0: f32, y0: f32, x1: f32, y1: f32, count: usize) {
+        self.state.borrow_mut().flowlines.add_timeline(SeedLine {
+            from: (x0, y0),
+            to: (x1, y1),
+            count,
+        });
+    }
+
+    /// Remove the pathlines, streaklines and timelines.
+    pub fn clear_flowlines(&self) {
+        self.state.borrow_mut().flowlines.clear();
+    }
+
+    /// Set which overlays are drawn over the field.
+    pub fn set_overlays(&self, colorbar: bool, axes: bool, hud: bool) {
+        let options = &mut self.state.borrow_mut().options;
+
+        options.colorbar = colorbar;
+        options.axes = axes;
+        options.hud = hud;
+    }
+
+    /// Set the kinematic viscosity (in m²/s) used for the Reynolds number in the
+    /// HUD and the drag on the inertial particles.
+    pub fn set_viscosity(&self, viscosity: f32) -> Result<(), JsValue> {
+        if !viscosity.is_finite() || viscosity <= 0. {
+            return Err(JsValue::from_str("the viscosity must be positive"));
+        }
+
+        let mut state = self.state.borrow_mut();
+
+        state.options.viscosity = viscosity;
+        state.particles.viscosity = viscosity;
+        Ok(())
+    }
+
+    /// Run the simulation steps in `worker`, or back on the main thread with `None`.
+    ///
+    /// The worker must call `run_solver` (see [crate::worker]). The page then only
+    /// renders the fields sent back after each step, and stays responsive on
+    /// large grids. If the worker fails, the steps go back to the main thread.
+    pub fn set_worker(&self, worker: Option<web_sys::Worker>) {
+        let worker = worker.map(|worker| {
+            let state = Rc::downgrade(&self.state);
+            let onmessage = move |message: JsValue| {
+                let Some(state) = state.upgrade() else {
+                    return;
+                };
+                let mut state = state.borrow_mut();
+                let state = &mut *state;
+                if let Some(worker) = &mut state.worker {
+                    worker.receive(&message, &mut state.fluid);
+                }
+            };
+
+            let state = Rc::downgrade(&self.state);
+            let onerror = move |error: String| {
+                let Some(state) = state.upgrade() else {
+                    return;
+                };
+                let mut state = state.borrow_mut();
+                if let Some(worker) = &mut state.worker {
+                    worker.fail(&error);
+                }
+            };
+
+            SolverWorker::new(worker, onmessage, onerror)
+        });
+
+        // the last fields received are kept, the step in flight is dropped
+        self.state.borrow_mut().worker = worker;
+    }
+
+    /// Return true if the simulation steps run in a worker.
+    pub fn has_worker(&self) -> bool {
+        self.state.borrow().worker.is_some()
+    }
+
+    /// Stop advancing the simulation; the canvas keeps being redrawn.
+    pub fn pause(&self) {
+        let was_paused = std::mem::replace(&mut self.state.borrow_mut().paused, true);
+        if !was_paused {
+            self.emit(vec![("pause", JsValue::UNDEFINED)]);
+        }
+    }
+
+    /// Resume advancing the simulation.
+    pub fn resume(&self) {
+        let was_paused = std::mem::replace(&mut self.state.borrow_mut().paused, false);
+        if was_paused {
+            self.emit(vec![("resume", JsValue::UNDEFINED)]);
+        }
+    }
+
+    /// Return true if the simulation is paused.
+    pub fn is_paused(&self) -> bool {
+        self.state.borrow().paused
+    }
+
+    /// Advance a paused simulation by one step on the next frame.
+    pub fn step(&self) {
+        self.step_frames(1);
+    }
+
+    /// Advance a paused simulation by `n` steps, one per animation frame.
+    pub fn step_frames(&self, n: u32) {
+        let mut state = self.state.borrow_mut();
+        if state.paused {
+            state.pending_steps += n;
+        }
+    }
+
+    /// Go back to the state right after the scenario was last applied.
+    pub fn reset(&self) {
+        {
+            let mut state = self.state.borrow_mut();
+            let state = &mut *state;
+
+            state.fluid =
+                Fluid::from_checkpoint(&state.initial).expect("the initial checkpoint is valid");
+            state.fluid_replaced();
+            state.pending_steps = 0;
+            state.replay = None;
+            state.particles.clear();
+            state.flowlines.restart();
+        }
+
+        self.emit(vec![("reset", JsValue::UNDEFINED)]);
+    }
+
+    /// Start recording a snapshot every `interval` steps, keeping the last `capacity` ones.
+    ///
+    /// With `half_precision`, the fields are stored with about 3 significant digits
+    /// to halve the memory used. Previous recordings are discarded.
+    pub fn start_recording(&self, capacity: usize, interval: u32, half_precision: bool) {
+        let precision = if half_precision {
+            Precision::Half
+        } else {
+            Precision::Full
+        };
+
+        let mut state = self.state.borrow_mut();
+        state.recorder = Some(Recorder::new(capacity, interval, precision));
+        state.recording = true;
+    }
+
+    /// Stop recording; the snapshots taken so far are kept.
+    pub fn stop_recording(&self) {
+        self.state.borrow_mut().recording = false;
+    }
+
+    /// Number of recorded snapshots
+    pub fn replay_len(&self) -> usize {
+        self.state
+            .borrow()
+            .recorder
+            .as_ref()
+            .map_or(0, Recorder::len)
+    }
+
+    /// Index of the snapshot being shown, if any
+    pub fn replay_index(&self) -> Option<usize> {
+        self.state.borrow().replay.as_ref().map(|(index, _)| *index)
+    }
+
+    /// Show the `index`-th recorded snapshot (oldest first) instead of the live simulation.
+    pub fn seek(&self, index: usize) -> Result<(), JsValue> {
+        let payload = {
+            let mut state = self.state.borrow_mut();
+
+            let fluid = state
+                .recorder
+                .as_ref()
+                .and_then(|recorder| recorder.get(index))
+                .ok_or_else(|| JsValue::from_str(&format!("no snapshot {}", index)))?;
+
+            let payload = js_sys::Object::new();
+            let _ = js_sys::Reflect::set(&payload, &"index".into(), &index.into());
+            let _ = js_sys::Reflect::set(
+                &payload,
+                &"step".into(),
+                &(fluid.step_count() as f64).into(),
+            );
+
+            state.replay = Some((index, fluid));
+            state.shown_fluid_changed();
+            payload
+        };
+
+        self.emit(vec![("seek", payload.into())]);
+        Ok(())
+    }
+
+    /// Move `delta` snapshots away from the one being shown.
+    ///
+    /// From the live simulation, `-1` is the most recent snapshot.
+    pub fn seek_by(&self, delta: i32) -> Result<(), JsValue> {
+        let len = self.replay_len() as i64;
+        if len == 0 {
+            return Err(JsValue::from_str("nothing recorded"));
+        }
+
+        let current = self.replay_index().map_or(len, |index| index as i64);
+        self.seek((current + delta as i64).clamp(0, len - 1) as usize)
+    }
+
+    /// Go back to the live simulation.
+    ///
+    /// With `branch`, the simulation continues from the snapshot being shown and the
+    /// snapshots recorded after it are dropped.
+    pub fn leave_replay(&self, branch: bool) {
+        let left = {
+            let mut state = self.state.borrow_mut();
+            let state = &mut *state;
+
+            match state.replay.take() {
+                Some((index, fluid)) => {
+                    state.shown_fluid_changed();
+                    if branch {
+                        state.fluid = fluid;
+                        state.fluid_replaced();
+                        if let Some(recorder) = &mut state.recorder {
+                            recorder.truncate(index + 1);
+                        }
+                    }
+                    true
+                }
+                None => false,
+            }
+        };
+
+        if left {
+            self.emit(vec![("live", JsValue::UNDEFINED)]);
+        }
+    }
+
+    /// Export the recorded snapshots as a sequence of checkpoints.
+    pub fn export_replay(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        if let Some(recorder) = &self.state.borrow().recorder {
+            recorder
+                .write(&mut buf)
+                .expect("writing to a Vec cannot fail");
+        }
+        buf
+    }
+
+    /// Save the full state of the fluid shown (as a `Uint8Array`), see [Fluid::save].
+    pub fn save(&self) -> Vec<u8> {
+        self.state.borrow().shown_fluid().to_checkpoint()
+    }
+
+    /// Continue from a state saved with [Simulation::save] or [Fluid::save].
+    ///
+    /// The state must be on the grid of the simulation. The particles and the
+    /// flowlines start over, and a replay being shown is left.
+    pub fn restore(&self, data: &[u8]) -> Result<(), JsValue> {
+        let fluid = Fluid::from_checkpoint(data).map_err(|e| JsValue::from_str(&e.to_string()))?;
+
+        let payload = {
+            let mut state = self.state.borrow_mut();
+            let state = &mut *state;
+
+            let grid = (state.fluid.num_x(), state.fluid.num_y());
+            if (fluid.num_x(), fluid.num_y()) != grid {
+                return Err(JsValue::from_str(&format!(
+                    "the state is on a {}x{} grid, not {}x{}",
+                    fluid.num_x(),
+                    fluid.num_y(),
+                    grid.0,
+                    grid.1
+                )));
+            }
+
+            state.fluid = fluid;
+            state.fluid_replaced();
+            state.pending_steps = 0;
+            state.replay = None;
+            state.particles.clear();
+            state.flowlines.restart();
+
+            let payload = js_sys::Object::new();
+            let _ = js_sys::Reflect::set(
+                &payload,
+                &"step".into(),
+                &(state.fluid.step_count() as f64).into(),
+            );
+            let _ = js_sys::Reflect::set(&payload, &"time".into(), &state.fluid.time().into());
+            payload
+        };
+
+        self.emit(vec![("restore", payload.into())]);
+        Ok(())
+    }
+
+    /// Export the fields of the fluid shown as an `.npz` archive, see [Fluid::export_npz].
+    pub fn export_npz(&self) -> Vec<u8> {
+        self.state.borrow().shown_fluid().export_npz()
+    }
+
+    /// Export the JSON sidecar of [Simulation::export_npz].
+    pub fn export_metadata(&self) -> String {
+        self.state.borrow().shown_fluid().export_metadata()
+    }
+
+    /// Export the fluid shown as a VTK ImageData file, see [Fluid::export_vti].
+    pub fn export_vti(&self, encoding: &str) -> Result<Vec<u8>, JsValue> {
+        self.state.borrow().shown_fluid().export_vti(encoding)
+    }
+
+    /// Number of cells in the x-direction (including the 2 border cells)
+    pub fn num_x(&self) -> usize {
+        self.state.borrow().fluid.num_x()
+    }
+
+    /// Number of cells in the y-direction (including the 2 border cells)
+    pub fn num_y(&self) -> usize {
+        self.state.borrow().fluid.num_y()
+    }
+
+    /// Cell size (in meters)
+    pub fn h(&self) -> f32 {
+        self.state.borrow().fluid.h()
+    }
+
+    /// Number of values in each field (`num_x * num_y`)
+    pub fn field_len(&self) -> usize {
+        self.state.borrow().fluid.field_len()
+    }
+
+    /// Zero-copy view of the x-component of the velocity of the fluid shown.
+    ///
+    /// Besides the caveats of [Fluid::u_view], the view is only valid until the
+    /// fluid shown changes: on the next frame with a worker, when seeking in the
+    /// replay, or after a reset or a restore.
+    pub fn u_view(&self) -> js_sys::Float32Array {
+        self.state.borrow().shown_fluid().u_view()
+    }
+
+    /// Zero-copy view of the y-component of the velocity of the fluid shown.
+    ///
+    /// See [Simulation::u_view] for how long the view stays valid.
+    pub fn v_view(&self) -> js_sys::Float32Array {
+        self.state.borrow().shown_fluid().v_view()
+    }
+
+    /// Zero-copy view of the pressure field of the fluid shown.
+    ///
+    /// See [Simulation::u_view] for how long the view stays valid.
+    pub fn p_view(&self) -> js_sys::Float32Array {
+        self.state.borrow().shown_fluid().p_view()
+    }
+
+    /// Zero-copy view of the obstacle field of the fluid shown.
+    ///
+    /// See [Simulation::u_view] for how long the view stays valid.
+    pub fn s_view(&self) -> js_sys::Float32Array {
+        self.state.borrow().shown_fluid().s_view()
+    }
+
+    /// Zero-copy view of the smoke field of the fluid shown.
+    ///
+    /// See [Simulation::u_view] for how long the view stays valid.
+    pub fn m_view(&self) -> js_sys::Float32Array {
+        self.state.borrow().shown_fluid().m_view()
+    }
+
+    /// Pointer to the x-component of the velocity of the fluid shown in wasm memory.
+    ///
+    /// Valid while the fluid shown does not change, see [Simulation::u_view].
+    pub fn u_ptr(&self) -> *const f32 {
+        self.state.borrow().shown_fluid().u_ptr()
+    }
+
+    /// Pointer to the y-component of the velocity of the fluid shown in wasm memory.
+    ///
+    /// See [Simulation::u_ptr] for how long the pointer stays valid.
+    pub fn v_ptr(&self) -> *const f32 {
+        self.state.borrow().shown_fluid().v_ptr()
+    }
+
+    /// Pointer to the pressure field of the fluid shown in wasm memory.
+    ///
+    /// See [Simulation::u_ptr] for how long the pointer stays valid.
+    pub fn p_ptr(&self) -> *const f32 {
+        self.state.borrow().shown_fluid().p_ptr()
+    }
+
+    /// Pointer to the obstacle field of the fluid shown in wasm memory.
+    ///
+    /// See [Simulation::u_ptr] for how long the pointer stays valid.
+    pub fn s_ptr(&self) -> *const f32 {
+        self.state.borrow().shown_fluid().s_ptr()
+    }
+
+    /// Pointer to the smoke field of the fluid shown in wasm memory.
+    ///
+    /// See [Simulation::u_ptr] for how long the pointer stays valid.
+    pub fn m_ptr(&self) -> *const f32 {
+        self.state.borrow().shown_fluid().m_ptr()
+    }
+
+    /// Render the current frame as a PNG file.
+    pub fn snapshot_png(&self) -> Result<Vec<u8>, JsValue> {
+        let image = self
+            .state
+            .borrow_mut()
+            .image(None)
+            .map_err(|e| JsValue::from_str(&e))?;
+        Ok(image.to_png())
+    }
+
+    /// Export the obstacles, iso-lines, streamlines and arrows shown as an SVG image.
+    ///
+    /// With `raster`, the field is embedded as an image below them.
+    pub fn export_svg(&self, raster: bool) -> Result<String, JsValue> {
+        let state = self.state.borrow();
+        let (fluid, field, range) = state.shown();
+
+        let mut buf = Vec::new();
+        svg::write_svg(
+            fluid,
+            &state.options,
+            &field,
+            range,
+            state.sim_to_canvas_ratio,
+            raster,
+            &mut buf,
+        )
+        .map_err(|e| JsValue::from_str(&e.to_string()))?;
+        Ok(String::from_utf8(buf).expect("the SVG is UTF-8"))
+    }
+
+    /// Start capturing the rendered frames into an animation (`gif` or `apng`).
+    ///
+    /// One frame is kept every `stride` simulation steps and played back at `fps`
+    /// frames per second. A capture in progress is discarded.
+    pub fn start_capture(&self, format: &str, stride: usize, fps: u16) -> Result<(), JsValue> {
+        let format = AnimationFormat::from_str(format).map_err(|e| JsValue::from_str(&e))?;
+        self.state.borrow_mut().capture = Some(FrameRecorder::new(format, stride, fps));
+        Ok(())
+    }
+
+    /// Number of frames captured so far
+    pub fn captured_frames(&self) -> usize {
+        self.state
+            .borrow()
+            .capture
+            .as_ref()
+            .map_or(0, FrameRecorder::len)
+    }
+
+    /// Stop capturing frames and return the animation file.
+    pub fn stop_capture(&self) -> Result<Vec<u8>, JsValue> {
+        let capture = self
+            .state
+            .borrow_mut()
+            .capture
+            .take()
+            .ok_or_else(|| JsValue::from_str("no capture in progress"))?;
+        capture.finish().map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Stop the animation loop for good.
+    ///
+    /// The pending animation frame is cancelled and the loop callback released.
+    pub fn stop(&self) {
+        let was_running = {
+            let mut state = self.state.borrow_mut();
+
+            if let Some(id) = state.request_id.take() {
+                window().cancel_animation_frame(id).ok();
+            }
+            state.animation.take().is_some()
+        };
+
+        if was_running {
+            self.emit(vec![("stop", JsValue::UNDEFINED)]);
+        }
+    }
+
+    /// Bind the keyboard shortcuts on `target` (typically the document):
+    /// - `space`: pause/resume
+    /// - `s`: step one frame (`S`: 10 frames) while paused
+    /// - `r`: reset
+    /// - `,`/`.`: show the previous/next recorded snapshot
+    /// - `Escape`: back to the live simulation, `Enter`: continue from the snapshot shown
+    ///
+    /// Keys typed in form fields or editable elements, or with Ctrl, Alt or
+    /// Meta held, are ignored. The listener is removed by [Simulation::stop].
+    pub fn bind_keyboard(&self, target: &web_sys::EventTarget) -> Result<(), JsValue> {
+        let state = Rc::downgrade(&self.state);
+        let listener = Closure::<dyn FnMut(web_sys::KeyboardEvent)>::new(
+            move |event: web_sys::KeyboardEvent| {
+                let Some(state) = state.upgrade() else {
+                    return;
+                };
+                let simulation = Simulation { state };
+
+                // leave the browser shortcuts and the text fields alone
+                if event.ctrl_key() || event.alt_key() || event.meta_key() {
+                    return;
+                }
+                if event.target().is_some_and(|target| is_editable(&target)) {
+                    return;
+                }
+
+                match event.key().as_str() {
+                    " " => {
+                        if simulation.is_paused() {
+                            simulation.resume();
+                        } else {
+                            simulation.pause();
+                        }
+                    }
+                    "s" => simulation.step(),
+                    "S" => simulation.step_frames(10),
+                    "r" => simulation.reset(),
+                    "," => {
+                        let _ = simulation.seek_by(-1);
+                    }
+                    "." => {
+                        let _ = simulation.seek_by(1);
+                    }
+                    "Escape" => simulation.leave_replay(false),
+                    "Enter" => simulation.leave_replay(true),
+                    _ => return,
+                }
+                event.prevent_default();
+            },
+        );
+
+        target.add_event_listener_with_callback("keydown", listener.as_ref().unchecked_ref())?;
+
+        let target = target.clone();
+        let remove = Closure::<dyn FnMut()>::new(move || {
+            let _ = target
+                .remove_event_listener_with_callback("keydown", listener.as_ref().unchecked_ref());
+        });
+        self.on("stop", remove.into_js_value().unchecked_into());
+
+        Ok(())
+    }
+
+    /// Register a callback for an event.
+    ///
+    /// Events:
+    /// - `frame`: after each frame is drawn, with `{ step, time, fps }`
+    /// - `scenario`: after the scenario is (re)applied, with `{ scenario, in_vel }`
+    /// - `renderer`: after the renderer changed, with `{ renderer, canvas }`
+    /// - `seek`: when a recorded snapshot is shown, with `{ index, step }`
+    /// - `live`: when going back to the live simulation
+    /// - `restore`: after a saved state is restored, with `{ step, time }`
+    /// - `pause`, `resume`, `reset`, `stop`
+    pub fn on(&self, event: &str, callback: js_sys::Function) {
+        self.state
+            .borrow_mut()
+            .listeners
+            .push((event.to_string(), callback));
+    }
+}
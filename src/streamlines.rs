@@ -0,0 +1,411 @@
+//! Streamline tracing
+//!
+//! Streamlines are integrated with RK4 along the unit velocity, so that the
+//! points are evenly spaced along each line, in both directions from the seed.
+//! A line stops at the obstacles, at the edges of the domain, at stagnation
+//! points and after a maximum length. Seeds come either from lines given by the
+//! user or from the evenly-spaced placement of Jobard and Lefer ("Creating
+//! evenly-spaced streamlines of arbitrary density", 1997).
+//!
+//! All lengths are in meters.
+use crate::simu::Fluid;
+
+/// Seeds evenly spaced on a segment.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SeedLine {
+    pub from: (f32, f32),
+    pub to: (f32, f32),
+    /// Number of seeds, including both ends
+    pub count: usize,
+}
+
+impl SeedLine {
+    /// Returns the seeds, in physical coordinates.
+    pub fn seeds(&self) -> impl Iterator<Item = (f32, f32)> + '_ {
+        let (x0, y0) = self.from;
+        let (x1, y1) = self.to;
+        let count = self.count;
+
+        (0..count).map(move |k| {
+            // a single seed is in the middle
+            let t = if count == 1 {
+                0.5
+            } else {
+                k as f32 / (count - 1) as f32
+            };
+            (x0 + (x1 - x0) * t, y0 + (y1 - y0) * t)
+        })
+    }
+}
+
+/// Parameters of the streamlines
+#[derive(Clone, Debug)]
+pub struct StreamlineOptions {
+    /// Distance between the evenly-spaced streamlines
+    pub spacing: f32,
+    /// Integration step, i.e. distance between the points of a line
+    pub step: f32,
+    /// Maximum length of a line, both directions included
+    pub max_length: f32,
+    /// Speed (in m/s) below which the flow is considered stagnant
+    pub min_speed: f32,
+    /// Seed lines; the streamlines are evenly spaced if there are none
+    pub seeds: Vec<SeedLine>,
+}
+
+/// Trace the streamlines, as polylines in physical coordinates.
+pub fn streamlines(fluid: &Fluid, options: &StreamlineOptions) -> Vec<Vec<(f32, f32)>> {
+    if options.seeds.is_empty() {
+        evenly_spaced(fluid, options)
+    } else {
+        options
+            .seeds
+            .iter()
+            .flat_map(|line| line.seeds())
+            .map(|seed| trace(fluid, seed, options, |_| true))
+            .filter(|line| line.len() >= 2)
+            .collect()
+    }
+}
+
+/// Unit velocity at `p`, `None` outside of the fluid or where it is stagnant.
+fn direction(fluid: &Fluid, (x, y): (f32, f32), min_speed: f32) -> Option<(f32, f32)> {
+    if !fluid.is_fluid(x, y) {
+        return None;
+    }
+
+    let (u, v) = fluid.velocity(x, y);
+    let speed = (u * u + v * v).sqrt();
+
+    // also rejects NaN
+    if speed >= min_speed.max(f32::MIN_POSITIVE) {
+        Some((u / speed, v / speed))
+    } else {
+        None
+    }
+}
+
+/// One RK4 step of (signed) length `step` along the unit velocity.
+fn rk4(fluid: &Fluid, (x, y): (f32, f32), step: f32, min_speed: f32) -> Option<(f32, f32)> {
+    let at = |(dx, dy): (f32, f32), t: f32| (x + dx * t, y + dy * t);
+
+    let k1 = direction(fluid, (x, y), min_speed)?;
+    let k2 = direction(fluid, at(k1, 0.5 * step), min_speed)?;
+    let k3 = direction(fluid, at(k2, 0.5 * step), min_speed)?;
+    let k4 = direction(fluid, at(k3, step), min_speed)?;
+
+    let dx = (k1.0 + 2. * k2.0 + 2. * k3.0 + k4.0) / 6.;
+    let dy = (k1.1 + 2. * k2.1 + 2. * k3.1 + k4.1) / 6.;
+    let next = at((dx, dy), step);
+
+    fluid.is_fluid(next.0, next.1).then_some(next)
+}
+
+/// Trace the streamline through `seed`, upstream and downstream.
+///
+/// The line also stops before the first point for which `accept` is false.
+/// Returns the points from upstream to downstream, empty if the seed is not in
+/// the fluid.
+pub fn trace(
+    fluid: &Fluid,
+    seed: (f32, f32),
+    options: &StreamlineOptions,
+    mut accept: impl FnMut((f32, f32)) -> bool,
+) -> Vec<(f32, f32)> {
+    let step = options.step;
+    if !fluid.is_fluid(seed.0, seed.1) || step.is_nan() || step <= 0. {
+        return vec![];
+    }
+
+    let max_steps = (0.5 * options.max_length / step).ceil() as usize;
+
+    let mut half = |step: f32| {
+        let mut points = vec![];
+        let mut p = seed;
+
+        for _ in 0..max_steps {
+            match rk4(fluid, p, step, options.min_speed) {
+                Some(next) if accept(next) => {
+                    points.push(next);
+                    p = next;
+                }
+                _ => break,
+            }
+        }
+
+        points
+    };
+
+    let mut line = half(-step);
+    line.reverse();
+    line.push(seed);
+    line.extend(half(step));
+
+    line
+}
+
+/// Points of the streamlines, bucketed by square cells for distance queries.
+struct Grid {
+    cell: f32,
+    width: usize,
+    height: usize,
+    cells: Vec<Vec<(f32, f32)>>,
+}
+
+impl Grid {
+    /// `cell` must be at least the largest distance queried.
+    fn new(fluid: &Fluid, cell: f32) -> Self {
+        let width = ((fluid.num_x as f32 * fluid.h / cell).ceil() as usize).max(1);
+        let height = ((fluid.num_y as f32 * fluid.h / cell).ceil() as usize).max(1);
+
+        Self {
+            cell,
+            width,
+            height,
+            cells: vec![vec![]; width * height],
+        }
+    }
+
+    fn index(&self, (x, y): (f32, f32)) -> (usize, usize) {
+        let i = ((x / self.cell).max(0.) as usize).min(self.width - 1);
+        let j = ((y / self.cell).max(0.) as usize).min(self.height - 1);
+        (i, j)
+    }
+
+    fn insert(&mut self, p: (f32, f32)) {
+        let (i, j) = self.index(p);
+        self.cells[j * self.width + i].push(p);
+    }
+
+    /// Returns true if no point is closer than `distance` to `p`.
+    fn is_free(&self, (x, y): (f32, f32), distance: f32) -> bool {
+        let (i, j) = self.index((x, y));
+        let d2 = distance * distance;
+
+        for jj in j.saturating_sub(1)..(j + 2).min(self.height) {
+            for ii in i.saturating_sub(1)..(i + 2).min(self.width) {
+                let close = self.cells[jj * self.width + ii]
+                    .iter()
+                    .any(|&(px, py)| (px - x) * (px - x) + (py - y) * (py - y) < d2);
+                if close {
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+}
+
+/// Streamlines about `options.spacing` apart, filling the domain.
+///
+/// New seeds are taken on both sides of the existing lines, at the spacing
+/// distance, and a line stops when it comes closer than half the spacing to
+/// another one. Regions the lines cannot reach (e.g. behind a stagnation point)
+/// are seeded from a lattice.
+pub fn evenly_spaced(fluid: &Fluid, options: &StreamlineOptions) -> Vec<Vec<(f32, f32)>> {
+    let separation = options.spacing.max(options.step);
+    if separation.is_nan() || separation <= 0. {
+        return vec![];
+    }
+    let test = 0.5 * separation;
+
+    let mut grid = Grid::new(fluid, separation);
+    let mut lines: Vec<Vec<(f32, f32)>> = vec![];
+
+    let add_line = |seed: (f32, f32), grid: &mut Grid, lines: &mut Vec<Vec<(f32, f32)>>| {
+        if !grid.is_free(seed, separation) || direction(fluid, seed, options.min_speed).is_none() {
+            return;
+        }
+
+        let line = trace(fluid, seed, options, |p| grid.is_free(p, test));
+        if line.len() >= 2 {
+            for &p in &line {
+                grid.insert(p);
+            }
+            lines.push(line);
+        }
+    };
+
+    let lattice_x = (fluid.num_x as f32 * fluid.h / separation) as usize;
+    let lattice_y = (fluid.num_y as f32 * fluid.h / separation) as usize;
+    let mut lattice = (0..lattice_x).flat_map(|i| {
+        (0..lattice_y).map(move |j| ((i as f32 + 0.5) * separation, (j as f32 + 0.5) * separation))
+    });
+
+    // index of the next line to seed from
+    let mut next = 0;
+    loop {
+        if next == lines.len() {
+            match lattice.next() {
+                Some(seed) => add_line(seed, &mut grid, &mut lines),
+                None => break,
+            }
+            continue;
+        }
+
+        let line = lines[next].clone();
+        next += 1;
+
+        for segment in line.windows(2) {
+            let [(x0, y0), (x1, y1)] = [segment[0], segment[1]];
+            let length = ((x1 - x0) * (x1 - x0) + (y1 - y0) * (y1 - y0)).sqrt();
+            if length == 0. {
+                continue;
+            }
+
+            // normal to the line, on both sides
+            let (nx, ny) = (-(y1 - y0) / length, (x1 - x0) / length);
+            for side in [1., -1.] {
+                let seed = (x0 + side * nx * separation, y0 + side * ny * separation);
+                add_line(seed, &mut grid, &mut lines);
+            }
+        }
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simu::apply_scenario;
+
+    fn options(seeds: Vec<SeedLine>) -> StreamlineOptions {
+        StreamlineOptions {
+            spacing: 0.05,
+            step: 0.01,
+            max_length: 2.,
+            min_speed: 1e-3,
+            seeds,
+        }
+    }
+
+    /// Flow around the cylinder, in a domain 2 m long and 1 m high
+    fn cylinder() -> Fluid {
+        let mut fluid = Fluid::create(0., 1.5, 60, 30, 1. / 30., 1000.);
+        apply_scenario(&mut fluid, "cylindrical", 1.5);
+        for _ in 0..20 {
+            fluid.simulate(1. / 60., 40, 1.9);
+        }
+        fluid
+    }
+
+    /// A channel of 40 by 20 cells of 1 cm, open on the right, with the
+    /// horizontal velocity `u(i)` on the faces of the column `i`.
+    fn channel(u: impl Fn(usize) -> f32) -> Fluid {
+        let mut fluid = Fluid::create(0., 0., 40, 20, 0.01, 1000.);
+        let n = fluid.num_y;
+        for i in 0..fluid.num_x {
+            for j in 0..n {
+                let wall = i == 0 || j == 0 || j == n - 1;
+                fluid.s[i * n + j] = if wall { 0. } else { 1. };
+                fluid.u[i * n + j] = u(i);
+            }
+        }
+        fluid
+    }
+
+    #[test]
+    fn seed_lines() {
+        let line = |count| SeedLine {
+            from: (0., 1.),
+            to: (2., 0.),
+            count,
+        };
+        assert_eq!(line(0).seeds().count(), 0);
+        assert_eq!(line(1).seeds().collect::<Vec<_>>(), [(1., 0.5)]);
+        assert_eq!(
+            line(3).seeds().collect::<Vec<_>>(),
+            [(0., 1.), (1., 0.5), (2., 0.)]
+        );
+    }
+
+    #[test]
+    fn both_directions_to_the_edges() {
+        let fluid = channel(|_| 0.05);
+        let options = options(vec![]);
+        let line = trace(&fluid, (0.2, 0.1), &options, |_| true);
+
+        // from the wall on the left to the outlet, one step apart
+        let (first, last) = (line[0], line[line.len() - 1]);
+        assert!(
+            first.0 >= 0.01 && first.0 < 0.01 + options.step,
+            "{:?}",
+            first
+        );
+        assert!(last.0 < 0.41 && last.0 >= 0.41 - options.step, "{:?}", last);
+        assert!(line.contains(&(0.2, 0.1)));
+        for pair in line.windows(2) {
+            assert!((pair[1].0 - pair[0].0 - options.step).abs() < 1e-5);
+            assert!((pair[1].1 - 0.1).abs() < 1e-5);
+        }
+
+        // the maximum length is split between both directions
+        let short = StreamlineOptions {
+            max_length: 0.1,
+            ..options.clone()
+        };
+        assert_eq!(trace(&fluid, (0.2, 0.1), &short, |_| true).len(), 11);
+
+        assert!(trace(&fluid, (0.005, 0.1), &options, |_| true).is_empty());
+    }
+
+    #[test]
+    fn stop_where_the_flow_is_stagnant() {
+        // stagnant from the column 21 on
+        let fluid = channel(|i| if i <= 20 { 0.05 } else { 0. });
+        let options = options(vec![]);
+        let line = trace(&fluid, (0.1, 0.1), &options, |_| true);
+
+        let last = line[line.len() - 1];
+        assert!(last.0 > 0.19 && last.0 < 0.21 + options.step, "{:?}", last);
+
+        // no line at all in a fluid at rest
+        let options = StreamlineOptions {
+            min_speed: 0.1,
+            ..options
+        };
+        assert_eq!(trace(&fluid, (0.1, 0.1), &options, |_| true).len(), 1);
+        assert!(streamlines(&fluid, &options).is_empty());
+    }
+
+    #[test]
+    fn lines_stay_in_the_fluid() {
+        let fluid = cylinder();
+        let seeded = options(vec![SeedLine {
+            from: (0.1, 0.05),
+            to: (0.1, 0.95),
+            count: 20,
+        }]);
+
+        for options in [options(vec![]), seeded] {
+            let lines = streamlines(&fluid, &options);
+            assert!(!lines.is_empty());
+            for line in &lines {
+                assert!(line.len() >= 2);
+                assert!(line.iter().all(|&(x, y)| fluid.is_fluid(x, y)));
+            }
+        }
+    }
+
+    #[test]
+    fn evenly_spaced_lines_keep_apart() {
+        let fluid = cylinder();
+        let options = options(vec![]);
+        let lines = evenly_spaced(&fluid, &options);
+        assert!(lines.len() > 10);
+
+        let min = 0.5 * options.spacing;
+        for (k, line) in lines.iter().enumerate() {
+            for other in &lines[k + 1..] {
+                for &(x0, y0) in line {
+                    for &(x1, y1) in other {
+                        let d = ((x1 - x0).powi(2) + (y1 - y0).powi(2)).sqrt();
+                        assert!(d >= min * (1. - 1e-5), "{} apart", d);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,11 +1,15 @@
 //! Visualization code
 
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::convert::TryFrom;
+use std::str::FromStr;
 
-use wasm_bindgen::{Clamped, JsValue};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::Clamped;
 
-//// An image
-pub(crate) struct Image {
+/// An RGBA image, as drawn on the canvas
+pub struct Image {
     data: Vec<u8>,
     width: usize,
     height: usize,
@@ -13,13 +17,12 @@ pub(crate) struct Image {
 }
 
 impl Image {
+    /// Create a transparent image of `width` by `height` cells of `resolution` pixels.
     #[inline]
-    pub(crate) fn new(width: usize, height: usize, resolution: usize) -> Self {
+    pub fn new(width: usize, height: usize, resolution: usize) -> Self {
         let width = width * resolution;
         let height = height * resolution;
 
-        let resolution = resolution;
-
         let data = vec![0_u8; 4 * width * height];
         Self {
             data,
@@ -47,9 +50,359 @@ impl Image {
     }
 
     /// Returns the size of the image in pixels.
-    pub(crate) fn size(&self) -> (usize, usize) {
+    pub fn size(&self) -> (usize, usize) {
         (self.width, self.height)
     }
+
+    /// Returns the RGBA pixels, row by row from the top.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Returns the number of pixels per cell.
+    pub fn resolution(&self) -> usize {
+        self.resolution
+    }
+
+    /// Returns a copy of the `width` by `height` pixels at (x, y).
+    pub(crate) fn crop(&self, x: usize, y: usize, width: usize, height: usize) -> Image {
+        let mut data = Vec::with_capacity(4 * width * height);
+        for row in y..y + height {
+            let start = 4 * (x + row * self.width);
+            data.extend_from_slice(&self.data[start..start + 4 * width]);
+        }
+
+        Image {
+            data,
+            width,
+            height,
+            resolution: self.resolution,
+        }
+    }
+
+    /// Encode the image as a PNG file.
+    pub fn to_png(&self) -> Vec<u8> {
+        crate::export::png::encode(self)
+    }
+
+    /// Returns the color of the pixel at (x, y).
+    pub fn pixel(&self, x: usize, y: usize) -> [u8; 4] {
+        let index = 4 * (x + y * self.width);
+        [
+            self.data[index],
+            self.data[index + 1],
+            self.data[index + 2],
+            self.data[index + 3],
+        ]
+    }
+
+    /// color the pixel at (x, y)
+    pub(crate) fn put_pixel(&mut self, x: usize, y: usize, color: [u8; 4]) {
+        let index = 4 * (x + y * self.width);
+        self.data[index..index + 4].copy_from_slice(&color);
+    }
+
+    /// blend `color` over the pixel at (x, y), with its alpha scaled by `coverage`
+    ///
+    /// Pixels outside of the image are ignored.
+    pub(crate) fn blend_pixel(&mut self, x: isize, y: isize, color: [u8; 4], coverage: f32) {
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+            return;
+        }
+
+        let index = 4 * (x as usize + y as usize * self.width);
+        let alpha = coverage.clamp(0., 1.) * color[3] as f32 / 255.;
+
+        for (dst, &src) in self.data[index..index + 3].iter_mut().zip(&color) {
+            *dst = (*dst as f32 + (src as f32 - *dst as f32) * alpha).round() as u8;
+        }
+        let dst = self.data[index + 3] as f32;
+        self.data[index + 3] = (dst + (255. - dst) * alpha).round() as u8;
+    }
+
+    /// blend `color` over the rectangle of size (w, h) at (x, y)
+    pub(crate) fn fill_rect(&mut self, x: isize, y: isize, w: usize, h: usize, color: [u8; 4]) {
+        for yy in y..y + h as isize {
+            for xx in x..x + w as isize {
+                self.blend_pixel(xx, yy, color, 1.);
+            }
+        }
+    }
+
+    /// blend an antialiased disk of the given center and radius, in pixels
+    pub(crate) fn fill_circle(&mut self, (cx, cy): (f32, f32), radius: f32, color: [u8; 4]) {
+        let (x0, x1) = (
+            (cx - radius).floor() as isize,
+            (cx + radius).ceil() as isize,
+        );
+        let (y0, y1) = (
+            (cy - radius).floor() as isize,
+            (cy + radius).ceil() as isize,
+        );
+
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let dx = x as f32 + 0.5 - cx;
+                let dy = y as f32 + 0.5 - cy;
+                // approximate coverage of the pixel by the distance to the edge
+                let coverage = (radius + 0.5 - (dx * dx + dy * dy).sqrt()).clamp(0., 1.);
+                if coverage > 0. {
+                    self.blend_pixel(x, y, color, coverage);
+                }
+            }
+        }
+    }
+
+    /// draw `text` with its top left corner at (x, y), each font pixel being
+    /// `scale` by `scale` pixels
+    ///
+    /// Lowercase letters are drawn as uppercase; see [text_size].
+    pub(crate) fn draw_text(
+        &mut self,
+        x: isize,
+        y: isize,
+        text: &str,
+        color: [u8; 4],
+        scale: usize,
+    ) {
+        let advance = (GLYPH_WIDTH + 1) * scale;
+
+        for (k, c) in text.chars().enumerate() {
+            let x0 = x + (k * advance) as isize;
+
+            for (row, bits) in glyph(c).iter().enumerate() {
+                for col in 0..GLYPH_WIDTH {
+                    if bits & (1 << (GLYPH_WIDTH - 1 - col)) != 0 {
+                        let px = x0 + (col * scale) as isize;
+                        let py = y + (row * scale) as isize;
+                        self.fill_rect(px, py, scale, scale, color);
+                    }
+                }
+            }
+        }
+    }
+
+    /// draw an arrow from `from` to `to`, in pixels
+    pub(crate) fn draw_arrow(&mut self, from: (f32, f32), to: (f32, f32), color: [u8; 4]) {
+        self.draw_line(from, to, color);
+
+        let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+        let len = (dx * dx + dy * dy).sqrt();
+        if len < 2. {
+            return;
+        }
+
+        // head: two strokes at +/- 25 degrees from the shaft
+        let head = (0.35 * len).min(8. * self.resolution as f32).max(2.);
+        let (ux, uy) = (dx / len, dy / len);
+        let (sin, cos) = 25_f32.to_radians().sin_cos();
+        for side in [-1., 1.] {
+            let hx = -(ux * cos - side * uy * sin) * head;
+            let hy = -(uy * cos + side * ux * sin) * head;
+            self.draw_line(to, (to.0 + hx, to.1 + hy), color);
+        }
+    }
+
+    /// draw an anti-aliased line between two points, in pixels
+    pub(crate) fn draw_line(&mut self, (x0, y0): (f32, f32), (x1, y1): (f32, f32), color: [u8; 4]) {
+        // Xiaolin Wu's algorithm: step along the major axis and split each
+        // sample between the two pixels straddling the line
+        let steep = (y1 - y0).abs() > (x1 - x0).abs();
+        let (x0, y0, x1, y1) = if steep {
+            (y0, x0, y1, x1)
+        } else {
+            (x0, y0, x1, y1)
+        };
+        let (x0, y0, x1, y1) = if x0 > x1 {
+            (x1, y1, x0, y0)
+        } else {
+            (x0, y0, x1, y1)
+        };
+
+        let dx = x1 - x0;
+        let gradient = if dx == 0. { 0. } else { (y1 - y0) / dx };
+
+        // pixel centers are at half-integers
+        let start = (x0 - 0.5).round() as isize;
+        let end = (x1 - 0.5).round() as isize;
+
+        for x in start..=end {
+            let y = y0 + gradient * (x as f32 + 0.5 - x0) - 0.5;
+            let y_floor = y.floor();
+            let frac = y - y_floor;
+
+            let (a, b) = (y_floor as isize, y_floor as isize + 1);
+            if steep {
+                self.blend_pixel(a, x, color, 1. - frac);
+                self.blend_pixel(b, x, color, frac);
+            } else {
+                self.blend_pixel(x, a, color, 1. - frac);
+                self.blend_pixel(x, b, color, frac);
+            }
+        }
+    }
+}
+
+const GLYPH_WIDTH: usize = 5;
+const GLYPH_HEIGHT: usize = 7;
+
+/// Size in pixels of `text` drawn by [Image::draw_text].
+pub(crate) fn text_size(text: &str, scale: usize) -> (usize, usize) {
+    let len = text.chars().count();
+    let width = (len * (GLYPH_WIDTH + 1)).saturating_sub(1) * scale;
+    (width, GLYPH_HEIGHT * scale)
+}
+
+/// Rows of a 5x7 glyph, the leftmost pixel in the highest bit
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    match c.to_ascii_uppercase() {
+        ' ' => [0; 7],
+        '0' => [
+            0b01110, 0b10001, 0b10011, 0b10101, 0b11001, 0b10001, 0b01110,
+        ],
+        '1' => [
+            0b00100, 0b01100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110,
+        ],
+        '2' => [
+            0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b01000, 0b11111,
+        ],
+        '3' => [
+            0b11111, 0b00010, 0b00100, 0b00010, 0b00001, 0b10001, 0b01110,
+        ],
+        '4' => [
+            0b00010, 0b00110, 0b01010, 0b10010, 0b11111, 0b00010, 0b00010,
+        ],
+        '5' => [
+            0b11111, 0b10000, 0b11110, 0b00001, 0b00001, 0b10001, 0b01110,
+        ],
+        '6' => [
+            0b00110, 0b01000, 0b10000, 0b11110, 0b10001, 0b10001, 0b01110,
+        ],
+        '7' => [
+            0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b01000, 0b01000,
+        ],
+        '8' => [
+            0b01110, 0b10001, 0b10001, 0b01110, 0b10001, 0b10001, 0b01110,
+        ],
+        '9' => [
+            0b01110, 0b10001, 0b10001, 0b01111, 0b00001, 0b00010, 0b01100,
+        ],
+        'A' => [
+            0b01110, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001,
+        ],
+        'B' => [
+            0b11110, 0b10001, 0b10001, 0b11110, 0b10001, 0b10001, 0b11110,
+        ],
+        'C' => [
+            0b01110, 0b10001, 0b10000, 0b10000, 0b10000, 0b10001, 0b01110,
+        ],
+        'D' => [
+            0b11100, 0b10010, 0b10001, 0b10001, 0b10001, 0b10010, 0b11100,
+        ],
+        'E' => [
+            0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b11111,
+        ],
+        'F' => [
+            0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b10000,
+        ],
+        'G' => [
+            0b01110, 0b10001, 0b10000, 0b10111, 0b10001, 0b10001, 0b01111,
+        ],
+        'H' => [
+            0b10001, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001,
+        ],
+        'I' => [
+            0b01110, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110,
+        ],
+        'J' => [
+            0b00111, 0b00010, 0b00010, 0b00010, 0b00010, 0b10010, 0b01100,
+        ],
+        'K' => [
+            0b10001, 0b10010, 0b10100, 0b11000, 0b10100, 0b10010, 0b10001,
+        ],
+        'L' => [
+            0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b11111,
+        ],
+        'M' => [
+            0b10001, 0b11011, 0b10101, 0b10101, 0b10001, 0b10001, 0b10001,
+        ],
+        'N' => [
+            0b10001, 0b10001, 0b11001, 0b10101, 0b10011, 0b10001, 0b10001,
+        ],
+        'O' => [
+            0b01110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110,
+        ],
+        'P' => [
+            0b11110, 0b10001, 0b10001, 0b11110, 0b10000, 0b10000, 0b10000,
+        ],
+        'Q' => [
+            0b01110, 0b10001, 0b10001, 0b10001, 0b10101, 0b10010, 0b01101,
+        ],
+        'R' => [
+            0b11110, 0b10001, 0b10001, 0b11110, 0b10100, 0b10010, 0b10001,
+        ],
+        'S' => [
+            0b01111, 0b10000, 0b10000, 0b01110, 0b00001, 0b00001, 0b11110,
+        ],
+        'T' => [
+            0b11111, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100,
+        ],
+        'U' => [
+            0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110,
+        ],
+        'V' => [
+            0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01010, 0b00100,
+        ],
+        'W' => [
+            0b10001, 0b10001, 0b10001, 0b10101, 0b10101, 0b10101, 0b01010,
+        ],
+        'X' => [
+            0b10001, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001, 0b10001,
+        ],
+        'Y' => [
+            0b10001, 0b10001, 0b10001, 0b01010, 0b00100, 0b00100, 0b00100,
+        ],
+        'Z' => [
+            0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b11111,
+        ],
+        '.' => [
+            0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b01100, 0b01100,
+        ],
+        ',' => [
+            0b00000, 0b00000, 0b00000, 0b00000, 0b01100, 0b00100, 0b01000,
+        ],
+        '-' => [
+            0b00000, 0b00000, 0b00000, 0b11111, 0b00000, 0b00000, 0b00000,
+        ],
+        '+' => [
+            0b00000, 0b00100, 0b00100, 0b11111, 0b00100, 0b00100, 0b00000,
+        ],
+        ':' => [
+            0b00000, 0b01100, 0b01100, 0b00000, 0b01100, 0b01100, 0b00000,
+        ],
+        '=' => [
+            0b00000, 0b00000, 0b11111, 0b00000, 0b11111, 0b00000, 0b00000,
+        ],
+        '/' => [
+            0b00000, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b00000,
+        ],
+        '(' => [
+            0b00010, 0b00100, 0b01000, 0b01000, 0b01000, 0b00100, 0b00010,
+        ],
+        ')' => [
+            0b01000, 0b00100, 0b00010, 0b00010, 0b00010, 0b00100, 0b01000,
+        ],
+        '%' => [
+            0b11000, 0b11001, 0b00010, 0b00100, 0b01000, 0b10011, 0b00011,
+        ],
+        '_' => [
+            0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b11111,
+        ],
+        '|' => [0b00100; 7],
+        _ => [
+            0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b00000, 0b00100,
+        ],
+    }
 }
 
 impl TryFrom<Image> for web_sys::ImageData {
@@ -71,6 +424,181 @@ impl TryFrom<Image> for web_sys::ImageData {
     }
 }
 
+/// How a field is sampled between the cell centers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interpolation {
+    /// One flat color per cell
+    Nearest,
+    /// Bilinear interpolation of the cell centers
+    Bilinear,
+    /// Bicubic (Catmull-Rom) interpolation of the cell centers
+    Bicubic,
+}
+
+impl FromStr for Interpolation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nearest" => Ok(Interpolation::Nearest),
+            "bilinear" => Ok(Interpolation::Bilinear),
+            "bicubic" => Ok(Interpolation::Bicubic),
+            _ => Err(format!("unknown interpolation: {}", s)),
+        }
+    }
+}
+
+/// How the range of the colormap is chosen
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorRange {
+    /// Range of the field, recomputed every frame
+    Auto,
+    /// A range set by the user
+    Fixed { min: f32, max: f32 },
+    /// Range of the field made symmetric around zero
+    Symmetric,
+    /// Range of the field without the `percent` % lowest and highest values
+    Percentile { percent: f32 },
+    /// Range of the field, exponentially smoothed across frames: each frame
+    /// moves the range by `rate` (in `(0, 1]`) of the way to the new one
+    Smoothed { rate: f32 },
+}
+
+/// A cell-centered scalar field, indexed like the fields of [Fluid](crate::simu::Fluid)
+///
+/// Only the interior cells are sampled: coordinates are in cells, with the
+/// center of cell (i, j) at (i, j), and are clamped to the interior.
+pub(crate) struct ScalarField<'a> {
+    data: &'a [f32],
+    num_x: usize,
+    num_y: usize,
+}
+
+impl<'a> ScalarField<'a> {
+    pub(crate) fn new(data: &'a [f32], num_x: usize, num_y: usize) -> Self {
+        debug_assert_eq!(data.len(), num_x * num_y);
+        Self { data, num_x, num_y }
+    }
+
+    /// value of the cell (i, j), clamped to the interior cells
+    fn at(&self, i: isize, j: isize) -> f32 {
+        let i = i.clamp(1, self.num_x as isize - 2) as usize;
+        let j = j.clamp(1, self.num_y as isize - 2) as usize;
+        self.data[i * self.num_y + j]
+    }
+
+    /// Sample the field at (x, y).
+    pub(crate) fn sample(&self, x: f32, y: f32, interpolation: Interpolation) -> f32 {
+        let x = x.clamp(1., self.num_x as f32 - 2.);
+        let y = y.clamp(1., self.num_y as f32 - 2.);
+
+        match interpolation {
+            Interpolation::Nearest => self.at(x.round() as isize, y.round() as isize),
+            Interpolation::Bilinear => {
+                let (i, j) = (x.floor() as isize, y.floor() as isize);
+                let (tx, ty) = (x - x.floor(), y - y.floor());
+
+                let bottom = lerp(self.at(i, j), self.at(i + 1, j), tx);
+                let top = lerp(self.at(i, j + 1), self.at(i + 1, j + 1), tx);
+                lerp(bottom, top, ty)
+            }
+            Interpolation::Bicubic => {
+                let (i, j) = (x.floor() as isize, y.floor() as isize);
+                let (tx, ty) = (x - x.floor(), y - y.floor());
+
+                let row = |j| {
+                    catmull_rom(
+                        [
+                            self.at(i - 1, j),
+                            self.at(i, j),
+                            self.at(i + 1, j),
+                            self.at(i + 2, j),
+                        ],
+                        tx,
+                    )
+                };
+                catmull_rom([row(j - 1), row(j), row(j + 1), row(j + 2)], ty)
+            }
+        }
+    }
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+/// Catmull-Rom spline through `p[1]` (t = 0) and `p[2]` (t = 1)
+fn catmull_rom(p: [f32; 4], t: f32) -> f32 {
+    let t2 = t * t;
+    let t3 = t2 * t;
+
+    0.5 * ((2. * p[1])
+        + (-p[0] + p[2]) * t
+        + (2. * p[0] - 5. * p[1] + 4. * p[2] - p[3]) * t2
+        + (-p[0] + 3. * p[1] - 3. * p[2] + p[3]) * t3)
+}
+
+/// Trace the iso-line `value = level` of a field with marching squares.
+///
+/// The squares join the centers of four interior cells; squares with a corner
+/// for which `skip` returns true are ignored. Returns segments in cell
+/// coordinates (see [ScalarField]).
+pub(crate) fn contour_segments(
+    field: &ScalarField,
+    level: f32,
+    skip: impl Fn(usize, usize) -> bool,
+) -> Vec<[(f32, f32); 2]> {
+    let mut segments = vec![];
+
+    for i in 1..field.num_x - 2 {
+        for j in 1..field.num_y - 2 {
+            // counter-clockwise from the bottom left corner
+            let corners = [(i, j), (i + 1, j), (i + 1, j + 1), (i, j + 1)];
+            if corners.iter().any(|&(i, j)| skip(i, j)) {
+                continue;
+            }
+
+            let values = corners.map(|(i, j)| field.data[i * field.num_y + j]);
+            let above = values.map(|v| v >= level);
+
+            // crossing of each edge: bottom, right, top, left
+            let mut crossings = [None; 4];
+            for (a, crossing) in crossings.iter_mut().enumerate() {
+                let b = (a + 1) % 4;
+                if above[a] != above[b] {
+                    let t = (level - values[a]) / (values[b] - values[a]);
+                    let (xa, ya) = corners[a];
+                    let (xb, yb) = corners[b];
+                    *crossing =
+                        Some((lerp(xa as f32, xb as f32, t), lerp(ya as f32, yb as f32, t)));
+                }
+            }
+
+            match crossings {
+                [Some(bottom), Some(right), Some(top), Some(left)] => {
+                    // saddle: disambiguate with the value at the center
+                    let center = values.iter().sum::<f32>() / 4.;
+                    if (center >= level) == above[0] {
+                        segments.push([bottom, right]);
+                        segments.push([top, left]);
+                    } else {
+                        segments.push([left, bottom]);
+                        segments.push([right, top]);
+                    }
+                }
+                _ => {
+                    let mut points = crossings.iter().flatten();
+                    if let (Some(&a), Some(&b)) = (points.next(), points.next()) {
+                        segments.push([a, b]);
+                    }
+                }
+            }
+        }
+    }
+
+    segments
+}
+
 /// A Colormap
 pub(crate) trait Colormap {
     /// Get the color for a value in a range
@@ -104,25 +632,6 @@ impl Colormap for JetColormap {
     }
 }
 
-pub(crate) struct CoolWarmColormap {}
-
-impl Colormap for CoolWarmColormap {
-    fn get_color(&self, x: f32, min_: f32, max_: f32) -> [u8; 4] {
-        let x = f32::min(f32::max(x, min_), max_ - 0.0001);
-        let d = max_ - min_;
-        let x = if d == 0. { 0.5 } else { (x - min_) / d };
-
-        let r = 0.5 * (1.0 + x);
-        let b = 1.0 - r;
-        let g = 1.0 - (r + b);
-
-        let r = (r * 255.0) as u8;
-        let g = (g * 255.0) as u8;
-        let b = (b * 255.0) as u8;
-        [r, g, b, 255]
-    }
-}
-
 /// Rainbow colormap
 pub(crate) struct RainbowColormap {}
 
@@ -147,16 +656,257 @@ impl Colormap for RainbowColormap {
     }
 }
 
-pub(crate) fn colormap(colormap: &str) -> Box<dyn Colormap> {
-    match colormap {
+/// Names of the built-in colormaps
+pub const COLORMAPS: [&str; 10] = [
+    "viridis",
+    "magma",
+    "inferno",
+    "cividis",
+    "turbo",
+    "coolwarm",
+    "jet",
+    "rainbow",
+    "grayscale",
+    "grayscale_r",
+];
+
+thread_local! {
+    /// colormaps registered with [register_colormap]
+    static CUSTOM_COLORMAPS: RefCell<HashMap<String, StopColormap>> = RefCell::new(HashMap::new());
+}
+
+/// Return the colormap `name`: a built-in one or one registered with [register_colormap].
+pub(crate) fn colormap(name: &str) -> Result<Box<dyn Colormap>, String> {
+    let colormap: Box<dyn Colormap> = match name {
+        "viridis" => Box::new(StopColormap::from_hex(&VIRIDIS)),
+        "magma" => Box::new(StopColormap::from_hex(&MAGMA)),
+        "inferno" => Box::new(StopColormap::from_hex(&INFERNO)),
+        "cividis" => Box::new(StopColormap::from_hex(&CIVIDIS)),
+        "turbo" => Box::new(StopColormap::from_hex(&TURBO)),
+        "coolwarm" => Box::new(StopColormap::from_hex(&COOLWARM)),
         "jet" => Box::new(JetColormap {}),
-        "coolwarm" => Box::new(CoolWarmColormap {}),
         "rainbow" => Box::new(RainbowColormap {}),
         "grayscale" => Box::new(GrayscaleColormap {}),
-        _ => panic!("unknown colormap"),
+        "grayscale_r" => Box::new(StopColormap::from_hex(&["#ffffff", "#000000"])),
+        _ => {
+            let custom = CUSTOM_COLORMAPS.with(|c| c.borrow().get(name).cloned());
+            match custom {
+                Some(colormap) => Box::new(colormap),
+                None => return Err(format!("unknown colormap: {}", name)),
+            }
+        }
+    };
+
+    Ok(colormap)
+}
+
+/// Register `colormap` under `name`, replacing a custom colormap with the same name.
+///
+/// The built-in colormaps cannot be replaced.
+pub fn register_colormap(name: &str, colormap: StopColormap) -> Result<(), String> {
+    if COLORMAPS.contains(&name) {
+        return Err(format!("{} is a built-in colormap", name));
+    }
+
+    CUSTOM_COLORMAPS.with(|c| c.borrow_mut().insert(name.to_string(), colormap));
+    Ok(())
+}
+
+/// Register a colormap defined by JSON stops, see [StopColormap::from_json].
+#[wasm_bindgen(js_name = register_colormap)]
+pub fn register_colormap_json(name: &str, json: &str) -> Result<(), JsValue> {
+    let colormap = StopColormap::from_json(json).map_err(|e| JsValue::from_str(&e))?;
+    register_colormap(name, colormap).map_err(|e| JsValue::from_str(&e))
+}
+
+/// A colormap interpolating linearly between colored stops
+#[derive(Clone, Debug)]
+pub struct StopColormap {
+    /// 256 colors sampled evenly from the stops
+    lut: Vec<[u8; 4]>,
+}
+
+impl StopColormap {
+    /// Create a colormap from `(position, [r, g, b])` stops.
+    ///
+    /// The positions must be increasing; they are rescaled so that the first
+    /// stop is at 0 and the last one at 1.
+    pub fn new(stops: &[(f32, [u8; 3])]) -> Result<Self, String> {
+        if stops.len() < 2 {
+            return Err("a colormap needs at least 2 stops".to_string());
+        }
+        if stops.iter().any(|(x, _)| !x.is_finite()) {
+            return Err("the positions of the stops must be finite".to_string());
+        }
+        if stops.windows(2).any(|w| w[1].0 < w[0].0) {
+            return Err("the positions of the stops must be increasing".to_string());
+        }
+
+        let first = stops[0].0;
+        let last = stops[stops.len() - 1].0;
+        if first == last {
+            return Err("the stops must span a range".to_string());
+        }
+
+        let lut = (0..256)
+            .map(|k| {
+                let x = first + (last - first) * k as f32 / 255.;
+
+                // first stop after x, the last segment includes its end
+                let next = stops
+                    .iter()
+                    .position(|&(p, _)| p > x)
+                    .unwrap_or(stops.len() - 1)
+                    .max(1);
+                let (x0, c0) = stops[next - 1];
+                let (x1, c1) = stops[next];
+
+                let t = if x1 == x0 {
+                    1.
+                } else {
+                    ((x - x0) / (x1 - x0)).clamp(0., 1.)
+                };
+                let mix = |a: u8, b: u8| lerp(a as f32, b as f32, t).round() as u8;
+                [mix(c0[0], c1[0]), mix(c0[1], c1[1]), mix(c0[2], c1[2]), 255]
+            })
+            .collect();
+
+        Ok(Self { lut })
+    }
+
+    /// Create a colormap from evenly spaced `#rrggbb` colors.
+    fn from_hex(colors: &[&str]) -> Self {
+        let stops: Vec<_> = colors
+            .iter()
+            .enumerate()
+            .map(|(k, hex)| {
+                let position = k as f32 / (colors.len() - 1) as f32;
+                (position, parse_hex(hex).expect("invalid built-in color"))
+            })
+            .collect();
+
+        Self::new(&stops).expect("invalid built-in colormap")
+    }
+
+    /// Create a colormap from JSON stops.
+    ///
+    /// Either a list of colors, evenly spaced, or a list of `[position, color]`
+    /// pairs, where a color is `"#rrggbb"` or `[r, g, b]` with components in 0..=255:
+    ///
+    /// ```json
+    /// ["#3b4cc0", "#dddddd", "#b40426"]
+    /// [[0, [0, 0, 0]], [0.8, "#ff0000"], [1, "#ffffff"]]
+    /// ```
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        let value: serde_json::Value =
+            serde_json::from_str(json).map_err(|e| format!("invalid JSON: {}", e))?;
+
+        let items = value
+            .as_array()
+            .ok_or_else(|| "the stops must be an array".to_string())?;
+
+        let mut stops = Vec::with_capacity(items.len());
+        for (k, item) in items.iter().enumerate() {
+            let (position, color) = match item.as_array() {
+                Some(pair) if pair.len() == 2 && pair[0].is_number() => {
+                    (pair[0].as_f64().unwrap_or_default() as f32, &pair[1])
+                }
+                _ => (k as f32 / (items.len().max(2) - 1) as f32, item),
+            };
+
+            stops.push((position, json_color(color)?));
+        }
+
+        Self::new(&stops)
     }
 }
 
+impl Colormap for StopColormap {
+    fn get_color(&self, x: f32, min_: f32, max_: f32) -> [u8; 4] {
+        let d = max_ - min_;
+        let x = if d == 0. { 0.5 } else { (x - min_) / d };
+
+        // NaN maps to the first color
+        let index = (x.clamp(0., 1.) * 255.).round() as usize;
+        self.lut[index.min(255)]
+    }
+}
+
+/// Parse a `#rrggbb` color.
+fn parse_hex(hex: &str) -> Result<[u8; 3], String> {
+    let digits = hex
+        .strip_prefix('#')
+        .filter(|d| d.len() == 6 && d.is_ascii())
+        .ok_or_else(|| format!("invalid color: {}", hex))?;
+
+    let mut color = [0; 3];
+    for (k, c) in color.iter_mut().enumerate() {
+        *c = u8::from_str_radix(&digits[2 * k..2 * k + 2], 16)
+            .map_err(|_| format!("invalid color: {}", hex))?;
+    }
+
+    Ok(color)
+}
+
+/// Parse a JSON color: `"#rrggbb"` or `[r, g, b]`.
+fn json_color(value: &serde_json::Value) -> Result<[u8; 3], String> {
+    if let Some(hex) = value.as_str() {
+        return parse_hex(hex);
+    }
+
+    match value.as_array().map(|c| c.as_slice()) {
+        Some([r, g, b]) => {
+            let mut color = [0; 3];
+            for (c, v) in color.iter_mut().zip([r, g, b]) {
+                *c = v
+                    .as_u64()
+                    .filter(|&v| v <= 255)
+                    .ok_or_else(|| format!("invalid color component: {}", v))?
+                    as u8;
+            }
+            Ok(color)
+        }
+        _ => Err(format!("invalid color: {}", value)),
+    }
+}
+
+// Control points of the built-in colormaps, evenly spaced.
+// viridis, magma, inferno and cividis are sampled from matplotlib, turbo from
+// Google's reference implementation and coolwarm is Moreland's diverging map.
+
+const VIRIDIS: [&str; 10] = [
+    "#440154", "#482878", "#3e4989", "#31688e", "#26828e", "#1f9e89", "#35b779", "#6ece58",
+    "#b5de2b", "#fde725",
+];
+
+const MAGMA: [&str; 10] = [
+    "#000004", "#180f3d", "#440f76", "#721f81", "#9e2f7f", "#cd4071", "#f1605d", "#fd9668",
+    "#feca8d", "#fcfdbf",
+];
+
+const INFERNO: [&str; 10] = [
+    "#000004", "#1b0c41", "#4a0c6b", "#781c6d", "#a52c60", "#cf4446", "#ed6925", "#fb9b06",
+    "#f7d13d", "#fcffa4",
+];
+
+const CIVIDIS: [&str; 10] = [
+    "#00224e", "#123570", "#3b496c", "#575d6d", "#707173", "#8a8779", "#a69d75", "#c4b56c",
+    "#e4cf5b", "#fee838",
+];
+
+const TURBO: [&str; 15] = [
+    "#30123b", "#4145ab", "#4675ed", "#39a2fc", "#1bcfd4", "#24eca6", "#61fc6c", "#a4fc3b",
+    "#d1e834", "#f3c63a", "#fe9b2d", "#f36315", "#d93806", "#b11901", "#7a0403",
+];
+
+const COOLWARM: [&str; 33] = [
+    "#3b4cc0", "#445acc", "#4d68d7", "#5775e1", "#6282ea", "#6c8ef1", "#779af7", "#82a5fb",
+    "#8db0fe", "#98b9ff", "#a3c2ff", "#aec9fd", "#b8d0f9", "#c2d5f4", "#ccd9ee", "#d5dbe6",
+    "#dddddd", "#e5d8d1", "#ecd3c5", "#f1ccb9", "#f5c4ad", "#f7bba0", "#f7b194", "#f7a687",
+    "#f49a7b", "#f18d6f", "#ec7f63", "#e57058", "#de604d", "#d55042", "#cb3e38", "#c0282f",
+    "#b40426",
+];
+
 /// Grayscale colormap
 pub(crate) struct GrayscaleColormap {}
 
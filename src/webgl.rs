@@ -0,0 +1,749 @@
+//! WebGL2 rendering backend
+//!
+//! The scalar field is uploaded as a float texture and the colormap is
+//! applied in the fragment shader through a lookup texture; the streamlines
+//! are drawn from a single line buffer.
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::WebGl2RenderingContext as GL;
+use web_sys::{
+    HtmlCanvasElement, WebGlBuffer, WebGlProgram, WebGlShader, WebGlTexture, WebGlUniformLocation,
+    WebGlVertexArrayObject,
+};
+
+use crate::flowlines::Flowlines;
+use crate::lic::{LicCache, LicOptions};
+use crate::overlay;
+use crate::particles::Particles;
+use crate::simu::{DrawOptions, Fluid, Quantity};
+use crate::visualization::{self, Image, Interpolation};
+
+const FIELD_VERTEX_SHADER: &str = r#"#version 300 es
+in vec2 a_position;
+out vec2 v_uv;
+
+void main() {
+    v_uv = a_position * 0.5 + 0.5;
+    gl_Position = vec4(a_position, 0.0, 1.0);
+}
+"#;
+
+const FIELD_FRAGMENT_SHADER: &str = r#"#version 300 es
+precision highp float;
+
+// r: value, g: 1 for obstacles
+uniform sampler2D u_field;
+uniform sampler2D u_colormap;
+uniform float u_min;
+uniform float u_max;
+uniform bool u_show_field;
+uniform bool u_show_obstacle;
+// 0: nearest, 1: bilinear, 2: bicubic
+uniform int u_interpolation;
+// line integral convolution, rows from the top
+uniform sampler2D u_lic;
+uniform bool u_show_lic;
+
+in vec2 v_uv;
+out vec4 color;
+
+// texel (i, j), clamped to the texture
+vec2 fetch(ivec2 ij) {
+    return texelFetch(u_field, clamp(ij, ivec2(0), textureSize(u_field, 0) - 1), 0).rg;
+}
+
+vec2 bilinear(vec2 p) {
+    vec2 p0 = floor(p);
+    vec2 t = p - p0;
+    ivec2 ij = ivec2(p0);
+
+    return mix(
+        mix(fetch(ij), fetch(ij + ivec2(1, 0)), t.x),
+        mix(fetch(ij + ivec2(0, 1)), fetch(ij + ivec2(1, 1)), t.x),
+        t.y);
+}
+
+// Catmull-Rom weights
+vec4 weights(float t) {
+    float t2 = t * t;
+    float t3 = t2 * t;
+    return 0.5 * vec4(
+        -t3 + 2.0 * t2 - t,
+        3.0 * t3 - 5.0 * t2 + 2.0,
+        -3.0 * t3 + 4.0 * t2 + t,
+        t3 - t2);
+}
+
+float bicubic(vec2 p) {
+    vec2 p0 = floor(p);
+    vec4 wx = weights(p.x - p0.x);
+    vec4 wy = weights(p.y - p0.y);
+    ivec2 ij = ivec2(p0);
+
+    float value = 0.0;
+    for (int b = 0; b < 4; b++) {
+        float row = 0.0;
+        for (int a = 0; a < 4; a++) {
+            row += wx[a] * fetch(ij + ivec2(a - 1, b - 1)).r;
+        }
+        value += wy[b] * row;
+    }
+    return value;
+}
+
+void main() {
+    vec2 f = texture(u_field, v_uv).rg;
+
+    if (u_interpolation != 0) {
+        // texel centers are at integer coordinates
+        vec2 p = v_uv * vec2(textureSize(u_field, 0)) - 0.5;
+        // the obstacle outline is always bilinear: bicubic would overshoot
+        f = bilinear(p);
+        if (u_interpolation == 2) {
+            f.r = bicubic(p);
+        }
+    }
+
+    if (u_show_obstacle && f.g > 0.5) {
+        color = vec4(0.0, 0.0, 0.0, 1.0);
+        return;
+    }
+
+    // see lic::apply
+    float g = texture(u_lic, vec2(v_uv.x, 1.0 - v_uv.y)).r;
+    if (!u_show_field) {
+        if (!u_show_lic) {
+            discard;
+        }
+        color = vec4(vec3(g), 1.0);
+        return;
+    }
+
+    float d = u_max - u_min;
+    float x = d == 0.0 ? 0.5 : clamp((f.r - u_min) / d, 0.0, 1.0);
+    // sample at the texel centers of the colormap
+    color = texture(u_colormap, vec2((x * 255.0 + 0.5) / 256.0, 0.5));
+    if (u_show_lic) {
+        color.rgb *= 0.3 + 0.7 * g;
+    }
+}
+"#;
+
+const OVERLAY_FRAGMENT_SHADER: &str = r#"#version 300 es
+precision highp float;
+
+// premultiplied alpha, rows from the top
+uniform sampler2D u_overlay;
+
+in vec2 v_uv;
+out vec4 color;
+
+void main() {
+    color = texture(u_overlay, vec2(v_uv.x, 1.0 - v_uv.y));
+}
+"#;
+
+const LINE_VERTEX_SHADER: &str = r#"#version 300 es
+// physical coordinates
+in vec2 a_position;
+uniform vec2 u_origin;
+uniform vec2 u_size;
+
+void main() {
+    gl_Position = vec4((a_position - u_origin) / u_size * 2.0 - 1.0, 0.0, 1.0);
+}
+"#;
+
+const LINE_FRAGMENT_SHADER: &str = r#"#version 300 es
+precision highp float;
+
+uniform vec4 u_color;
+out vec4 color;
+
+void main() {
+    color = u_color;
+}
+"#;
+
+fn compile_shader(gl: &GL, kind: u32, source: &str) -> Result<WebGlShader, JsValue> {
+    let shader = gl
+        .create_shader(kind)
+        .ok_or_else(|| JsValue::from_str("unable to create shader"))?;
+    gl.shader_source(&shader, source);
+    gl.compile_shader(&shader);
+
+    if gl
+        .get_shader_parameter(&shader, GL::COMPILE_STATUS)
+        .as_bool()
+        .unwrap_or(false)
+    {
+        Ok(shader)
+    } else {
+        let log = gl.get_shader_info_log(&shader).unwrap_or_default();
+        Err(JsValue::from_str(&format!(
+            "failed to compile shader: {}",
+            log
+        )))
+    }
+}
+
+fn link_program(gl: &GL, vertex: &str, fragment: &str) -> Result<WebGlProgram, JsValue> {
+    let vertex = compile_shader(gl, GL::VERTEX_SHADER, vertex)?;
+    let fragment = compile_shader(gl, GL::FRAGMENT_SHADER, fragment)?;
+
+    let program = gl
+        .create_program()
+        .ok_or_else(|| JsValue::from_str("unable to create program"))?;
+    gl.attach_shader(&program, &vertex);
+    gl.attach_shader(&program, &fragment);
+    // a_position is the only attribute of both programs
+    gl.bind_attrib_location(&program, 0, "a_position");
+    gl.link_program(&program);
+
+    if gl
+        .get_program_parameter(&program, GL::LINK_STATUS)
+        .as_bool()
+        .unwrap_or(false)
+    {
+        Ok(program)
+    } else {
+        let log = gl.get_program_info_log(&program).unwrap_or_default();
+        Err(JsValue::from_str(&format!(
+            "failed to link program: {}",
+            log
+        )))
+    }
+}
+
+fn uniform(gl: &GL, program: &WebGlProgram, name: &str) -> Result<WebGlUniformLocation, JsValue> {
+    gl.get_uniform_location(program, name)
+        .ok_or_else(|| JsValue::from_str(&format!("no uniform {}", name)))
+}
+
+fn create_texture(gl: &GL, filter: u32) -> Result<WebGlTexture, JsValue> {
+    let texture = gl
+        .create_texture()
+        .ok_or_else(|| JsValue::from_str("unable to create texture"))?;
+
+    gl.bind_texture(GL::TEXTURE_2D, Some(&texture));
+    gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_MIN_FILTER, filter as i32);
+    gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_MAG_FILTER, filter as i32);
+    gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_WRAP_S, GL::CLAMP_TO_EDGE as i32);
+    gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_WRAP_T, GL::CLAMP_TO_EDGE as i32);
+
+    Ok(texture)
+}
+
+/// Bind `buffer` as the only (2D) attribute of `vao`.
+fn create_vao(gl: &GL, buffer: &WebGlBuffer) -> Result<WebGlVertexArrayObject, JsValue> {
+    let vao = gl
+        .create_vertex_array()
+        .ok_or_else(|| JsValue::from_str("unable to create vertex array"))?;
+
+    gl.bind_vertex_array(Some(&vao));
+    gl.bind_buffer(GL::ARRAY_BUFFER, Some(buffer));
+    gl.enable_vertex_attrib_array(0);
+    gl.vertex_attrib_pointer_with_i32(0, 2, GL::FLOAT, false, 0, 0);
+    gl.bind_vertex_array(None);
+
+    Ok(vao)
+}
+
+fn create_buffer(gl: &GL) -> Result<WebGlBuffer, JsValue> {
+    gl.create_buffer()
+        .ok_or_else(|| JsValue::from_str("unable to create buffer"))
+}
+
+/// Upload `data` to the currently bound `ARRAY_BUFFER`.
+fn upload_buffer(gl: &GL, data: &[f32]) {
+    // SAFETY: the view is consumed before anything can allocate
+    let view = unsafe { js_sys::Float32Array::view(data) };
+    gl.buffer_data_with_array_buffer_view(GL::ARRAY_BUFFER, &view, GL::DYNAMIC_DRAW);
+}
+
+/// What the colorbar and the axes depend on
+#[derive(PartialEq)]
+struct StaticOverlays {
+    /// size of the overlay image, in pixels
+    size: (usize, usize),
+    /// (num_x, num_y, h) of the fluid
+    grid: (usize, usize, f32),
+    axes: bool,
+    colorbar: bool,
+    field: Quantity,
+    /// colors of the colormap, see [WebGlRenderer::update_colormap]
+    colormap: Vec<u8>,
+    range: (f32, f32),
+}
+
+/// Renders a [Fluid] with WebGL2.
+pub(crate) struct WebGlRenderer {
+    gl: GL,
+
+    field_program: WebGlProgram,
+    quad_vao: WebGlVertexArrayObject,
+    field_texture: WebGlTexture,
+    colormap_texture: WebGlTexture,
+    /// colors in `colormap_texture`
+    colormap_lut: Vec<u8>,
+
+    u_field: WebGlUniformLocation,
+    u_colormap: WebGlUniformLocation,
+    u_min: WebGlUniformLocation,
+    u_max: WebGlUniformLocation,
+    u_show_field: WebGlUniformLocation,
+    u_show_obstacle: WebGlUniformLocation,
+    u_interpolation: WebGlUniformLocation,
+
+    /// line integral convolution of the velocity, see [crate::lic::lic]
+    lic_texture: WebGlTexture,
+    /// texture uploaded to `lic_texture`, recomputed only when it is outdated
+    lic: LicCache,
+    lic_data: Vec<u8>,
+    u_lic: WebGlUniformLocation,
+    u_show_lic: WebGlUniformLocation,
+
+    line_program: WebGlProgram,
+    line_vao: WebGlVertexArrayObject,
+    line_buffer: WebGlBuffer,
+
+    u_origin: WebGlUniformLocation,
+    u_size: WebGlUniformLocation,
+    u_color: WebGlUniformLocation,
+
+    /// arrows, particles and flow lines, drawn every frame
+    layers_texture: WebGlTexture,
+
+    /// draws `layers_texture` or `overlay_texture` over the canvas
+    overlay_program: WebGlProgram,
+    u_overlay: WebGlUniformLocation,
+    /// colorbar and axes, redrawn when they change, with the HUD updated in place
+    overlay_texture: WebGlTexture,
+    /// what `overlay_texture` was drawn for
+    overlay_key: Option<StaticOverlays>,
+    /// colorbar and axes in `overlay_texture`, without the HUD
+    overlay_image: Option<Image>,
+    /// width of the y axis, where the HUD starts
+    overlay_left: usize,
+    /// (x, y, width, height) of the HUD in `overlay_texture`, if drawn
+    hud_rect: Option<(usize, usize, usize, usize)>,
+
+    /// (value, obstacle) of the interior cells, x varying fastest
+    field_data: Vec<f32>,
+    /// end points of the streamline or contour segments
+    line_data: Vec<f32>,
+}
+
+impl WebGlRenderer {
+    /// Create a renderer drawing to `canvas`.
+    ///
+    /// Fails if the canvas has no WebGL2 context, e.g. because it already has a 2D one.
+    pub(crate) fn new(canvas: &HtmlCanvasElement) -> Result<Self, JsValue> {
+        let gl = canvas
+            .get_context("webgl2")?
+            .ok_or_else(|| JsValue::from_str("WebGL2 is not available"))?
+            .dyn_into::<GL>()?;
+
+        let field_program = link_program(&gl, FIELD_VERTEX_SHADER, FIELD_FRAGMENT_SHADER)?;
+        let line_program = link_program(&gl, LINE_VERTEX_SHADER, LINE_FRAGMENT_SHADER)?;
+        let overlay_program = link_program(&gl, FIELD_VERTEX_SHADER, OVERLAY_FRAGMENT_SHADER)?;
+
+        // full screen quad, as a triangle strip
+        let quad_buffer = create_buffer(&gl)?;
+        gl.bind_buffer(GL::ARRAY_BUFFER, Some(&quad_buffer));
+        upload_buffer(&gl, &[-1., -1., 1., -1., -1., 1., 1., 1.]);
+        let quad_vao = create_vao(&gl, &quad_buffer)?;
+
+        let line_buffer = create_buffer(&gl)?;
+        let line_vao = create_vao(&gl, &line_buffer)?;
+
+        // float textures are not filterable without an extension
+        let field_texture = create_texture(&gl, GL::NEAREST)?;
+        let colormap_texture = create_texture(&gl, GL::LINEAR)?;
+        let layers_texture = create_texture(&gl, GL::LINEAR)?;
+        let overlay_texture = create_texture(&gl, GL::LINEAR)?;
+        let lic_texture = create_texture(&gl, GL::LINEAR)?;
+
+        Ok(Self {
+            u_field: uniform(&gl, &field_program, "u_field")?,
+            u_colormap: uniform(&gl, &field_program, "u_colormap")?,
+            u_min: uniform(&gl, &field_program, "u_min")?,
+            u_max: uniform(&gl, &field_program, "u_max")?,
+            u_show_field: uniform(&gl, &field_program, "u_show_field")?,
+            u_show_obstacle: uniform(&gl, &field_program, "u_show_obstacle")?,
+            u_interpolation: uniform(&gl, &field_program, "u_interpolation")?,
+            u_lic: uniform(&gl, &field_program, "u_lic")?,
+            u_show_lic: uniform(&gl, &field_program, "u_show_lic")?,
+            u_origin: uniform(&gl, &line_program, "u_origin")?,
+            u_size: uniform(&gl, &line_program, "u_size")?,
+            u_color: uniform(&gl, &line_program, "u_color")?,
+            u_overlay: uniform(&gl, &overlay_program, "u_overlay")?,
+            gl,
+            field_program,
+            quad_vao,
+            field_texture,
+            colormap_texture,
+            colormap_lut: vec![],
+            lic_texture,
+            lic: LicCache::default(),
+            lic_data: vec![],
+            line_program,
+            line_vao,
+            line_buffer,
+            layers_texture,
+            overlay_program,
+            overlay_texture,
+            overlay_key: None,
+            overlay_image: None,
+            overlay_left: 0,
+            hud_rect: None,
+            field_data: vec![],
+            line_data: vec![],
+        })
+    }
+
+    /// Fill the colormap texture if the colormap changed.
+    ///
+    /// The colors are compared rather than the names, as a custom colormap can
+    /// be redefined.
+    fn update_colormap(&mut self, colormap: &dyn visualization::Colormap) -> Result<(), JsValue> {
+        let mut lut = Vec::with_capacity(4 * 256);
+        for i in 0..256 {
+            lut.extend_from_slice(&colormap.get_color(i as f32 / 255., 0., 1.));
+        }
+        if lut == self.colormap_lut {
+            return Ok(());
+        }
+
+        let gl = &self.gl;
+        gl.bind_texture(GL::TEXTURE_2D, Some(&self.colormap_texture));
+        gl.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+            GL::TEXTURE_2D,
+            0,
+            GL::RGBA as i32,
+            256,
+            1,
+            0,
+            GL::RGBA,
+            GL::UNSIGNED_BYTE,
+            Some(&lut),
+        )?;
+
+        self.colormap_lut = lut;
+        Ok(())
+    }
+
+    /// Redraw the colorbar and the axes into `overlay_texture` if they changed.
+    fn update_overlays(
+        &mut self,
+        fluid: &Fluid,
+        options: &DrawOptions,
+        colormap: &dyn visualization::Colormap,
+        range: (f32, f32),
+        ratio: usize,
+    ) -> Result<(), JsValue> {
+        let (nx, ny) = (fluid.num_x - 2, fluid.num_y - 2);
+        let key = StaticOverlays {
+            size: (nx * ratio, ny * ratio),
+            grid: (fluid.num_x, fluid.num_y, fluid.h),
+            axes: options.axes,
+            colorbar: options.colorbar && options.pressure,
+            field: options.field,
+            colormap: self.colormap_lut.clone(),
+            range,
+        };
+        if self.overlay_key.as_ref() == Some(&key) {
+            return Ok(());
+        }
+
+        let mut image = Image::new(nx, ny, ratio);
+        self.overlay_left = overlay::draw_static(fluid, &mut image, options, colormap, range);
+
+        let (width, height) = image.size();
+        let gl = &self.gl;
+        gl.active_texture(GL::TEXTURE0);
+        gl.bind_texture(GL::TEXTURE_2D, Some(&self.overlay_texture));
+        gl.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+            GL::TEXTURE_2D,
+            0,
+            GL::RGBA as i32,
+            width as i32,
+            height as i32,
+            0,
+            GL::RGBA,
+            GL::UNSIGNED_BYTE,
+            Some(image.data()),
+        )?;
+
+        self.overlay_image = Some(image);
+        self.overlay_key = Some(key);
+        // the new texture has no HUD
+        self.hud_rect = None;
+        Ok(())
+    }
+
+    /// Draw the HUD into `overlay_texture`, or erase it if it is disabled.
+    ///
+    /// Only the pixels of the HUD, and of the previous one, are uploaded.
+    fn update_hud(
+        &mut self,
+        fluid: &Fluid,
+        options: &DrawOptions,
+        fps: Option<f32>,
+    ) -> Result<(), JsValue> {
+        let Some(image) = &self.overlay_image else {
+            return Ok(());
+        };
+        let (width, height) = image.size();
+
+        let hud = options
+            .hud
+            .then(|| overlay::Hud::new(fluid, options, fps, self.overlay_left, height));
+        let current = hud
+            .as_ref()
+            .map(|hud| (hud.x, hud.y, hud.width, hud.height));
+
+        // the pixels covered by the previous HUD and by the new one, in the image
+        let Some((x0, y0, x1, y1)) = [self.hud_rect, current]
+            .into_iter()
+            .flatten()
+            .map(|(x, y, w, h)| (x, y, x + w, y + h))
+            .reduce(|a, b| (a.0.min(b.0), a.1.min(b.1), a.2.max(b.2), a.3.max(b.3)))
+        else {
+            return Ok(());
+        };
+        let (x1, y1) = (x1.min(width), y1.min(height));
+        self.hud_rect = current;
+        if x0 >= x1 || y0 >= y1 {
+            return Ok(());
+        }
+
+        let mut region = image.crop(x0, y0, x1 - x0, y1 - y0);
+        if let Some(hud) = &hud {
+            hud.draw(&mut region, (x0, y0));
+        }
+
+        let gl = &self.gl;
+        gl.active_texture(GL::TEXTURE0);
+        gl.bind_texture(GL::TEXTURE_2D, Some(&self.overlay_texture));
+        gl.tex_sub_image_2d_with_i32_and_i32_and_u32_and_type_and_opt_u8_array(
+            GL::TEXTURE_2D,
+            0,
+            x0 as i32,
+            y0 as i32,
+            (x1 - x0) as i32,
+            (y1 - y0) as i32,
+            GL::RGBA,
+            GL::UNSIGNED_BYTE,
+            Some(region.data()),
+        )
+    }
+
+    /// Blend `texture` (premultiplied RGBA, rows from the top) over the canvas.
+    fn draw_overlay(&self, texture: &WebGlTexture) {
+        let gl = &self.gl;
+        gl.active_texture(GL::TEXTURE0);
+        gl.bind_texture(GL::TEXTURE_2D, Some(texture));
+        gl.use_program(Some(&self.overlay_program));
+        gl.uniform1i(Some(&self.u_overlay), 0);
+
+        gl.enable(GL::BLEND);
+        gl.blend_func(GL::ONE, GL::ONE_MINUS_SRC_ALPHA);
+        gl.bind_vertex_array(Some(&self.quad_vao));
+        gl.draw_arrays(GL::TRIANGLE_STRIP, 0, 4);
+        gl.disable(GL::BLEND);
+    }
+
+    /// Forget the LIC texture: the velocity changed without a step.
+    pub(crate) fn clear_lic(&mut self) {
+        self.lic.clear();
+    }
+
+    /// Render the simulation, like [Fluid::render].
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn render(
+        &mut self,
+        fluid: &Fluid,
+        options: &DrawOptions,
+        field: &[f32],
+        (min, max): (f32, f32),
+        dt: f32,
+        particles: &Particles,
+        flowlines: &Flowlines,
+    ) -> Result<(), JsValue> {
+        let n = fluid.num_y;
+        let nx = fluid.num_x - 2;
+        let ny = fluid.num_y - 2;
+        let h = fluid.h;
+
+        let colormap =
+            visualization::colormap(&options.colormap).map_err(|e| JsValue::from_str(&e))?;
+        self.update_colormap(&*colormap)?;
+
+        // texture rows go up, like y
+        self.field_data.clear();
+        for j in 1..fluid.num_y - 1 {
+            for i in 1..fluid.num_x - 1 {
+                let obstacle = if fluid.s[i * n + j] == 0. { 1. } else { 0. };
+                self.field_data.push(field[i * n + j]);
+                self.field_data.push(obstacle);
+            }
+        }
+
+        let gl = &self.gl;
+
+        gl.viewport(0, 0, gl.drawing_buffer_width(), gl.drawing_buffer_height());
+        gl.clear_color(0., 0., 0., 0.);
+        gl.clear(GL::COLOR_BUFFER_BIT);
+
+        // field
+        gl.active_texture(GL::TEXTURE0);
+        gl.bind_texture(GL::TEXTURE_2D, Some(&self.field_texture));
+        {
+            // SAFETY: the view is consumed before anything can allocate
+            let view = unsafe { js_sys::Float32Array::view(&self.field_data) };
+            gl.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_array_buffer_view(
+                GL::TEXTURE_2D,
+                0,
+                GL::RG32F as i32,
+                nx as i32,
+                ny as i32,
+                0,
+                GL::RG,
+                GL::FLOAT,
+                Some(&view),
+            )?;
+        }
+
+        gl.active_texture(GL::TEXTURE1);
+        gl.bind_texture(GL::TEXTURE_2D, Some(&self.colormap_texture));
+
+        if options.lic {
+            let lic_options = LicOptions {
+                length: options.lic_length,
+                resolution: (gl.drawing_buffer_width() as usize / nx).clamp(1, 4),
+                phase: options.lic_phase,
+            };
+            let (texture, computed) = self.lic.get(fluid, &lic_options);
+
+            gl.active_texture(GL::TEXTURE2);
+            gl.bind_texture(GL::TEXTURE_2D, Some(&self.lic_texture));
+            if computed {
+                self.lic_data.clear();
+                self.lic_data
+                    .extend(texture.data.iter().map(|g| (g * 255.).round() as u8));
+
+                // rows of one byte per texel are not 4-byte aligned
+                gl.pixel_storei(GL::UNPACK_ALIGNMENT, 1);
+                gl.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+                    GL::TEXTURE_2D,
+                    0,
+                    GL::R8 as i32,
+                    texture.width as i32,
+                    texture.height as i32,
+                    0,
+                    GL::RED,
+                    GL::UNSIGNED_BYTE,
+                    Some(&self.lic_data),
+                )?;
+                gl.pixel_storei(GL::UNPACK_ALIGNMENT, 4);
+            }
+        }
+
+        gl.use_program(Some(&self.field_program));
+        gl.uniform1i(Some(&self.u_field), 0);
+        gl.uniform1i(Some(&self.u_colormap), 1);
+        gl.uniform1f(Some(&self.u_min), min);
+        gl.uniform1f(Some(&self.u_max), max);
+        gl.uniform1i(Some(&self.u_show_field), options.pressure as i32);
+        gl.uniform1i(Some(&self.u_show_obstacle), options.obstacle as i32);
+        let interpolation = match options.interpolation {
+            Interpolation::Nearest => 0,
+            Interpolation::Bilinear => 1,
+            Interpolation::Bicubic => 2,
+        };
+        gl.uniform1i(Some(&self.u_interpolation), interpolation);
+        gl.uniform1i(Some(&self.u_lic), 2);
+        gl.uniform1i(Some(&self.u_show_lic), options.lic as i32);
+
+        gl.bind_vertex_array(Some(&self.quad_vao));
+        gl.draw_arrays(GL::TRIANGLE_STRIP, 0, 4);
+
+        gl.use_program(Some(&self.line_program));
+        // the viewport covers the interior cells
+        gl.uniform2f(Some(&self.u_origin), h, h);
+        gl.uniform2f(Some(&self.u_size), nx as f32 * h, ny as f32 * h);
+        gl.bind_vertex_array(Some(&self.line_vao));
+        gl.bind_buffer(GL::ARRAY_BUFFER, Some(&self.line_buffer));
+
+        // iso-lines
+        if options.contours > 0 {
+            self.line_data.clear();
+            for [(x0, y0), (x1, y1)] in fluid.contours(field, (min, max), options.contours) {
+                self.line_data.extend_from_slice(&[x0, y0, x1, y1]);
+            }
+
+            gl.enable(GL::BLEND);
+            gl.blend_func(GL::SRC_ALPHA, GL::ONE_MINUS_SRC_ALPHA);
+            gl.uniform4f(Some(&self.u_color), 0., 0., 0., 0.7);
+            upload_buffer(gl, &self.line_data);
+            gl.draw_arrays(GL::LINES, 0, (self.line_data.len() / 2) as i32);
+            gl.disable(GL::BLEND);
+        }
+
+        // streamlines
+        if options.streamlines {
+            self.line_data.clear();
+            for line in fluid.streamlines(options) {
+                for segment in line.windows(2) {
+                    let [(x0, y0), (x1, y1)] = [segment[0], segment[1]];
+                    self.line_data.extend_from_slice(&[x0, y0, x1, y1]);
+                }
+            }
+
+            gl.uniform4f(Some(&self.u_color), 1., 0., 0., 1.);
+            upload_buffer(gl, &self.line_data);
+            gl.draw_arrays(GL::LINES, 0, (self.line_data.len() / 2) as i32);
+        }
+
+        // about as many pixels as the canvas
+        let ratio = (gl.drawing_buffer_width() as usize / nx).max(1);
+
+        // arrows, particles and flow lines
+        if options.quiver || !particles.is_empty() || !flowlines.is_empty() {
+            let mut image = Image::new(nx, ny, ratio);
+            if options.quiver {
+                fluid.draw_quiver(&mut image, options, &*colormap);
+            }
+            flowlines.draw(fluid, &mut image);
+            particles.draw(fluid, &mut image, options.particle_style);
+
+            let (width, height) = image.size();
+            gl.active_texture(GL::TEXTURE0);
+            gl.bind_texture(GL::TEXTURE_2D, Some(&self.layers_texture));
+            gl.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+                GL::TEXTURE_2D,
+                0,
+                GL::RGBA as i32,
+                width as i32,
+                height as i32,
+                0,
+                GL::RGBA,
+                GL::UNSIGNED_BYTE,
+                Some(image.data()),
+            )?;
+            self.draw_overlay(&self.layers_texture);
+        }
+
+        // colorbar, axes and HUD
+        if options.colorbar || options.axes || options.hud {
+            self.update_overlays(fluid, options, &*colormap, (min, max), ratio)?;
+            self.update_hud(fluid, options, Some(1. / dt))?;
+            self.draw_overlay(&self.overlay_texture);
+        }
+
+        self.gl.bind_vertex_array(None);
+
+        Ok(())
+    }
+}
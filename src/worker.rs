@@ -0,0 +1,282 @@
+//! Simulation steps in a Web Worker
+//!
+//! On large grids a step takes longer than an animation frame, and the page
+//! freezes while it runs in the `requestAnimationFrame` callback. With a
+//! worker, the page only renders: the steps are posted to the worker, which has
+//! its own copy of the [Fluid] (in its own wasm instance), and the fields come
+//! back as a checkpoint (see [crate::checkpoint]) whose buffer is transferred
+//! rather than copied.
+//!
+//! The worker script only has to load the module and call [run_solver]:
+//!
+//! ```js
+//! import("./pkg").then(wasm => wasm.run_solver());
+//! ```
+//!
+//! Messages:
+//! - worker → page, once: `{ ready: true }`
+//! - page → worker: `{ generation, dt, num_iters, over_relaxation, checkpoint? }`;
+//!   the checkpoint is sent when the fluid of the page changed (new scenario,
+//!   reset...) and replaces the one of the worker
+//! - worker → page: `{ generation, dt, residual, checkpoint }` after the step
+//!
+//! One step is in flight at a time. The replies to the steps requested before
+//! the fluid of the page last changed are dropped.
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+
+use crate::simu::Fluid;
+
+fn get(message: &JsValue, key: &str) -> JsValue {
+    js_sys::Reflect::get(message, &key.into()).unwrap_or(JsValue::UNDEFINED)
+}
+
+fn get_f64(message: &JsValue, key: &str) -> Result<f64, JsValue> {
+    get(message, key)
+        .as_f64()
+        .ok_or_else(|| JsValue::from_str(&format!("missing `{}` in the message", key)))
+}
+
+fn set(message: &js_sys::Object, key: &str, value: &JsValue) {
+    let _ = js_sys::Reflect::set(message, &key.into(), value);
+}
+
+/// A checkpoint as a `Uint8Array`, with the buffer to transfer.
+fn checkpoint_array(fluid: &Fluid) -> (js_sys::Uint8Array, js_sys::Array) {
+    let checkpoint = js_sys::Uint8Array::from(&fluid.to_checkpoint()[..]);
+    let transfer = js_sys::Array::of1(&checkpoint.buffer());
+    (checkpoint, transfer)
+}
+
+/// The worker side: steps its copy of the fluid as requested.
+struct Solver {
+    fluid: Option<Fluid>,
+}
+
+impl Solver {
+    fn handle(&mut self, request: &JsValue) -> Result<(js_sys::Object, js_sys::Array), JsValue> {
+        let checkpoint = get(request, "checkpoint");
+        if !checkpoint.is_undefined() {
+            let data = checkpoint.dyn_into::<js_sys::Uint8Array>()?.to_vec();
+            let fluid =
+                Fluid::from_checkpoint(&data).map_err(|e| JsValue::from_str(&e.to_string()))?;
+            self.fluid = Some(fluid);
+        }
+        let fluid = self
+            .fluid
+            .as_mut()
+            .ok_or_else(|| JsValue::from_str("no fluid to step"))?;
+
+        let dt = get_f64(request, "dt")? as f32;
+        let num_iters = get_f64(request, "num_iters")? as u32;
+        let over_relaxation = get_f64(request, "over_relaxation")? as f32;
+        fluid.simulate(dt, num_iters, over_relaxation);
+
+        let (checkpoint, transfer) = checkpoint_array(fluid);
+        let reply = js_sys::Object::new();
+        set(&reply, "generation", &get(request, "generation"));
+        set(&reply, "dt", &dt.into());
+        // not part of the checkpoint, but shown in the HUD
+        set(&reply, "residual", &fluid.residual.into());
+        set(&reply, "checkpoint", &checkpoint);
+
+        Ok((reply, transfer))
+    }
+}
+
+/// Serve the step requests of a page, see [crate::simulation::Simulation::set_worker].
+///
+/// To be called once from a dedicated worker.
+#[wasm_bindgen]
+pub fn run_solver() -> Result<(), JsValue> {
+    let scope: web_sys::DedicatedWorkerGlobalScope = js_sys::global().dyn_into()?;
+
+    let mut solver = Solver { fluid: None };
+    let reply_scope = scope.clone();
+    let onmessage =
+        Closure::<dyn FnMut(web_sys::MessageEvent)>::new(move |event: web_sys::MessageEvent| {
+            // an error is reported to the page as an `error` event
+            let (reply, transfer) = solver.handle(&event.data()).unwrap_throw();
+            reply_scope
+                .post_message_with_transfer(&reply, &transfer)
+                .unwrap_throw();
+        });
+    scope.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
+    // the handler lives as long as the worker
+    onmessage.forget();
+
+    let ready = js_sys::Object::new();
+    set(&ready, "ready", &true.into());
+    scope.post_message(&ready)
+}
+
+/// The page side: a worker running [run_solver] and the step in flight
+pub(crate) struct SolverWorker {
+    worker: web_sys::Worker,
+    /// incremented whenever the fluid of the page is replaced
+    generation: u64,
+    /// true if the fluid of the worker must be replaced with the next request
+    stale: bool,
+    /// true once the worker is listening
+    ready: bool,
+    in_flight: bool,
+    /// time to simulate with the next request, accumulated while a step is in flight
+    backlog: f32,
+    /// time step of the fields received since the last frame
+    received: Option<f32>,
+    /// set when the worker failed, to go back to the main thread
+    failed: bool,
+
+    _onmessage: Closure<dyn FnMut(web_sys::MessageEvent)>,
+    _onerror: Closure<dyn FnMut(web_sys::ErrorEvent)>,
+}
+
+impl SolverWorker {
+    /// Take over `worker`; its messages are passed to `onmessage` and its
+    /// errors to `onerror`, which should call [SolverWorker::receive] and
+    /// [SolverWorker::fail].
+    pub(crate) fn new(
+        worker: web_sys::Worker,
+        mut onmessage: impl FnMut(JsValue) + 'static,
+        mut onerror: impl FnMut(String) + 'static,
+    ) -> SolverWorker {
+        let onmessage = Closure::<dyn FnMut(web_sys::MessageEvent)>::new(
+            move |event: web_sys::MessageEvent| onmessage(event.data()),
+        );
+        let onerror =
+            Closure::<dyn FnMut(web_sys::ErrorEvent)>::new(move |event: web_sys::ErrorEvent| {
+                onerror(event.message())
+            });
+        worker.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
+        worker.set_onerror(Some(onerror.as_ref().unchecked_ref()));
+
+        SolverWorker {
+            worker,
+            generation: 0,
+            stale: true,
+            ready: false,
+            in_flight: false,
+            backlog: 0.,
+            received: None,
+            failed: false,
+            _onmessage: onmessage,
+            _onerror: onerror,
+        }
+    }
+
+    /// True if the worker failed and the steps must go back to the main thread
+    pub(crate) fn failed(&self) -> bool {
+        self.failed
+    }
+
+    /// True if a step can be requested
+    pub(crate) fn idle(&self) -> bool {
+        self.ready && !self.in_flight
+    }
+
+    /// Time step of the fields received since the last call, if any.
+    pub(crate) fn take_received(&mut self) -> Option<f32> {
+        self.received.take()
+    }
+
+    /// The fluid of the page was replaced: the step in flight is outdated, and
+    /// the worker gets the new fluid with the next request.
+    pub(crate) fn reload(&mut self) {
+        self.generation += 1;
+        self.stale = true;
+        self.in_flight = false;
+        self.backlog = 0.;
+        self.received = None;
+    }
+
+    /// Simulate `dt` more seconds of `fluid`.
+    ///
+    /// The step is requested at once if the worker is idle, otherwise it is
+    /// added to the next one.
+    pub(crate) fn step(&mut self, fluid: &Fluid, dt: f32, num_iters: u32, over_relaxation: f32) {
+        self.backlog += dt;
+        if !self.idle() {
+            return;
+        }
+
+        let dt = std::mem::take(&mut self.backlog);
+        if let Err(e) = self.request(fluid, dt, num_iters, over_relaxation) {
+            self.fail(&format!("{:?}", e));
+        }
+    }
+
+    fn request(
+        &mut self,
+        fluid: &Fluid,
+        dt: f32,
+        num_iters: u32,
+        over_relaxation: f32,
+    ) -> Result<(), JsValue> {
+        let request = js_sys::Object::new();
+        set(&request, "generation", &(self.generation as f64).into());
+        set(&request, "dt", &dt.into());
+        set(&request, "num_iters", &num_iters.into());
+        set(&request, "over_relaxation", &over_relaxation.into());
+
+        let transfer = if self.stale {
+            let (checkpoint, transfer) = checkpoint_array(fluid);
+            set(&request, "checkpoint", &checkpoint);
+            transfer
+        } else {
+            js_sys::Array::new()
+        };
+
+        self.worker
+            .post_message_with_transfer(&request, &transfer)?;
+        self.stale = false;
+        self.in_flight = true;
+        Ok(())
+    }
+
+    /// Handle a message of the worker; the fields of a step replace `fluid`.
+    pub(crate) fn receive(&mut self, message: &JsValue, fluid: &mut Fluid) {
+        if let Err(e) = self.try_receive(message, fluid) {
+            self.fail(&format!("{:?}", e));
+        }
+    }
+
+    fn try_receive(&mut self, message: &JsValue, fluid: &mut Fluid) -> Result<(), JsValue> {
+        if get(message, "ready").is_truthy() {
+            // the time spent loading the worker is not simulated
+            self.ready = true;
+            self.backlog = 0.;
+            return Ok(());
+        }
+
+        if get_f64(message, "generation")? as u64 != self.generation {
+            // requested before the fluid was replaced
+            return Ok(());
+        }
+        self.in_flight = false;
+
+        let data = get(message, "checkpoint")
+            .dyn_into::<js_sys::Uint8Array>()?
+            .to_vec();
+        let mut stepped =
+            Fluid::from_checkpoint(&data).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        stepped.residual = get_f64(message, "residual")? as f32;
+        *fluid = stepped;
+
+        self.received = Some(get_f64(message, "dt")? as f32);
+        Ok(())
+    }
+
+    /// Report an error of the worker; the steps go back to the main thread.
+    pub(crate) fn fail(&mut self, error: &str) {
+        web_sys::console::error_1(&format!("solver worker failed: {}", error).into());
+        self.failed = true;
+    }
+}
+
+impl Drop for SolverWorker {
+    fn drop(&mut self) {
+        self.worker.set_onmessage(None);
+        self.worker.set_onerror(None);
+        self.worker.terminate();
+    }
+}
@@ -0,0 +1,4 @@
+// Runs the simulation steps posted by the page, see `Simulation.set_worker`.
+import('./pkg')
+    .then(wasm => wasm.run_solver())
+    .catch(console.error);